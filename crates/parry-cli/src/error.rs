@@ -0,0 +1,83 @@
+use std::fmt;
+use std::io;
+use std::process::ExitCode;
+
+/// Exit code of commands that completed, but found shards missing or damaged.
+pub const EXIT_PARTIAL: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_IO: u8 = 3;
+const EXIT_UNRECOVERABLE: u8 = 4;
+
+/// Why a command failed, which determines its exit code.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// The arguments are invalid or ask for something that is not supported.
+    Usage,
+    /// Reading or writing a file failed.
+    Io,
+    /// The shards do not hold enough intact data to decode.
+    Unrecoverable,
+}
+
+impl Failure {
+    /// Tells failures apart by the kind of error the library reported.
+    fn of(error: &io::Error) -> Failure {
+        match error.kind() {
+            io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported => Failure::Usage,
+            io::ErrorKind::InvalidData => Failure::Unrecoverable,
+            _ => Failure::Io,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Failure::Usage => "usage",
+            Failure::Io => "io",
+            Failure::Unrecoverable => "unrecoverable",
+        }
+    }
+
+    pub fn exit_code(self) -> ExitCode {
+        ExitCode::from(match self {
+            Failure::Usage => EXIT_USAGE,
+            Failure::Io => EXIT_IO,
+            Failure::Unrecoverable => EXIT_UNRECOVERABLE,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct CliError {
+    pub failure: Failure,
+    pub message: String,
+}
+
+impl CliError {
+    pub fn usage(message: impl Into<String>) -> CliError {
+        CliError {
+            failure: Failure::Usage,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.message)
+    }
+}
+
+/// Adds a description of what was being done, such as which shard or path was involved, to
+/// errors from the library.
+pub trait Context<T> {
+    fn context<F: FnOnce() -> String>(self, describe: F) -> Result<T, CliError>;
+}
+
+impl<T> Context<T> for io::Result<T> {
+    fn context<F: FnOnce() -> String>(self, describe: F) -> Result<T, CliError> {
+        self.map_err(|error| CliError {
+            failure: Failure::of(&error),
+            message: format!("{}: {}", describe(), error),
+        })
+    }
+}
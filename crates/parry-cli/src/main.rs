@@ -1,10 +1,23 @@
+mod error;
+mod progress;
+
 use clap::{Args, Parser, Subcommand};
-use std::fs::File;
+use serde_json::{Value, json};
+use std::fs::{File, OpenOptions};
 use std::io::BufReader;
 use std::io::BufWriter;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use parry::{
+    ChecksumKind, Compression, CompressionKind, KEY_SIZE, NameContext, NameTemplate, Placement,
+    ReedSolomonEncoder, SetId, ShardLatencies, ShardSink, SparseReader, WritePolicy,
+};
 
-use parry::ReedSolomonEncoder;
+use crate::error::{CliError, Context, EXIT_PARTIAL, Failure};
+use crate::progress::{ProgressBar, Tracker};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -13,6 +26,14 @@ use parry::ReedSolomonEncoder;
     about = "Tool to encode and decode files using Reed-Solomon encoding"
 )]
 struct Cli {
+    /// Print the result as a JSON object, on stderr when decoding to stdout
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Do not draw a progress bar on stderr
+    #[arg(long, global = true)]
+    no_progress: bool,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -21,6 +42,29 @@ struct Cli {
 enum Command {
     Encode(EncodeArgs),
     Decode(DecodeArgs),
+    Append(AppendArgs),
+    Transcode(TranscodeArgs),
+    Verify(VerifyArgs),
+}
+
+impl Command {
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Encode(_) => "encode",
+            Command::Decode(_) => "decode",
+            Command::Append(_) => "append",
+            Command::Transcode(_) => "transcode",
+            Command::Verify(_) => "verify",
+        }
+    }
+
+    /// Returns whether decoded data is written to stdout, which then cannot carry reports.
+    fn writes_stdout(&self) -> bool {
+        match self {
+            Command::Decode(args) => args.output_file.as_deref().is_some_and(is_stdio),
+            _ => false,
+        }
+    }
 }
 
 #[derive(Args, Debug, Clone)]
@@ -33,6 +77,243 @@ struct CommonArgs {
 
     #[arg(long, value_name = "BYTES")]
     chunk_size: usize,
+
+    /// Per-chunk checksum: none, crc32c, xxh3-64, xxh3-128, blake3, blake3-keyed, or
+    /// xchacha20-poly1305 to encrypt chunks
+    #[arg(long, value_name = "ALGORITHM", default_value_t = ChecksumKind::default())]
+    checksum: ChecksumKind,
+
+    /// File holding the 32-byte key for blake3-keyed checksums or encryption
+    #[arg(
+        long,
+        value_name = "FILE",
+        required_if_eq_any([("checksum", "blake3-keyed"), ("checksum", "xchacha20-poly1305")])
+    )]
+    key_file: Option<PathBuf>,
+
+    /// Directory to place shards in, typically one per disk; may be repeated. File patterns then
+    /// name the shard files within these directories
+    #[arg(long = "shard-directory", value_name = "DIR")]
+    shard_directories: Vec<PathBuf>,
+
+    /// Comma-separated index into the shard directories for every shard, in shard order.
+    /// Shards are spread over the directories round-robin otherwise
+    #[arg(
+        long,
+        value_name = "INDICES",
+        value_delimiter = ',',
+        requires = "shard_directories"
+    )]
+    placement_map: Option<Vec<usize>>,
+
+    /// Allow placing several shards on one file system
+    #[arg(long, requires = "shard_directories")]
+    allow_shared_devices: bool,
+}
+
+impl CommonArgs {
+    fn shards(&self) -> usize {
+        self.data_shards + self.parity_shards
+    }
+
+    fn encoder(&self, tracker: &Arc<Tracker>) -> Result<ReedSolomonEncoder, CliError> {
+        let encoder = new_encoder(self.data_shards, self.parity_shards, self.chunk_size)?;
+        let encoder = with_checksum(encoder, self.checksum, self.key_file.as_ref())?;
+        Result::Ok(encoder.with_progress(tracker.clone()))
+    }
+
+    /// Returns where the first `shards` shards are placed, if shard directories are given.
+    fn placement(&self, shards: usize) -> Result<Option<Placement>, CliError> {
+        if self.shard_directories.is_empty() {
+            return Result::Ok(None);
+        }
+
+        let directories = self.shard_directories.clone();
+        let Some(mapping) = &self.placement_map else {
+            return Result::Ok(Some(Placement::round_robin(directories)));
+        };
+
+        if mapping.len() < shards {
+            return Result::Err(CliError::usage(format!(
+                "--placement-map maps {} shards, but there are {}",
+                mapping.len(),
+                shards
+            )));
+        }
+
+        if let Some(&index) = mapping.iter().find(|&&index| index >= directories.len()) {
+            return Result::Err(CliError::usage(format!(
+                "--placement-map refers to directory {}, but only {} are given",
+                index,
+                directories.len()
+            )));
+        }
+
+        Result::Ok(Some(Placement::mapped(directories, mapping.clone())))
+    }
+
+    fn name_context(&self) -> NameContext {
+        NameContext::new(self.data_shards, self.shards())
+    }
+
+    /// Refuses to write the shards placed by `placement` if any two of them would share a file
+    /// system.
+    fn check_devices(&self, placement: Option<&Placement>, shards: usize) -> Result<(), CliError> {
+        match placement {
+            Some(placement) if !self.allow_shared_devices => placement
+                .check_devices(shards)
+                .context(|| "Cannot place shards".to_string()),
+            _ => Result::Ok(()),
+        }
+    }
+}
+
+fn new_encoder(
+    data_shards: usize,
+    parity_shards: usize,
+    chunk_size: usize,
+) -> Result<ReedSolomonEncoder, CliError> {
+    if data_shards == 0 {
+        return Result::Err(CliError::usage("At least one data shard is required"));
+    }
+
+    if data_shards + parity_shards > 256 {
+        return Result::Err(CliError::usage("Total number of shards cannot exceed 256"));
+    }
+
+    if chunk_size == 0 || chunk_size > u32::MAX as usize {
+        return Result::Err(CliError::usage(format!(
+            "Chunk size must be between 1 and {} bytes",
+            u32::MAX
+        )));
+    }
+
+    Result::Ok(ReedSolomonEncoder::new(
+        data_shards,
+        parity_shards,
+        chunk_size,
+    ))
+}
+
+fn with_checksum(
+    encoder: ReedSolomonEncoder,
+    checksum: ChecksumKind,
+    key_file: Option<&PathBuf>,
+) -> Result<ReedSolomonEncoder, CliError> {
+    if !checksum.is_keyed() {
+        return Result::Ok(encoder.with_checksum(checksum));
+    }
+
+    let key_file =
+        key_file.ok_or_else(|| CliError::usage("A key file is required for keyed checksums"))?;
+    let key = std::fs::read(key_file)
+        .context(|| format!("Cannot read key file {}", key_file.display()))?;
+    let key: [u8; KEY_SIZE] = key.try_into().map_err(|_| {
+        CliError::usage(format!(
+            "Key file {} must hold exactly {} bytes",
+            key_file.display(),
+            KEY_SIZE
+        ))
+    })?;
+
+    Result::Ok(if checksum.is_encrypted() {
+        encoder.with_encryption(Arc::new(key))
+    } else {
+        encoder.with_key(key)
+    })
+}
+
+/// Returns the path shard `shard` is written to.
+fn shard_path(
+    placement: Option<&Placement>,
+    template: &NameTemplate,
+    context: &NameContext,
+    shard: usize,
+) -> Result<PathBuf, CliError> {
+    let file_name = template
+        .render(shard, context)
+        .context(|| format!("Cannot name shard {} with {}", shard, template))?;
+
+    Result::Ok(match placement {
+        Some(placement) => placement.shard_path(shard, &file_name),
+        None => PathBuf::from(file_name),
+    })
+}
+
+/// Returns the paths the first `shards` shards are read from, looking for them in every shard
+/// directory. When the template uses values that are not known, such as the set ID, the
+/// directories are searched for files the template could have produced instead.
+fn find_shards(
+    placement: Option<&Placement>,
+    template: &NameTemplate,
+    context: &NameContext,
+    shards: usize,
+) -> Result<Vec<Option<PathBuf>>, CliError> {
+    if template.render(0, context).is_ok() {
+        return (0..shards)
+            .map(|shard| {
+                let file_name = template
+                    .render(shard, context)
+                    .context(|| format!("Cannot name shard {} with {}", shard, template))?;
+
+                Result::Ok(Some(match placement {
+                    Some(placement) => placement
+                        .find_shard(shard, &file_name)
+                        .unwrap_or_else(|| placement.shard_path(shard, &file_name)),
+                    None => PathBuf::from(file_name),
+                }))
+            })
+            .collect();
+    }
+
+    let template_text = template.to_string();
+    let parent = Path::new(&template_text).parent().unwrap_or(Path::new(""));
+    if parent.to_string_lossy().contains('{') {
+        return Result::Err(CliError::usage(
+            "Placeholders in directory names must be known to find shards",
+        ));
+    }
+
+    let roots = match placement {
+        Some(placement) => placement.directories().to_vec(),
+        None => vec![PathBuf::new()],
+    };
+
+    let mut paths: Vec<Option<PathBuf>> = vec![None; shards];
+
+    for root in roots {
+        let directory = root.join(parent);
+        let listed = if directory.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            &directory
+        };
+
+        let Result::Ok(entries) = std::fs::read_dir(listed) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let name = parent.join(entry.file_name());
+            let Some(shard) = template.matches(&name.to_string_lossy(), context) else {
+                continue;
+            };
+
+            let path = root.join(&name);
+            if let Some(other) = &paths[shard] {
+                return Result::Err(CliError::usage(format!(
+                    "Both {} and {} match shard {}",
+                    other.display(),
+                    path.display(),
+                    shard
+                )));
+            }
+
+            paths[shard] = Some(path);
+        }
+    }
+
+    Result::Ok(paths)
 }
 
 #[derive(Args, Debug)]
@@ -40,11 +321,79 @@ struct EncodeArgs {
     #[command(flatten)]
     common: CommonArgs,
 
-    #[arg(long, value_name = "FILE")]
-    input_file: PathBuf,
+    /// File to encode, or - to encode standard input until it ends
+    #[arg(long, value_name = "FILE", required_unless_present = "input_directory")]
+    input_file: Option<PathBuf>,
+
+    /// Encode every file, directory and symbolic link under DIR, along with a manifest describing
+    /// them. Other entries, such as sockets and FIFOs, are skipped and reported
+    #[arg(long, value_name = "DIR", conflicts_with = "input_file")]
+    input_directory: Option<PathBuf>,
 
+    /// Encode through memory maps of the input and shard files
+    #[cfg(feature = "mmap")]
+    #[arg(
+        long,
+        conflicts_with_all = ["input_directory", "compression", "sparse", "min_shards", "resume"]
+    )]
+    mmap: bool,
+
+    /// Compress the input with zstd or lz4 before encoding it
+    #[arg(long, value_name = "ALGORITHM", conflicts_with = "input_directory")]
+    compression: Option<CompressionKind>,
+
+    /// zstd compression level
+    #[arg(long, value_name = "LEVEL", requires = "compression")]
+    compression_level: Option<i32>,
+
+    /// Amount of data compressed into each independently decompressible frame
+    #[arg(long, value_name = "BYTES", requires = "compression")]
+    frame_size: Option<usize>,
+
+    /// Leave holes in the shard files in place of all-zero chunks, and skip reading holes in the
+    /// input file
+    #[arg(long, conflicts_with_all = ["input_directory", "compression"])]
+    sparse: bool,
+
+    /// Shard file name template with placeholders {index}, {index:03}, {total}, {kind}, {name}
+    /// and {set}; {} is short for {index}
     #[arg(long, value_name = "PATTERN")]
-    output_file_pattern: String,
+    output_file_pattern: NameTemplate,
+
+    /// Keep encoding when shard files fail to be written, as long as N of them still are. The
+    /// failed shards are reported for repair. Defaults to all shards
+    #[arg(long, value_name = "N")]
+    min_shards: Option<usize>,
+
+    /// Continue an interrupted encode of the same input file from its last checkpoint, keeping
+    /// the stripes the shard files already hold in full. The input is read up to the checkpoint
+    /// again and refused if it changed since
+    #[arg(
+        long,
+        conflicts_with_all = ["input_directory", "compression", "sparse", "min_shards"]
+    )]
+    resume: bool,
+
+    /// Amount of input encoded between checkpoints, which an interrupted encode can be resumed
+    /// from with --resume. Only plain encodes of an input file without --min-shards record them
+    #[arg(long, value_name = "BYTES", default_value_t = 1 << 30)]
+    checkpoint_interval: usize,
+}
+
+impl EncodeArgs {
+    fn compression(&self) -> Option<Compression> {
+        let mut compression = Compression::new(self.compression?);
+
+        if let Some(level) = self.compression_level {
+            compression = compression.with_level(level);
+        }
+
+        if let Some(frame_size) = self.frame_size {
+            compression = compression.with_frame_size(frame_size);
+        }
+
+        Some(compression)
+    }
 }
 
 #[derive(Args, Debug)]
@@ -52,50 +401,859 @@ struct DecodeArgs {
     #[command(flatten)]
     common: CommonArgs,
 
+    /// Shard file name template as given when encoding. Values that are not known when decoding,
+    /// such as {set}, match any file name that could stand in their place
+    #[arg(long, value_name = "PATTERN", required_unless_present = "input_glob")]
+    input_file_pattern: Option<NameTemplate>,
+
+    /// Glob matching the shard files, which are told apart by their headers
+    #[arg(long, value_name = "GLOB", conflicts_with = "input_file_pattern")]
+    input_glob: Option<String>,
+
+    /// File to decode into, or - for standard output
+    #[arg(
+        long,
+        value_name = "FILE",
+        required_unless_present = "output_directory"
+    )]
+    output_file: Option<PathBuf>,
+
+    /// Restore a directory tree encoded with --input-directory under DIR
+    #[arg(long, value_name = "DIR", conflicts_with = "output_file")]
+    output_directory: Option<PathBuf>,
+
+    /// Extract the single file at PATH from an encoded directory tree into --output-file
+    #[arg(long, value_name = "PATH", requires = "output_file")]
+    extract: Option<String>,
+
+    /// Request every stripe from N more shards than needed at once and decode it from the first
+    /// chunks to arrive, so that slow disks do not hold up the decode
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["output_directory", "extract"]
+    )]
+    hedge: Option<usize>,
+
+    /// Decode through memory maps of the shard and output files
+    #[cfg(feature = "mmap")]
+    #[arg(long, conflicts_with_all = ["output_directory", "extract", "hedge"])]
+    mmap: bool,
+}
+
+#[derive(Args, Debug)]
+struct AppendArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, value_name = "FILE")]
+    input_file: PathBuf,
+
     #[arg(long, value_name = "PATTERN")]
-    input_file_pattern: String,
+    shard_file_pattern: NameTemplate,
+}
 
+#[derive(Args, Debug, Clone)]
+struct TargetArgs {
+    #[arg(long, value_name = "N")]
+    target_data_shards: usize,
+
+    #[arg(long, value_name = "N")]
+    target_parity_shards: usize,
+
+    #[arg(long, value_name = "BYTES")]
+    target_chunk_size: usize,
+
+    /// Defaults to the checksum of the source shards
+    #[arg(long, value_name = "ALGORITHM")]
+    target_checksum: Option<ChecksumKind>,
+
+    /// Defaults to the key file of the source shards
     #[arg(long, value_name = "FILE")]
-    output_file: PathBuf,
+    target_key_file: Option<PathBuf>,
+}
+
+impl TargetArgs {
+    fn shards(&self) -> usize {
+        self.target_data_shards + self.target_parity_shards
+    }
+
+    fn encoder(&self, source: &CommonArgs) -> Result<ReedSolomonEncoder, CliError> {
+        let encoder = new_encoder(
+            self.target_data_shards,
+            self.target_parity_shards,
+            self.target_chunk_size,
+        )?;
+
+        with_checksum(
+            encoder,
+            self.target_checksum.unwrap_or(source.checksum),
+            self.target_key_file.as_ref().or(source.key_file.as_ref()),
+        )
+    }
+}
+
+#[derive(Args, Debug)]
+struct TranscodeArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+
+    #[command(flatten)]
+    target: TargetArgs,
+
+    #[arg(long, value_name = "PATTERN")]
+    input_file_pattern: NameTemplate,
+
+    /// May equal the input pattern when only parity shards are added, in which case the existing
+    /// shards are kept and only the new parity shards are written
+    #[arg(long, value_name = "PATTERN")]
+    output_file_pattern: NameTemplate,
+}
+
+#[derive(Args, Debug)]
+struct VerifyArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, value_name = "PATTERN")]
+    input_file_pattern: NameTemplate,
+}
+
+/// Returns whether `path` stands for standard input or output.
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
 }
 
-fn main() {
+/// Returns the name of `path` for the {name} placeholder.
+fn base_name(path: &Path) -> Result<String, CliError> {
+    if is_stdio(path) {
+        return Result::Ok("stdin".to_string());
+    }
+
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| CliError::usage(format!("{} has no file name", path.display())))
+}
+
+/// Creates the shard files of `shards`, which only appear under their names once committed by
+/// `commit_shards`.
+fn create_shards(
+    placement: Option<&Placement>,
+    template: &NameTemplate,
+    context: &NameContext,
+    shards: std::ops::Range<usize>,
+) -> Result<ShardSink, CliError> {
+    let paths = shards
+        .map(|shard| shard_path(placement, template, context, shard))
+        .collect::<Result<Vec<PathBuf>, CliError>>()?;
+
+    ShardSink::create(paths).context(|| "Cannot create the shard files".to_string())
+}
+
+fn commit_shards(sink: ShardSink) -> Result<(), CliError> {
+    sink.commit()
+        .context(|| "Cannot write the shard files".to_string())
+}
+
+/// Opens the shard files found at `paths`, returning the shards that could not be opened
+/// alongside them. Those are treated as erasures.
+fn open_shards(paths: Vec<Option<PathBuf>>) -> (Vec<Option<BufReader<File>>>, Vec<usize>) {
+    let files: Vec<Option<BufReader<File>>> = paths
+        .into_iter()
+        .map(|path| File::open(path?).ok().map(BufReader::new))
+        .collect();
+
+    let missing = missing_shards(&files);
+    (files, missing)
+}
+
+fn missing_shards<T>(files: &[Option<T>]) -> Vec<usize> {
+    (0..files.len())
+        .filter(|&shard| files[shard].is_none())
+        .collect()
+}
+
+fn path_values(paths: &[PathBuf]) -> Vec<Value> {
+    paths
+        .iter()
+        .map(|path| Value::from(path.display().to_string()))
+        .collect()
+}
+
+/// What a command that completed reports.
+struct Outcome {
+    /// Whether shards were found to be missing or damaged along the way.
+    partial: bool,
+    /// Lines printed in text mode.
+    messages: Vec<String>,
+    /// Fields of the JSON report.
+    details: Value,
+}
+
+impl Outcome {
+    fn complete(details: Value) -> Outcome {
+        Outcome {
+            partial: false,
+            messages: vec![],
+            details,
+        }
+    }
+
+    /// Reports the shards that failed to be written while creating a shard set, which then needs
+    /// repair.
+    fn written(failed: Vec<usize>, details: Value) -> Outcome {
+        let mut messages = vec![];
+
+        if !failed.is_empty() {
+            let shards: Vec<String> = failed.iter().map(usize::to_string).collect();
+            messages.push(format!(
+                "Shards that failed to be written and need repair: {}",
+                shards.join(", ")
+            ));
+        }
+
+        let mut details = details;
+        details["failed_shards"] = json!(failed);
+
+        Outcome {
+            partial: !messages.is_empty(),
+            messages,
+            details,
+        }
+    }
+
+    /// Reports shards that were missing or chunks that had to be reconstructed while reading a
+    /// shard set, which then needs repair.
+    fn degraded(missing: Vec<usize>, tracker: &Tracker, details: Value) -> Outcome {
+        let repaired_chunks = tracker.progress().repaired_chunks;
+        let mut messages = vec![];
+
+        if !missing.is_empty() {
+            let shards: Vec<String> = missing.iter().map(usize::to_string).collect();
+            messages.push(format!(
+                "Shards missing or unreadable: {}",
+                shards.join(", ")
+            ));
+        }
+
+        if repaired_chunks > 0 {
+            messages.push(format!(
+                "{} data chunks were reconstructed",
+                repaired_chunks
+            ));
+        }
+
+        let mut details = details;
+        details["missing_shards"] = json!(missing);
+        details["repaired_chunks"] = json!(repaired_chunks);
+
+        Outcome {
+            partial: !messages.is_empty(),
+            messages,
+            details,
+        }
+    }
+}
+
+fn main() -> ExitCode {
     let cli = Cli::parse();
 
-    match cli.command {
-        Command::Encode(args) => {
-            let encoder = ReedSolomonEncoder::new(
-                args.common.data_shards,
-                args.common.parity_shards,
-                args.common.chunk_size,
-            );
+    let name = cli.command.name();
+    let json = cli.json;
+    let to_stderr = cli.command.writes_stdout();
+
+    let progress_bar = ProgressBar::for_terminal().filter(|_| !cli.no_progress);
+    let tracker = Arc::new(Tracker::new(progress_bar));
 
-            let input_file = File::open(args.input_file).unwrap();
-            let length = input_file.metadata().unwrap().len() as usize;
+    let result = run(cli.command, &tracker);
+    tracker.finish();
+
+    let (report, exit_code) = match result {
+        Result::Ok(outcome) => {
+            let (status, exit_code) = if outcome.partial {
+                ("partial", ExitCode::from(EXIT_PARTIAL))
+            } else {
+                ("ok", ExitCode::SUCCESS)
+            };
+
+            let report = if json {
+                let mut report = json!({ "command": name, "status": status });
+                if let (Value::Object(report), Value::Object(details)) =
+                    (&mut report, outcome.details)
+                {
+                    report.extend(details);
+                }
+                vec![report.to_string()]
+            } else {
+                outcome.messages
+            };
+
+            (report, exit_code)
+        }
+        Result::Err(error) if json => {
+            let report = json!({
+                "command": name,
+                "status": "error",
+                "error": { "kind": error.failure.name(), "message": error.message },
+            });
+
+            (vec![report.to_string()], error.failure.exit_code())
+        }
+        Result::Err(error) => {
+            eprintln!("parry-cli: {}", error);
+            return error.failure.exit_code();
+        }
+    };
+
+    for line in report {
+        if to_stderr {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    }
+
+    exit_code
+}
+
+fn run(command: Command, tracker: &Arc<Tracker>) -> Result<Outcome, CliError> {
+    match command {
+        Command::Encode(args) => encode(args, tracker),
+        Command::Decode(args) => decode(args, tracker),
+        Command::Append(args) => append(args, tracker),
+        Command::Transcode(args) => transcode(args, tracker),
+        Command::Verify(args) => verify(args, tracker),
+    }
+}
+
+fn encode(args: EncodeArgs, tracker: &Arc<Tracker>) -> Result<Outcome, CliError> {
+    let set_id = SetId::random();
+    let encoder = args.common.encoder(tracker)?.with_set_id(set_id);
+    let shards = args.common.shards();
+    let placement = args.common.placement(shards)?;
+    args.common.check_devices(placement.as_ref(), shards)?;
+
+    let input_path = args
+        .input_file
+        .as_ref()
+        .or(args.input_directory.as_ref())
+        .unwrap();
+    let context = args
+        .common
+        .name_context()
+        .with_set_id(set_id)
+        .with_name(base_name(input_path)?);
+
+    let describe = || format!("Cannot encode {}", input_path.display());
+
+    // The input is opened before any shard file is created so that a missing input leaves no
+    // empty shards behind.
+    let input_file = if args.input_directory.is_none() && !is_stdio(input_path) {
+        Some(File::open(input_path).context(|| format!("Cannot open {}", input_path.display()))?)
+    } else {
+        None
+    };
+
+    #[cfg(feature = "mmap")]
+    if args.mmap {
+        if is_stdio(input_path) {
+            return Result::Err(CliError::usage("Standard input cannot be memory-mapped"));
+        }
+
+        let input_file = input_file.unwrap();
+        let length = input_file.metadata().context(describe)?.len();
+
+        let mut sink = create_shards(
+            placement.as_ref(),
+            &args.output_file_pattern,
+            &context,
+            0..shards,
+        )?;
+        let output_files = sink
+            .writers()
+            .iter()
+            .map(|writer| writer.get_ref().try_clone())
+            .collect::<std::io::Result<Vec<File>>>()
+            .context(describe)?;
+
+        encoder
+            .encode_mmap(&input_file, &output_files)
+            .context(describe)?;
+
+        let paths = sink.paths().to_vec();
+        commit_shards(sink)?;
+
+        return Result::Ok(Outcome::complete(json!({
+            "set_id": set_id.to_string(),
+            "bytes": length,
+            "shards": path_values(&paths),
+        })));
+    }
+
+    if is_stdio(input_path) && (args.sparse || args.compression.is_some()) {
+        return Result::Err(CliError::usage(
+            "--sparse and --compression need an input file of known length",
+        ));
+    }
+
+    let min_shards = args.min_shards.unwrap_or(shards);
+    if min_shards < args.common.data_shards || min_shards > shards {
+        return Result::Err(CliError::usage(format!(
+            "--min-shards must be between {} and {}",
+            args.common.data_shards, shards
+        )));
+    }
+
+    // Checkpoints are taken every interval, rounded down to whole stripes.
+    let interval =
+        (args.checkpoint_interval / (args.common.data_shards * encoder.chunk_size())).max(1);
+
+    if args.resume {
+        let Some(input_file) = input_file else {
+            return Result::Err(CliError::usage("--resume needs an input file"));
+        };
+
+        return resume_encode(
+            &encoder,
+            &args,
+            placement.as_ref(),
+            input_path,
+            input_file,
+            interval,
+        );
+    }
+
+    let mut sink = create_shards(
+        placement.as_ref(),
+        &args.output_file_pattern,
+        &context,
+        0..shards,
+    )?;
+    let paths = sink.paths().to_vec();
+    let recorder = sink.checkpoints().context(describe)?;
+    let policy = WritePolicy::new(min_shards);
+    let mut output_files = policy
+        .writers(sink.writers().iter_mut().map(Some))
+        .context(describe)?;
+
+    let mut skipped = vec![];
+    let length = if let Some(input_directory) = &args.input_directory {
+        let manifest = encoder
+            .encode_directory(input_directory, &mut output_files)
+            .context(describe)?;
+        let length = manifest.data_length() as usize;
+        skipped = manifest.skipped;
+
+        length
+    } else if let Some(input_file) = input_file {
+        let length = input_file.metadata().context(describe)?.len() as usize;
+
+        if args.sparse {
+            let mut input = SparseReader::new(&input_file).context(describe)?;
+            encoder
+                .encode_sparse(&mut input, length, &mut output_files)
+                .context(describe)?;
+        } else {
             let mut buffered_input_file = BufReader::new(input_file);
 
-            let mut output_files =
-                Vec::with_capacity(args.common.data_shards + args.common.parity_shards);
-            for shard in 0..args.common.data_shards + args.common.parity_shards {
-                output_files.push(BufWriter::new(
-                    File::create(args.output_file_pattern.replace("{}", &shard.to_string()))
-                        .unwrap(),
-                ));
+            match args.compression() {
+                Some(compression) => encoder.encode_compressed(
+                    &mut buffered_input_file,
+                    length,
+                    compression,
+                    &mut output_files,
+                ),
+                None if min_shards == shards => encoder.encode_with_checkpoints(
+                    &mut buffered_input_file,
+                    length,
+                    &mut output_files,
+                    interval,
+                    |checkpoint| recorder.record(checkpoint),
+                ),
+                None => encoder.encode(&mut buffered_input_file, length, &mut output_files),
             }
+            .context(describe)?;
+        }
 
-            encoder
-                .encode(&mut buffered_input_file, length, &mut output_files)
-                .unwrap();
+        length
+    } else {
+        encoder
+            .encode_stream(&mut std::io::stdin().lock(), &mut output_files)
+            .context(describe)?
+    };
+
+    // Flushing through the policy lets shards that fail only now be discarded as well.
+    for output_file in output_files.iter_mut() {
+        output_file.flush().context(describe)?;
+    }
+    drop(output_files);
+
+    for shard in policy.failed_shards() {
+        sink.discard(shard);
+    }
+    commit_shards(sink)?;
+
+    let mut outcome = Outcome::written(
+        policy.failed_shards(),
+        json!({
+            "set_id": set_id.to_string(),
+            "bytes": length,
+            "shards": path_values(&paths),
+        }),
+    );
+
+    if !skipped.is_empty() {
+        outcome.messages.push(format!(
+            "Entries that cannot be archived were skipped: {}",
+            skipped.join(", ")
+        ));
+        outcome.details["skipped"] = json!(skipped);
+    }
+
+    Result::Ok(outcome)
+}
+
+/// Continues an interrupted encode of `input_file` from the last checkpoint recorded next to its
+/// shard files. Their names cannot use the set ID, which is only known from the checkpoint.
+fn resume_encode(
+    encoder: &ReedSolomonEncoder,
+    args: &EncodeArgs,
+    placement: Option<&Placement>,
+    input_path: &Path,
+    input_file: File,
+    interval: usize,
+) -> Result<Outcome, CliError> {
+    let context = args.common.name_context().with_name(base_name(input_path)?);
+    let paths = (0..args.common.shards())
+        .map(|shard| shard_path(placement, &args.output_file_pattern, &context, shard))
+        .collect::<Result<Vec<PathBuf>, CliError>>()?;
+
+    let (mut sink, checkpoint) =
+        ShardSink::resume(paths).context(|| "Cannot resume the encode".to_string())?;
+
+    let describe = || format!("Cannot encode {}", input_path.display());
+    let length = input_file.metadata().context(describe)?.len() as usize;
+    if length != checkpoint.length {
+        return Result::Err(CliError::usage(format!(
+            "{} is {} bytes long, but the interrupted encode was of {} bytes",
+            input_path.display(),
+            length,
+            checkpoint.length
+        )));
+    }
+
+    let recorder = sink.checkpoints().context(describe)?;
+    let offset = encoder
+        .resume_encode(
+            &mut BufReader::new(input_file),
+            &checkpoint,
+            sink.writers(),
+            interval,
+            |checkpoint| recorder.record(checkpoint),
+        )
+        .context(describe)?;
+
+    let paths = sink.paths().to_vec();
+    commit_shards(sink)?;
+
+    Result::Ok(Outcome {
+        partial: false,
+        messages: vec![format!("Resumed at byte {} of {}", offset, length)],
+        details: json!({
+            "set_id": checkpoint.set_id.to_string(),
+            "bytes": length,
+            "shards": path_values(&paths),
+            "resumed_from": offset,
+        }),
+    })
+}
+
+fn decode(args: DecodeArgs, tracker: &Arc<Tracker>) -> Result<Outcome, CliError> {
+    let encoder = args.common.encoder(tracker)?;
+    let shards = args.common.shards();
+
+    let (mut input_files, missing) = match &args.input_glob {
+        Some(pattern) => {
+            let paths = glob::glob(pattern)
+                .map_err(|error| CliError::usage(format!("Invalid glob {}: {}", pattern, error)))?;
+            let files = paths
+                .filter_map(|path| File::open(path.ok()?).ok())
+                .collect();
+
+            let input_files: Vec<Option<BufReader<File>>> = encoder
+                .arrange_shards(files)
+                .context(|| format!("Cannot read the shards matching {}", pattern))?
+                .into_iter()
+                .map(|file| file.map(BufReader::new))
+                .collect();
+
+            let missing = missing_shards(&input_files);
+            (input_files, missing)
         }
-        Command::Decode(args) => {
-            eprintln!(
-                "decode: data_shards={}, parity_shards={}, chunk_size={}, input_file_pattern={}, output_file={:?}",
-                args.common.data_shards,
-                args.common.parity_shards,
-                args.common.chunk_size,
-                args.input_file_pattern,
-                args.output_file
-            );
+        None => {
+            let placement = args.common.placement(shards)?;
+            open_shards(find_shards(
+                placement.as_ref(),
+                args.input_file_pattern.as_ref().unwrap(),
+                &args.common.name_context(),
+                shards,
+            )?)
         }
+    };
+
+    let describe = || "Cannot decode the shards".to_string();
+
+    #[cfg(feature = "mmap")]
+    if args.mmap {
+        let input_files: Vec<Option<File>> = input_files
+            .into_iter()
+            .map(|input_file| input_file.map(BufReader::into_inner))
+            .collect();
+
+        let output_path = args.output_file.unwrap();
+        if is_stdio(&output_path) {
+            return Result::Err(CliError::usage("Standard output cannot be memory-mapped"));
+        }
+
+        let output_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&output_path)
+            .context(|| format!("Cannot create {}", output_path.display()))?;
+
+        encoder
+            .decode_mmap(&input_files, &output_file)
+            .context(describe)?;
+
+        return Result::Ok(Outcome::degraded(missing, tracker, json!({})));
+    }
+
+    if let Some(output_directory) = args.output_directory {
+        let manifest = encoder
+            .decode_directory(&mut input_files, &output_directory)
+            .context(describe)?;
+
+        return Result::Ok(Outcome::degraded(
+            missing,
+            tracker,
+            json!({ "entries": manifest.entries.len() }),
+        ));
     }
+
+    let output_path = args.output_file.unwrap();
+    let mut output_file: BufWriter<Box<dyn Write>> = if is_stdio(&output_path) {
+        BufWriter::new(Box::new(std::io::stdout().lock()))
+    } else {
+        let file = File::create(&output_path)
+            .context(|| format!("Cannot create {}", output_path.display()))?;
+        BufWriter::new(Box::new(file))
+    };
+
+    match (args.extract, args.hedge) {
+        (Some(path), _) => encoder
+            .decode_file(&mut input_files, &path, &mut output_file)
+            .map(|_| ())
+            .context(|| format!("Cannot extract {}", path))?,
+        (None, Some(extra_reads)) => encoder
+            .decode_hedged(
+                &mut input_files,
+                &mut output_file,
+                extra_reads,
+                &ShardLatencies::new(),
+            )
+            .context(describe)?,
+        (None, None) => encoder
+            .decode(&mut input_files, &mut output_file)
+            .context(describe)?,
+    }
+
+    output_file
+        .flush()
+        .context(|| format!("Cannot write {}", output_path.display()))?;
+
+    Result::Ok(Outcome::degraded(missing, tracker, json!({})))
+}
+
+fn append(args: AppendArgs, tracker: &Arc<Tracker>) -> Result<Outcome, CliError> {
+    let encoder = args.common.encoder(tracker)?;
+
+    let input_file = File::open(&args.input_file)
+        .context(|| format!("Cannot open {}", args.input_file.display()))?;
+    let describe = || format!("Cannot append {}", args.input_file.display());
+    let length = input_file.metadata().context(describe)?.len() as usize;
+    let mut buffered_input_file = BufReader::new(input_file);
+
+    let shards = args.common.shards();
+    let placement = args.common.placement(shards)?;
+    let shard_paths = find_shards(
+        placement.as_ref(),
+        &args.shard_file_pattern,
+        &args.common.name_context(),
+        shards,
+    )?;
+
+    let mut shard_files = Vec::with_capacity(shards);
+    for (shard, path) in shard_paths.into_iter().enumerate() {
+        let path = path.ok_or_else(|| CliError {
+            failure: Failure::Io,
+            message: format!("Shard {} was not found", shard),
+        })?;
+
+        shard_files.push(
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .context(|| format!("Cannot open shard {} at {}", shard, path.display()))?,
+        );
+    }
+
+    encoder
+        .append(&mut buffered_input_file, length, &mut shard_files)
+        .context(describe)?;
+
+    Result::Ok(Outcome::complete(json!({ "bytes": length })))
+}
+
+fn transcode(args: TranscodeArgs, tracker: &Arc<Tracker>) -> Result<Outcome, CliError> {
+    let source = args.common.encoder(tracker)?;
+
+    let target_set_id = SetId::random();
+    let target = args
+        .target
+        .encoder(&args.common)?
+        .with_set_id(target_set_id);
+
+    let source_shards = args.common.shards();
+    let target_shards = args.target.shards();
+    let placement = args.common.placement(source_shards.max(target_shards))?;
+    args.common
+        .check_devices(placement.as_ref(), target_shards)?;
+
+    let input_paths = find_shards(
+        placement.as_ref(),
+        &args.input_file_pattern,
+        &args.common.name_context(),
+        source_shards,
+    )?;
+    let describe = || "Cannot transcode the shards".to_string();
+
+    if args.output_file_pattern == args.input_file_pattern {
+        if target.data_shards() != source.data_shards()
+            || target.chunk_size() != source.chunk_size()
+            || target.checksum() != source.checksum()
+            || target.parity_shards() < source.parity_shards()
+        {
+            return Result::Err(CliError::usage(
+                "In-place transcoding can only add parity shards",
+            ));
+        }
+
+        // The existing chunks stay authenticated under the key of the source shards.
+        let changes_key = source.checksum().is_keyed()
+            && args
+                .target
+                .target_key_file
+                .as_ref()
+                .is_some_and(|target_key_file| {
+                    args.common.key_file.as_ref().is_none_or(|key_file| {
+                        std::fs::read(target_key_file).ok() != std::fs::read(key_file).ok()
+                    })
+                });
+        if changes_key {
+            return Result::Err(CliError::usage(
+                "In-place transcoding cannot change the key of the shards",
+            ));
+        }
+
+        let mut shard_files: Vec<Option<File>> = input_paths
+            .into_iter()
+            .map(|path| OpenOptions::new().read(true).write(true).open(path?).ok())
+            .collect();
+        let missing = missing_shards(&shard_files);
+
+        // The set keeps its ID when only parity shards are added.
+        let context = NameContext::new(args.target.target_data_shards, target_shards);
+        let mut sink = create_shards(
+            placement.as_ref(),
+            &args.output_file_pattern,
+            &context,
+            source_shards..target_shards,
+        )?;
+
+        source
+            .add_parity(&mut shard_files, &target, sink.writers())
+            .context(describe)?;
+        let paths = sink.paths().to_vec();
+        commit_shards(sink)?;
+
+        return Result::Ok(Outcome::degraded(
+            missing,
+            tracker,
+            json!({ "shards": path_values(&paths) }),
+        ));
+    }
+
+    let (mut input_files, missing) = open_shards(input_paths);
+
+    let target_context =
+        NameContext::new(args.target.target_data_shards, target_shards).with_set_id(target_set_id);
+    let mut sink = create_shards(
+        placement.as_ref(),
+        &args.output_file_pattern,
+        &target_context,
+        0..target_shards,
+    )?;
+
+    source
+        .transcode(&mut input_files, &target, sink.writers())
+        .context(describe)?;
+    let paths = sink.paths().to_vec();
+    commit_shards(sink)?;
+
+    Result::Ok(Outcome::degraded(
+        missing,
+        tracker,
+        json!({
+            "set_id": target_set_id.to_string(),
+            "shards": path_values(&paths),
+        }),
+    ))
+}
+
+fn verify(args: VerifyArgs, tracker: &Arc<Tracker>) -> Result<Outcome, CliError> {
+    let encoder = args.common.encoder(tracker)?;
+
+    let shards = args.common.shards();
+    let placement = args.common.placement(shards)?;
+    let (mut input_files, _) = open_shards(find_shards(
+        placement.as_ref(),
+        &args.input_file_pattern,
+        &args.common.name_context(),
+        shards,
+    )?);
+
+    let damaged = encoder
+        .verify(&mut input_files)
+        .context(|| "Cannot verify the shards".to_string())?;
+
+    let mut messages: Vec<String> = damaged
+        .iter()
+        .enumerate()
+        .filter(|&(_, &count)| count > 0)
+        .map(|(shard, count)| format!("Shard {}: {} damaged chunks", shard, count))
+        .collect();
+
+    let partial = !messages.is_empty();
+    if !partial {
+        messages.push("All chunks are intact".to_string());
+    }
+
+    Result::Ok(Outcome {
+        partial,
+        messages,
+        details: json!({ "damaged_chunks": damaged }),
+    })
 }
@@ -0,0 +1,157 @@
+use std::io::{IsTerminal, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use parry::{Progress, ProgressObserver};
+
+const WIDTH: usize = 30;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Draws a progress bar with the throughput and the estimated time remaining on stderr.
+pub struct ProgressBar {
+    start: Instant,
+    last_draw: Mutex<Option<Instant>>,
+}
+
+impl ProgressBar {
+    /// Returns a progress bar unless stderr is not a terminal.
+    pub fn for_terminal() -> Option<ProgressBar> {
+        std::io::stderr().is_terminal().then(|| ProgressBar {
+            start: Instant::now(),
+            last_draw: Mutex::new(None),
+        })
+    }
+
+    /// Ends the line of the progress bar, if it was drawn, so that other output follows it.
+    pub fn finish(&self) {
+        if self.last_draw.lock().unwrap().take().is_some() {
+            eprintln!();
+        }
+    }
+}
+
+impl ProgressObserver for ProgressBar {
+    fn on_progress(&self, progress: &Progress) {
+        let now = Instant::now();
+        let mut last_draw = self.last_draw.lock().unwrap();
+
+        let finished = progress
+            .total_bytes
+            .is_some_and(|total_bytes| progress.bytes >= total_bytes);
+        if !finished && last_draw.is_some_and(|last_draw| now - last_draw < REDRAW_INTERVAL) {
+            return;
+        }
+        *last_draw = Some(now);
+
+        let elapsed = (now - self.start).as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            progress.bytes as f64 / elapsed
+        } else {
+            0.0
+        };
+
+        let mut line = match progress.total_bytes {
+            Some(total_bytes) if total_bytes > 0 => {
+                let fraction = progress.bytes as f64 / total_bytes as f64;
+                let filled = (fraction * WIDTH as f64) as usize;
+                let eta = if rate > 0.0 {
+                    format_duration((total_bytes - progress.bytes) as f64 / rate)
+                } else {
+                    "?".to_string()
+                };
+
+                format!(
+                    "[{}{}] {:3.0}% {} / {}, {}/s, ETA {}",
+                    "#".repeat(filled),
+                    " ".repeat(WIDTH - filled),
+                    fraction * 100.0,
+                    format_bytes(progress.bytes as f64),
+                    format_bytes(total_bytes as f64),
+                    format_bytes(rate),
+                    eta
+                )
+            }
+            _ => format!(
+                "{}, {}/s",
+                format_bytes(progress.bytes as f64),
+                format_bytes(rate)
+            ),
+        };
+
+        if progress.repaired_chunks > 0 {
+            line.push_str(&format!(", {} chunks repaired", progress.repaired_chunks));
+        }
+
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r{}\x1b[K", line);
+        let _ = stderr.flush();
+    }
+}
+
+/// Keeps the latest progress of an operation, such as the number of chunks repaired, for its
+/// report, and draws it on an optional progress bar.
+pub struct Tracker {
+    progress: Mutex<Progress>,
+    progress_bar: Option<ProgressBar>,
+}
+
+impl Tracker {
+    pub fn new(progress_bar: Option<ProgressBar>) -> Tracker {
+        Tracker {
+            progress: Mutex::new(Progress::default()),
+            progress_bar,
+        }
+    }
+
+    pub fn progress(&self) -> Progress {
+        *self.progress.lock().unwrap()
+    }
+
+    pub fn finish(&self) {
+        if let Some(progress_bar) = &self.progress_bar {
+            progress_bar.finish();
+        }
+    }
+}
+
+impl ProgressObserver for Tracker {
+    fn on_progress(&self, progress: &Progress) {
+        *self.progress.lock().unwrap() = *progress;
+
+        if let Some(progress_bar) = &self.progress_bar {
+            progress_bar.on_progress(progress);
+        }
+    }
+}
+
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{:.0} {}", value, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
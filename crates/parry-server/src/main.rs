@@ -0,0 +1,438 @@
+use clap::Parser;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use tiny_http::{Header, Method, Request, Response, ResponseBox, Server};
+
+use parry::{FileStore, ShardStore};
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "parry-server",
+    version,
+    about = "Serves the shards stored in a directory to parry clients over HTTP"
+)]
+struct Cli {
+    /// Directory holding the shards of this node
+    #[arg(long, value_name = "DIR")]
+    directory: PathBuf,
+
+    /// Address to listen on; port 0 picks any free port
+    #[arg(long, value_name = "ADDRESS", default_value = "127.0.0.1:7480")]
+    listen: String,
+
+    /// Number of requests served at once. Reading an object streams its shards at the same time,
+    /// each occupying a thread until it is read, so this bounds the number of shards of one
+    /// object that can be read from this node
+    #[arg(long, value_name = "N", default_value_t = 16)]
+    threads: usize,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let server = match Server::http(&cli.listen) {
+        Result::Ok(server) => server,
+        Result::Err(error) => {
+            eprintln!("parry-server: Cannot listen on {}: {}", cli.listen, error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Some(address) = server.server_addr().to_ip() {
+        println!("Listening on {}", address);
+    }
+
+    serve(
+        Arc::new(server),
+        Arc::new(FileStore::new(cli.directory)),
+        cli.threads.max(1),
+    );
+
+    ExitCode::SUCCESS
+}
+
+/// Handles requests on `threads` threads until the server is unblocked.
+fn serve(server: Arc<Server>, store: Arc<dyn ShardStore>, threads: usize) {
+    let workers: Vec<_> = (0..threads)
+        .map(|_| {
+            let server = server.clone();
+            let store = store.clone();
+
+            std::thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(store.as_ref(), request);
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+fn handle(store: &dyn ShardStore, mut request: Request) {
+    let response = route(store, &mut request).unwrap_or_else(|error| {
+        let status = match error.kind() {
+            io::ErrorKind::NotFound => 404,
+            io::ErrorKind::InvalidInput => 400,
+            _ => 500,
+        };
+
+        Response::from_string(error.to_string())
+            .with_status_code(status)
+            .boxed()
+    });
+
+    // Clients rely on a Content-Length instead of decoding chunked responses.
+    let _ = request.respond(response.with_chunked_threshold(usize::MAX));
+}
+
+fn bad_request(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn route(store: &dyn ShardStore, request: &mut Request) -> io::Result<ResponseBox> {
+    let url = request.url().to_string();
+    let path = url.split('?').next().unwrap();
+    let segments = path
+        .trim_start_matches('/')
+        .split('/')
+        .map(decode)
+        .collect::<Option<Vec<String>>>()
+        .ok_or_else(|| bad_request("Malformed path"))?;
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+    let shard = |index: &str| {
+        index
+            .parse::<usize>()
+            .map_err(|_| bad_request("Malformed shard index"))
+    };
+
+    match (request.method(), segments.as_slice()) {
+        (Method::Get, ["shards", object]) => {
+            let listing: String = store
+                .list(object)?
+                .iter()
+                .map(|shard| format!("{}\n", shard))
+                .collect();
+            Result::Ok(Response::from_string(listing).boxed())
+        }
+        (Method::Put, ["shards", object, index]) => {
+            let mut writer = store.put(object, shard(index)?)?;
+            io::copy(request.as_reader(), &mut writer)?;
+            writer.commit()?;
+            Result::Ok(Response::empty(201).boxed())
+        }
+        (Method::Get, ["shards", object, index]) => get(store, request, object, shard(index)?),
+        (Method::Head, ["shards", object, index]) => {
+            let size = store.size(object, shard(index)?)?;
+            Result::Ok(
+                Response::new(200.into(), vec![], io::empty(), Some(size as usize), None).boxed(),
+            )
+        }
+        (Method::Delete, ["shards", object, index]) => {
+            store.delete(object, shard(index)?)?;
+            Result::Ok(Response::empty(204).boxed())
+        }
+        (Method::Get, ["checksum", object, index]) => {
+            let mut hasher = blake3::Hasher::new();
+            io::copy(&mut store.get(object, shard(index)?)?, &mut hasher)?;
+            Result::Ok(Response::from_string(hasher.finalize().to_hex().to_string()).boxed())
+        }
+        _ => Result::Ok(
+            Response::from_string("No such resource")
+                .with_status_code(404)
+                .boxed(),
+        ),
+    }
+}
+
+/// Serves a shard, or the part of it selected by a `Range` header.
+fn get(
+    store: &dyn ShardStore,
+    request: &Request,
+    object: &str,
+    shard: usize,
+) -> io::Result<ResponseBox> {
+    let size = store.size(object, shard)?;
+
+    let range = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Range"))
+        .and_then(|header| parse_range(header.value.as_str(), size));
+
+    let (first, last) = match range {
+        None => {
+            let reader = store.get(object, shard)?.take(size);
+            return Result::Ok(
+                Response::new(200.into(), vec![], reader, Some(size as usize), None).boxed(),
+            );
+        }
+        Some(None) => {
+            let content_range = format!("bytes */{}", size);
+            return Result::Ok(
+                Response::empty(416)
+                    .with_header(header("Content-Range", &content_range))
+                    .boxed(),
+            );
+        }
+        Some(Some(range)) => range,
+    };
+
+    let length = last - first + 1;
+    let reader = store.get_range(object, shard, first, length)?.take(length);
+    let content_range = format!("bytes {}-{}/{}", first, last, size);
+
+    Result::Ok(
+        Response::new(
+            206.into(),
+            vec![header("Content-Range", &content_range)],
+            reader,
+            Some(length as usize),
+            None,
+        )
+        .boxed(),
+    )
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("Header is valid")
+}
+
+/// Parses a single byte range as in `bytes=10-19`, `bytes=10-` or `bytes=-10` into the first
+/// and last byte it selects of a shard of `size` bytes. Returns `None` for malformed ranges,
+/// which are ignored, and `Some(None)` for ranges that select nothing.
+fn parse_range(value: &str, size: u64) -> Option<Option<(u64, u64)>> {
+    let (first, last) = value.trim().strip_prefix("bytes=")?.split_once('-')?;
+
+    let (first, last) = if first.is_empty() {
+        let suffix: u64 = last.parse().ok()?;
+        (size.saturating_sub(suffix), size.checked_sub(1)?)
+    } else {
+        let first: u64 = first.parse().ok()?;
+        let last = if last.is_empty() {
+            u64::MAX
+        } else {
+            last.parse().ok()?
+        };
+        (first, last.min(size.saturating_sub(1)))
+    };
+
+    if first > last || first >= size {
+        return Some(None);
+    }
+
+    Some(Some((first, last)))
+}
+
+/// Decodes a percent-encoded path segment.
+fn decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let digits = segment.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(digits, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parry::{ReedSolomonEncoder, RemoteStore};
+    use std::fs;
+    use std::io::Cursor;
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    /// The same as the helper of the parry tests, which are not compiled for dependent crates.
+    fn scratch_directory(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("parry-server-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    /// Starts a server for `directory` on a free port, returning its address.
+    fn start(directory: PathBuf) -> String {
+        start_with_threads(directory, 4)
+    }
+
+    fn start_with_threads(directory: PathBuf, threads: usize) -> String {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap().to_string();
+
+        std::thread::spawn(move || {
+            serve(
+                Arc::new(server),
+                Arc::new(FileStore::new(directory)),
+                threads,
+            );
+        });
+
+        address
+    }
+
+    /// Returns an address that refuses connections.
+    fn dead_address() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    fn buffer(length: usize) -> Vec<u8> {
+        (0..length).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn cluster() {
+        let directory = scratch_directory("cluster");
+        let addresses: Vec<String> = (0..6)
+            .map(|node| start(directory.join(format!("node{}", node))))
+            .collect();
+
+        let encoder = ReedSolomonEncoder::new(4, 2, 1000);
+        let store = RemoteStore::new(addresses.clone());
+        let data = buffer(100_017);
+        let object = "disk image #1";
+
+        encoder
+            .put_object(&store, object, &mut Cursor::new(&data), data.len())
+            .unwrap();
+        assert_eq!(store.list(object).unwrap(), vec![0, 1, 2, 3, 4, 5]);
+
+        let path = directory.join("node3").join(format!("{}.3", object));
+        let contents = fs::read(&path).unwrap();
+        assert_eq!(store.size(object, 3).unwrap(), contents.len() as u64);
+        assert_eq!(
+            store.checksum(object, 3).unwrap(),
+            *blake3::hash(&contents).as_bytes()
+        );
+
+        let mut range = vec![];
+        store
+            .get_range(object, 3, 100, 50)
+            .unwrap()
+            .read_to_end(&mut range)
+            .unwrap();
+        assert!(range == contents[100..150]);
+
+        let mut output = vec![];
+        encoder.get_object(&store, object, &mut output).unwrap();
+        assert!(output == data);
+
+        // Two nodes going down leaves four to decode from.
+        let mut degraded = addresses.clone();
+        degraded[0] = dead_address();
+        degraded[4] = dead_address();
+        let degraded = RemoteStore::new(degraded).with_timeout(Duration::from_secs(5));
+        assert_eq!(degraded.list(object).unwrap(), vec![1, 2, 3, 5]);
+
+        let mut output = vec![];
+        encoder.get_object(&degraded, object, &mut output).unwrap();
+        assert!(output == data);
+
+        let mut output = vec![];
+        encoder
+            .get_object_at(&degraded, object, &mut output, 54321, 20000)
+            .unwrap();
+        assert!(output == data[54321..74321]);
+
+        encoder.delete_object(&store, object).unwrap();
+        assert!(store.list(object).unwrap().is_empty());
+        assert_eq!(
+            store.get(object, 0).err().map(|error| error.kind()),
+            Some(io::ErrorKind::NotFound)
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn more_shards_than_threads() {
+        let directory = scratch_directory("threads");
+        let address = start_with_threads(directory.clone(), 1);
+
+        // Every shard lands on the same single-threaded node, and each is larger than socket
+        // buffers hold.
+        let encoder = ReedSolomonEncoder::new(4, 2, 1000);
+        let store = RemoteStore::new(vec![address]).with_timeout(Duration::from_secs(5));
+        let data = buffer(16_000_000);
+
+        encoder
+            .put_object(&store, "object", &mut Cursor::new(&data), data.len())
+            .unwrap();
+        assert_eq!(store.list("object").unwrap(), vec![0, 1, 2, 3, 4, 5]);
+
+        let mut output = vec![];
+        encoder
+            .get_object(&FileStore::new(directory.clone()), "object", &mut output)
+            .unwrap();
+        assert!(output == data);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn requests() {
+        let directory = scratch_directory("requests");
+        let store = RemoteStore::new(vec![start(directory.clone())]);
+
+        let mut writer = store.put("object", 0).unwrap();
+        writer.write_all(b"0123456789").unwrap();
+        writer.commit().unwrap();
+
+        let read = |offset, length| {
+            let mut contents = vec![];
+            store
+                .get_range("object", 0, offset, length)
+                .unwrap()
+                .read_to_end(&mut contents)
+                .unwrap();
+            contents
+        };
+        assert_eq!(read(2, 3), b"234");
+        assert_eq!(read(8, 100), b"89");
+        assert_eq!(read(10, 5), b"");
+
+        // Uploads that end early are discarded.
+        let mut writer = store.put("object", 0).unwrap();
+        writer.write_all(&buffer(200_000)).unwrap();
+        drop(writer);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(read(0, 10), b"0123456789");
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+
+        assert_eq!(
+            store.size("../escape", 0).err().map(|error| error.kind()),
+            Some(io::ErrorKind::InvalidInput)
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("bytes=2-4", 10), Some(Some((2, 4))));
+        assert_eq!(parse_range("bytes=2-", 10), Some(Some((2, 9))));
+        assert_eq!(parse_range("bytes=5-100", 10), Some(Some((5, 9))));
+        assert_eq!(parse_range("bytes=-3", 10), Some(Some((7, 9))));
+        assert_eq!(parse_range("bytes=10-12", 10), Some(None));
+        assert_eq!(parse_range("bytes=0-0", 0), Some(None));
+        assert_eq!(parse_range("items=0-1", 10), None);
+        assert_eq!(parse_range("bytes=a-1", 10), None);
+    }
+}
@@ -0,0 +1,939 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Take, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::ReedSolomonEncoder;
+use crate::stripe::StripeDecoder;
+
+const MAGIC: [u8; 4] = *b"PRRA";
+const LENGTH_PREFIX_SIZE: usize = 8;
+/// Largest manifest accepted when decoding, which bounds the memory a corrupted or crafted length
+/// prefix can make a decode buffer.
+const MAX_MANIFEST_LENGTH: usize = 1 << 30;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    /// A symbolic link, which is archived as its target rather than followed.
+    Symlink,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ManifestEntry {
+    /// Path relative to the root of the archive, with `/` separating components.
+    pub path: String,
+    pub kind: EntryKind,
+    pub mode: u32,
+    pub modified: SystemTime,
+    pub size: u64,
+    /// Offset of the file contents relative to the start of the archived file data.
+    pub offset: u64,
+    /// Target of a symbolic link, exactly as it was read from the link.
+    pub target: Option<String>,
+}
+
+/// Describes every file, directory and symbolic link in an archived directory tree. The manifest
+/// is stored at the start of the encoded stream, followed by the contents of every file in
+/// manifest order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+    /// Paths left out of the archive because they cannot be archived, such as sockets, FIFOs,
+    /// devices and symbolic links whose target is not UTF-8. They are not stored, so decoded
+    /// manifests have none.
+    pub skipped: Vec<String>,
+}
+
+impl Manifest {
+    /// Walks the tree under `root` in lexicographic order of file names, so that encoding the
+    /// same tree twice produces the same stream. Symbolic links are not followed, and entries
+    /// that cannot be archived are recorded in `skipped` instead of failing the walk.
+    pub fn from_directory(root: &Path) -> io::Result<Manifest> {
+        let mut manifest = Manifest {
+            entries: vec![],
+            skipped: vec![],
+        };
+        let mut offset = 0;
+
+        manifest.walk(root, "", &mut offset)?;
+
+        Result::Ok(manifest)
+    }
+
+    fn walk(&mut self, directory: &Path, prefix: &str, offset: &mut u64) -> io::Result<()> {
+        let mut children = fs::read_dir(directory)?.collect::<io::Result<Vec<fs::DirEntry>>>()?;
+        children.sort_by_key(|child| child.file_name());
+
+        for child in children {
+            let name = child.file_name().into_string().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Non UTF-8 file name in {}", directory.display()),
+                )
+            })?;
+
+            let path = format!("{}{}", prefix, name);
+            let metadata = child.metadata()?;
+
+            let (kind, target) = if metadata.is_dir() {
+                (EntryKind::Directory, None)
+            } else if metadata.is_file() {
+                (EntryKind::File, None)
+            } else if metadata.is_symlink()
+                && let Result::Ok(target) =
+                    fs::read_link(child.path())?.into_os_string().into_string()
+            {
+                (EntryKind::Symlink, Some(target))
+            } else {
+                self.skipped.push(path);
+                continue;
+            };
+
+            let size = if kind == EntryKind::File {
+                metadata.len()
+            } else {
+                0
+            };
+
+            self.entries.push(ManifestEntry {
+                path: path.clone(),
+                kind,
+                mode: mode(&metadata),
+                modified: metadata.modified()?,
+                size,
+                offset: *offset,
+                target,
+            });
+
+            *offset += size;
+
+            if kind == EntryKind::Directory {
+                self.walk(&child.path(), &format!("{}/", path), offset)?;
+            }
+        }
+
+        Result::Ok(())
+    }
+
+    pub fn entry(&self, path: &str) -> Option<&ManifestEntry> {
+        self.entries.iter().find(|entry| entry.path == path)
+    }
+
+    pub fn data_length(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+
+        for entry in self.entries.iter() {
+            let (seconds, nanoseconds) = to_timestamp(entry.modified);
+
+            bytes.extend_from_slice(&(entry.path.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(entry.path.as_bytes());
+            bytes.push(match entry.kind {
+                EntryKind::File => 0,
+                EntryKind::Directory => 1,
+                EntryKind::Symlink => 2,
+            });
+            bytes.extend_from_slice(&entry.mode.to_be_bytes());
+            bytes.extend_from_slice(&seconds.to_be_bytes());
+            bytes.extend_from_slice(&nanoseconds.to_be_bytes());
+            bytes.extend_from_slice(&entry.size.to_be_bytes());
+            bytes.extend_from_slice(&entry.offset.to_be_bytes());
+
+            if let Some(target) = &entry.target {
+                bytes.extend_from_slice(&(target.len() as u32).to_be_bytes());
+                bytes.extend_from_slice(target.as_bytes());
+            }
+        }
+
+        bytes
+    }
+
+    fn from_bytes(mut bytes: &[u8]) -> io::Result<Manifest> {
+        fn take<'a>(bytes: &mut &'a [u8], count: usize) -> io::Result<&'a [u8]> {
+            if bytes.len() < count {
+                return Result::Err(invalid_manifest());
+            }
+
+            let (taken, rest) = bytes.split_at(count);
+            *bytes = rest;
+            Result::Ok(taken)
+        }
+
+        fn take_array<const N: usize>(bytes: &mut &[u8]) -> io::Result<[u8; N]> {
+            Result::Ok(take(bytes, N)?.try_into().unwrap())
+        }
+
+        fn take_string(bytes: &mut &[u8]) -> io::Result<String> {
+            let length = u32::from_be_bytes(take_array(bytes)?) as usize;
+            String::from_utf8(take(bytes, length)?.to_vec()).map_err(|_| invalid_manifest())
+        }
+
+        if take_array::<4>(&mut bytes)? != MAGIC {
+            return Result::Err(invalid_manifest());
+        }
+
+        let count = u32::from_be_bytes(take_array(&mut bytes)?) as usize;
+        let mut entries = Vec::with_capacity(count.min(bytes.len()));
+
+        for _ in 0..count {
+            let path = take_string(&mut bytes)?;
+
+            let kind = match take_array::<1>(&mut bytes)?[0] {
+                0 => EntryKind::File,
+                1 => EntryKind::Directory,
+                2 => EntryKind::Symlink,
+                _ => return Result::Err(invalid_manifest()),
+            };
+
+            let mode = u32::from_be_bytes(take_array(&mut bytes)?);
+            let seconds = i64::from_be_bytes(take_array(&mut bytes)?);
+            let nanoseconds = u32::from_be_bytes(take_array(&mut bytes)?);
+            let size = u64::from_be_bytes(take_array(&mut bytes)?);
+            let offset = u64::from_be_bytes(take_array(&mut bytes)?);
+
+            let target = match kind {
+                EntryKind::Symlink if size == 0 => Some(take_string(&mut bytes)?),
+                EntryKind::Symlink => return Result::Err(invalid_manifest()),
+                _ => None,
+            };
+
+            entries.push(ManifestEntry {
+                path,
+                kind,
+                mode,
+                modified: from_timestamp(seconds, nanoseconds),
+                size,
+                offset,
+                target,
+            });
+        }
+
+        if !bytes.is_empty() {
+            return Result::Err(invalid_manifest());
+        }
+
+        Result::Ok(Manifest {
+            entries,
+            skipped: vec![],
+        })
+    }
+}
+
+fn invalid_manifest() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Invalid archive manifest")
+}
+
+/// Returns the manifest length recorded in the length prefix at the start of `prefix`, checking
+/// that the manifest fits within the `length` bytes of the archive.
+fn manifest_length(prefix: &[u8], length: usize) -> io::Result<usize> {
+    let manifest_length = u64::from_be_bytes(prefix[..LENGTH_PREFIX_SIZE].try_into().unwrap());
+    let available = length
+        .checked_sub(LENGTH_PREFIX_SIZE)
+        .ok_or_else(invalid_manifest)?
+        .min(MAX_MANIFEST_LENGTH);
+
+    usize::try_from(manifest_length)
+        .ok()
+        .filter(|&manifest_length| manifest_length <= available)
+        .ok_or_else(invalid_manifest)
+}
+
+fn to_timestamp(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Result::Ok(duration) => (duration.as_secs() as i64, duration.subsec_nanos()),
+        Result::Err(error) => {
+            let duration = error.duration();
+
+            if duration.subsec_nanos() == 0 {
+                (-(duration.as_secs() as i64), 0)
+            } else {
+                (
+                    -(duration.as_secs() as i64) - 1,
+                    1_000_000_000 - duration.subsec_nanos(),
+                )
+            }
+        }
+    }
+}
+
+fn from_timestamp(seconds: i64, nanoseconds: u32) -> SystemTime {
+    if seconds >= 0 {
+        UNIX_EPOCH + Duration::new(seconds as u64, nanoseconds)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs()) + Duration::new(0, nanoseconds)
+    }
+}
+
+#[cfg(unix)]
+fn mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode()
+}
+
+#[cfg(not(unix))]
+fn mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_target: &str, path: &Path) -> io::Result<()> {
+    Result::Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "Cannot restore the symbolic link {} outside Unix",
+            path.display()
+        ),
+    ))
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o200 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+/// Resolves an archived path under `root`, refusing anything that could escape it.
+fn resolve(root: &Path, path: &str) -> io::Result<PathBuf> {
+    let relative = Path::new(path);
+
+    if path.is_empty()
+        || !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Result::Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Refusing to extract unsafe path {:?}", path),
+        ));
+    }
+
+    Result::Ok(root.join(relative))
+}
+
+/// Produces the archive stream for a directory tree: the length of the manifest, the manifest
+/// itself and then the contents of every file.
+struct ArchiveReader<'a> {
+    root: &'a Path,
+    manifest: &'a Manifest,
+    prefix: Vec<u8>,
+    position: usize,
+    entry: usize,
+    file: Option<Take<File>>,
+}
+
+impl<'a> ArchiveReader<'a> {
+    fn new(root: &'a Path, manifest: &'a Manifest) -> ArchiveReader<'a> {
+        let manifest_bytes = manifest.to_bytes();
+
+        let mut prefix = Vec::with_capacity(LENGTH_PREFIX_SIZE + manifest_bytes.len());
+        prefix.extend_from_slice(&(manifest_bytes.len() as u64).to_be_bytes());
+        prefix.extend_from_slice(&manifest_bytes);
+
+        ArchiveReader {
+            root,
+            manifest,
+            prefix,
+            position: 0,
+            entry: 0,
+            file: None,
+        }
+    }
+
+    fn length(&self) -> usize {
+        self.prefix.len() + self.manifest.data_length() as usize
+    }
+}
+
+impl Read for ArchiveReader<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.position < self.prefix.len() {
+            let count = buffer.len().min(self.prefix.len() - self.position);
+            buffer[..count].copy_from_slice(&self.prefix[self.position..self.position + count]);
+            self.position += count;
+            return Result::Ok(count);
+        }
+
+        loop {
+            if let Some(file) = &mut self.file {
+                let count = file.read(buffer)?;
+
+                if count > 0 || buffer.is_empty() {
+                    return Result::Ok(count);
+                }
+
+                if file.limit() > 0 {
+                    return Result::Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!(
+                            "{} shrank while it was being encoded",
+                            self.manifest.entries[self.entry - 1].path
+                        ),
+                    ));
+                }
+
+                self.file = None;
+            }
+
+            let Some(entry) = self.manifest.entries.get(self.entry) else {
+                return Result::Ok(0);
+            };
+
+            self.entry += 1;
+
+            if entry.kind == EntryKind::File && entry.size > 0 {
+                let file = File::open(self.root.join(&entry.path))?;
+                self.file = Some(file.take(entry.size));
+            }
+        }
+    }
+}
+
+/// Consumes an archive stream and recreates the directory tree it describes under `root`.
+struct ArchiveExtractor<'a> {
+    root: &'a Path,
+    prefix: Vec<u8>,
+    manifest: Option<Manifest>,
+    entry: usize,
+    file: Option<(File, u64)>,
+}
+
+impl<'a> ArchiveExtractor<'a> {
+    fn new(root: &'a Path) -> ArchiveExtractor<'a> {
+        ArchiveExtractor {
+            root,
+            prefix: vec![],
+            manifest: None,
+            entry: 0,
+            file: None,
+        }
+    }
+
+    fn parse_manifest(&mut self) -> io::Result<()> {
+        let manifest_length = manifest_length(&self.prefix, usize::MAX)?;
+
+        if self.prefix.len() < LENGTH_PREFIX_SIZE + manifest_length {
+            return Result::Ok(());
+        }
+
+        let manifest = Manifest::from_bytes(&self.prefix[LENGTH_PREFIX_SIZE..])?;
+
+        fs::create_dir_all(self.root)?;
+
+        for entry in manifest.entries.iter() {
+            let path = resolve(self.root, &entry.path)?;
+
+            if entry.kind == EntryKind::Directory {
+                fs::create_dir_all(path)?;
+            }
+        }
+
+        self.manifest = Some(manifest);
+        self.open_next_file()
+    }
+
+    /// Opens the next file with contents left to write, finishing empty files along the way.
+    fn open_next_file(&mut self) -> io::Result<()> {
+        let manifest = self.manifest.as_ref().unwrap();
+
+        while let Some(entry) = manifest.entries.get(self.entry) {
+            if entry.kind == EntryKind::File {
+                let path = resolve(self.root, &entry.path)?;
+                let file = File::create(&path)?;
+
+                if entry.size > 0 {
+                    self.file = Some((file, entry.size));
+                    return Result::Ok(());
+                }
+
+                finish_file(file, &path, entry)?;
+            }
+
+            self.entry += 1;
+        }
+
+        Result::Ok(())
+    }
+
+    fn finish(self) -> io::Result<Manifest> {
+        let Some(manifest) = self.manifest else {
+            return Result::Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Archive ended before its manifest",
+            ));
+        };
+
+        if self.file.is_some() {
+            return Result::Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Archive ended before the contents of every file",
+            ));
+        }
+
+        // Links are only created once every file is written, so that no file is written through
+        // one, and before directory times are restored, which creating them would change.
+        for entry in manifest.entries.iter() {
+            if let (EntryKind::Symlink, Some(target)) = (entry.kind, &entry.target) {
+                create_symlink(target, &resolve(self.root, &entry.path)?)?;
+            }
+        }
+
+        for entry in manifest.entries.iter().rev() {
+            if entry.kind == EntryKind::Directory {
+                let path = resolve(self.root, &entry.path)?;
+                File::open(&path)?.set_modified(entry.modified)?;
+                set_mode(&path, entry.mode)?;
+            }
+        }
+
+        Result::Ok(manifest)
+    }
+}
+
+fn finish_file(file: File, path: &Path, entry: &ManifestEntry) -> io::Result<()> {
+    file.set_modified(entry.modified)?;
+    drop(file);
+
+    set_mode(path, entry.mode)
+}
+
+impl Write for ArchiveExtractor<'_> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        if self.manifest.is_none() {
+            let needed = if self.prefix.len() < LENGTH_PREFIX_SIZE {
+                LENGTH_PREFIX_SIZE - self.prefix.len()
+            } else {
+                LENGTH_PREFIX_SIZE + manifest_length(&self.prefix, usize::MAX)? - self.prefix.len()
+            };
+
+            let count = buffer.len().min(needed);
+            self.prefix.extend_from_slice(&buffer[..count]);
+
+            if self.prefix.len() >= LENGTH_PREFIX_SIZE {
+                self.parse_manifest()?;
+            }
+
+            return Result::Ok(count);
+        }
+
+        let Some((file, remaining)) = &mut self.file else {
+            return Result::Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Archive contains more data than its manifest describes",
+            ));
+        };
+
+        let count = buffer.len().min(*remaining as usize);
+        file.write_all(&buffer[..count])?;
+        *remaining -= count as u64;
+
+        if *remaining == 0 {
+            let (file, _) = self.file.take().unwrap();
+            let manifest = self.manifest.as_ref().unwrap();
+            let entry = &manifest.entries[self.entry];
+
+            finish_file(file, &resolve(self.root, &entry.path)?, entry)?;
+
+            self.entry += 1;
+            self.open_next_file()?;
+        }
+
+        Result::Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some((file, _)) => file.flush(),
+            None => Result::Ok(()),
+        }
+    }
+}
+
+impl ReedSolomonEncoder {
+    /// Encodes every file, directory and symbolic link under `root`, along with a manifest
+    /// describing them. Entries that cannot be archived are left out and listed in the
+    /// `skipped` paths of the manifest returned.
+    pub fn encode_directory<W: Write>(
+        &self,
+        root: &Path,
+        shard_writers: &mut [W],
+    ) -> io::Result<Manifest> {
+        let manifest = Manifest::from_directory(root)?;
+
+        let mut archive = ArchiveReader::new(root, &manifest);
+        let length = archive.length();
+        self.encode(&mut archive, length, shard_writers)?;
+
+        Result::Ok(manifest)
+    }
+
+    /// Restores a directory tree encoded with `encode_directory` under `root`.
+    pub fn decode_directory<R: Read>(
+        &self,
+        shard_readers: &mut [Option<R>],
+        root: &Path,
+    ) -> io::Result<Manifest> {
+        let mut extractor = ArchiveExtractor::new(root);
+        self.decode(shard_readers, &mut extractor)?;
+        extractor.finish()
+    }
+
+    /// Reads only the manifest of a directory tree encoded with `encode_directory`.
+    pub fn decode_manifest<R: Read + Seek>(
+        &self,
+        shard_readers: &mut [Option<R>],
+    ) -> io::Result<Manifest> {
+        let (manifest, _) = self.decode_manifest_and_length(shard_readers)?;
+        Result::Ok(manifest)
+    }
+
+    fn decode_manifest_and_length<R: Read + Seek>(
+        &self,
+        shard_readers: &mut [Option<R>],
+    ) -> io::Result<(Manifest, usize)> {
+        let length = self.archive_length(shard_readers)?;
+
+        let mut length_prefix = Vec::with_capacity(LENGTH_PREFIX_SIZE);
+        self.decode_at(shard_readers, &mut length_prefix, 0, LENGTH_PREFIX_SIZE)?;
+
+        let manifest_length = manifest_length(&length_prefix, length)?;
+
+        let mut manifest_bytes = Vec::with_capacity(manifest_length);
+        self.decode_at(
+            shard_readers,
+            &mut manifest_bytes,
+            LENGTH_PREFIX_SIZE,
+            manifest_length,
+        )?;
+
+        Result::Ok((
+            Manifest::from_bytes(&manifest_bytes)?,
+            LENGTH_PREFIX_SIZE + manifest_length,
+        ))
+    }
+
+    /// Returns the length of the archive encoded in the shards, which are left at their start.
+    /// Archives are never compressed, so compressed shard sets are rejected.
+    fn archive_length<R: Read + Seek>(&self, shard_readers: &mut [Option<R>]) -> io::Result<usize> {
+        for shard_reader in shard_readers.iter_mut() {
+            if let Some(reader) = shard_reader
+                && reader.seek(SeekFrom::Start(0)).is_err()
+            {
+                *shard_reader = None;
+            }
+        }
+
+        let mut decoder = StripeDecoder::new(self);
+        decoder.read_headers(shard_readers)?;
+        let length = decoder.read_trailer(shard_readers)?;
+
+        if decoder.set().framing.is_some() {
+            return Result::Err(invalid_manifest());
+        }
+
+        Result::Ok(length)
+    }
+
+    /// Extracts the contents of a single file from a directory tree encoded with
+    /// `encode_directory`, decoding only the stripes that hold it.
+    pub fn decode_file<R: Read + Seek, W: Write>(
+        &self,
+        shard_readers: &mut [Option<R>],
+        path: &str,
+        output: &mut W,
+    ) -> io::Result<ManifestEntry> {
+        let (manifest, data_start) = self.decode_manifest_and_length(shard_readers)?;
+
+        let Some(entry) = manifest
+            .entry(path)
+            .filter(|entry| entry.kind == EntryKind::File)
+        else {
+            return Result::Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No file {:?} in archive", path),
+            ));
+        };
+
+        let offset = usize::try_from(entry.offset)
+            .ok()
+            .and_then(|offset| offset.checked_add(data_start))
+            .ok_or_else(invalid_manifest)?;
+
+        self.decode_at(shard_readers, output, offset, entry.size as usize)?;
+
+        Result::Ok(entry.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SetId;
+    use crate::test_util::{scratch_directory, shard_readers};
+    use std::io::Cursor;
+
+    fn populate(root: &Path) {
+        fs::create_dir_all(root.join("b/nested/deeper")).unwrap();
+        fs::create_dir_all(root.join("a-empty")).unwrap();
+        fs::write(root.join("top.txt"), b"hello world").unwrap();
+        fs::write(root.join("empty"), b"").unwrap();
+        fs::write(root.join("b/one.bin"), vec![7u8; 5000]).unwrap();
+        fs::write(
+            root.join("b/nested/deeper/two.bin"),
+            (0..20000u32).map(|i| (i % 251) as u8).collect::<Vec<u8>>(),
+        )
+        .unwrap();
+
+        File::options()
+            .write(true)
+            .open(root.join("top.txt"))
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::new(1_500_000_000, 123))
+            .unwrap();
+
+        set_mode(&root.join("b/one.bin"), 0o100600).unwrap();
+    }
+
+    fn encode(encoder: &ReedSolomonEncoder, root: &Path) -> (Manifest, Vec<Vec<u8>>) {
+        let mut writers: Vec<Cursor<Vec<u8>>> = (0..6).map(|_| Cursor::new(vec![])).collect();
+        let manifest = encoder.encode_directory(root, &mut writers).unwrap();
+        (
+            manifest,
+            writers.into_iter().map(Cursor::into_inner).collect(),
+        )
+    }
+
+    #[test]
+    fn manifest_round_trip() {
+        let root = scratch_directory("archive-manifest");
+        populate(&root);
+
+        let manifest = Manifest::from_directory(&root).unwrap();
+
+        let paths: Vec<&str> = manifest
+            .entries
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "a-empty",
+                "b",
+                "b/nested",
+                "b/nested/deeper",
+                "b/nested/deeper/two.bin",
+                "b/one.bin",
+                "empty",
+                "top.txt"
+            ]
+        );
+
+        assert_eq!(
+            Manifest::from_bytes(&manifest.to_bytes()).unwrap(),
+            manifest
+        );
+        assert_eq!(manifest.data_length(), 11 + 5000 + 20000);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn timestamps() {
+        for (seconds, nanoseconds) in [(0, 0), (1_700_000_000, 999_999_999), (-1, 5), (-86400, 0)] {
+            let time = from_timestamp(seconds, nanoseconds);
+            assert_eq!(to_timestamp(time), (seconds, nanoseconds));
+        }
+    }
+
+    #[test]
+    fn encode_and_decode_directory() {
+        let root = scratch_directory("archive-source");
+        let restored = scratch_directory("archive-restored");
+        populate(&root);
+
+        let encoder = ReedSolomonEncoder::new(4, 2, 1000).with_set_id(SetId::random());
+        let (manifest, shards) = encode(&encoder, &root);
+
+        assert_eq!(encode(&encoder, &root).1, shards);
+
+        let decoded_manifest = encoder
+            .decode_directory(&mut shard_readers(&shards, &[1]), &restored)
+            .unwrap();
+        assert_eq!(decoded_manifest, manifest);
+
+        for entry in manifest.entries.iter() {
+            let metadata = fs::metadata(restored.join(&entry.path)).unwrap();
+            assert_eq!(
+                metadata.modified().unwrap(),
+                entry.modified,
+                "{}",
+                entry.path
+            );
+            assert_eq!(mode(&metadata), entry.mode, "{}", entry.path);
+
+            if entry.kind == EntryKind::File {
+                assert_eq!(
+                    fs::read(restored.join(&entry.path)).unwrap(),
+                    fs::read(root.join(&entry.path)).unwrap()
+                );
+            }
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+        fs::remove_dir_all(&restored).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_and_special_files() {
+        let root = scratch_directory("archive-links");
+        let restored = scratch_directory("archive-links-restored");
+        populate(&root);
+
+        std::os::unix::fs::symlink("b/one.bin", root.join("link")).unwrap();
+        std::os::unix::fs::symlink("../outside", root.join("b/dangling")).unwrap();
+        let _listener = std::os::unix::net::UnixListener::bind(root.join("b/socket")).unwrap();
+
+        let encoder = ReedSolomonEncoder::new(4, 2, 1000);
+        let (manifest, shards) = encode(&encoder, &root);
+        assert_eq!(manifest.skipped, ["b/socket"]);
+        assert_eq!(manifest.data_length(), 11 + 5000 + 20000);
+
+        let link = manifest.entry("link").unwrap();
+        assert_eq!(link.kind, EntryKind::Symlink);
+        assert_eq!(link.target.as_deref(), Some("b/one.bin"));
+        assert_eq!(link.size, 0);
+
+        let decoded_manifest = encoder
+            .decode_directory(&mut shard_readers(&shards, &[2]), &restored)
+            .unwrap();
+        assert_eq!(decoded_manifest.entries, manifest.entries);
+        assert!(decoded_manifest.skipped.is_empty());
+
+        assert_eq!(
+            fs::read_link(restored.join("link")).unwrap(),
+            Path::new("b/one.bin")
+        );
+        assert_eq!(
+            fs::read_link(restored.join("b/dangling")).unwrap(),
+            Path::new("../outside")
+        );
+        assert!(!restored.join("b/socket").exists());
+        assert_eq!(
+            fs::metadata(restored.join("b"))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            manifest.entry("b").unwrap().modified
+        );
+
+        let mut output = vec![];
+        assert!(
+            encoder
+                .decode_file(&mut shard_readers(&shards, &[0]), "link", &mut output)
+                .is_err()
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+        fs::remove_dir_all(&restored).unwrap();
+    }
+
+    #[test]
+    fn decode_single_file() {
+        let root = scratch_directory("archive-single");
+        populate(&root);
+
+        let encoder = ReedSolomonEncoder::new(4, 2, 1000);
+        let (_, shards) = encode(&encoder, &root);
+
+        let mut output = vec![];
+        let entry = encoder
+            .decode_file(
+                &mut shard_readers(&shards, &[0]),
+                "b/nested/deeper/two.bin",
+                &mut output,
+            )
+            .unwrap();
+        assert_eq!(entry.size, 20000);
+        assert_eq!(
+            output,
+            fs::read(root.join("b/nested/deeper/two.bin")).unwrap()
+        );
+
+        let mut output = vec![];
+        assert!(
+            encoder
+                .decode_file(&mut shard_readers(&shards, &[0]), "b/nested", &mut output)
+                .is_err()
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn rejects_implausible_manifest_lengths() {
+        let encoder = ReedSolomonEncoder::new(4, 2, 1000);
+
+        for prefix in [u64::MAX, 1 << 40, 100] {
+            let mut data = prefix.to_be_bytes().to_vec();
+            data.resize(50, 0);
+
+            let mut writers: Vec<Cursor<Vec<u8>>> = (0..6).map(|_| Cursor::new(vec![])).collect();
+            encoder
+                .encode(&mut Cursor::new(&data), data.len(), &mut writers)
+                .unwrap();
+            let shards: Vec<Vec<u8>> = writers.into_iter().map(Cursor::into_inner).collect();
+
+            let error = encoder
+                .decode_manifest(&mut shard_readers(&shards, &[0]))
+                .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+            let root = scratch_directory("archive-implausible");
+            let error = encoder
+                .decode_directory(&mut shard_readers(&shards, &[0]), &root)
+                .unwrap_err();
+            assert!(matches!(
+                error.kind(),
+                io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+            ));
+            fs::remove_dir_all(&root).unwrap();
+        }
+    }
+
+    #[test]
+    fn refuses_unsafe_paths() {
+        let root = Path::new("/tmp/root");
+        assert!(resolve(root, "a/b").is_ok());
+        assert!(resolve(root, "../a").is_err());
+        assert!(resolve(root, "/etc/passwd").is_err());
+        assert!(resolve(root, "a/../../b").is_err());
+        assert!(resolve(root, "").is_err());
+    }
+}
@@ -0,0 +1,592 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use xxhash_rust::xxh3::{Xxh3, xxh3_128};
+
+use crate::ReedSolomonEncoder;
+use crate::checksum::{Checksum, ChecksumKind};
+use crate::header::{SetId, SetInfo, ShardHeader, read_header};
+use crate::io::{read_chunk, seek_to_chunk};
+use crate::stripe::StripeEncoder;
+
+const MAGIC: [u8; 4] = *b"PRYC";
+const VERSION: u16 = 1;
+const CHECKPOINT_SIZE: usize = 64;
+const HASH_SIZE: usize = 16;
+
+/// How far an encode with checkpoints had got, recorded once everything written up to that point
+/// was made durable, so that the encode can be resumed from there after an interruption.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub set_id: SetId,
+    /// Length of the data being encoded.
+    pub length: usize,
+    /// Number of stripes written in full.
+    pub stripes: usize,
+    /// 64-bit xxh3 hash of the input encoded up to the checkpoint, which `resume_encode` checks
+    /// the input against.
+    pub input_hash: u64,
+}
+
+impl Checkpoint {
+    fn to_bytes(self) -> [u8; CHECKPOINT_SIZE] {
+        let mut bytes = [0u8; CHECKPOINT_SIZE];
+
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4..6].copy_from_slice(&VERSION.to_be_bytes());
+        bytes[8..24].copy_from_slice(self.set_id.as_bytes());
+        bytes[24..32].copy_from_slice(&(self.length as u64).to_be_bytes());
+        bytes[32..40].copy_from_slice(&(self.stripes as u64).to_be_bytes());
+        bytes[40..48].copy_from_slice(&self.input_hash.to_be_bytes());
+
+        let hash = xxh3_128(&bytes[..CHECKPOINT_SIZE - HASH_SIZE]);
+        bytes[CHECKPOINT_SIZE - HASH_SIZE..].copy_from_slice(&hash.to_be_bytes());
+
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; CHECKPOINT_SIZE]) -> Option<Checkpoint> {
+        let hash = u128::from_be_bytes(bytes[CHECKPOINT_SIZE - HASH_SIZE..].try_into().unwrap());
+
+        if hash != xxh3_128(&bytes[..CHECKPOINT_SIZE - HASH_SIZE])
+            || bytes[0..4] != MAGIC
+            || u16::from_be_bytes([bytes[4], bytes[5]]) != VERSION
+        {
+            return None;
+        }
+
+        Some(Checkpoint {
+            set_id: SetId::from_bytes(bytes[8..24].try_into().unwrap()),
+            length: u64::from_be_bytes(bytes[24..32].try_into().unwrap()) as usize,
+            stripes: u64::from_be_bytes(bytes[32..40].try_into().unwrap()) as usize,
+            input_hash: u64::from_be_bytes(bytes[40..48].try_into().unwrap()),
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Checkpoint> {
+        let mut bytes = [0u8; CHECKPOINT_SIZE];
+        reader.read_exact(&mut bytes)?;
+
+        Checkpoint::from_bytes(&bytes)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid checkpoint"))
+    }
+}
+
+/// Hashes everything read through it, so that checkpoints can identify the input they were
+/// taken of.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Xxh3,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> HashingReader<R> {
+        HashingReader {
+            inner,
+            hasher: Xxh3::new(),
+        }
+    }
+
+    /// Reads and hashes the next `length` bytes, failing if the input ends before them.
+    fn skip(&mut self, length: usize) -> io::Result<()> {
+        let skipped = io::copy(&mut self.take(length as u64), &mut io::sink())?;
+        if skipped != length as u64 {
+            return Result::Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        Result::Ok(())
+    }
+}
+
+impl<R: Read + Seek> HashingReader<R> {
+    /// Reads the next `block.len()` bytes and hashes them if they match `block`, or seeks back to
+    /// them otherwise, returning whether they matched.
+    fn skip_matching(&mut self, block: &[u8], buffer: &mut [u8]) -> io::Result<bool> {
+        let buffer = &mut buffer[..block.len()];
+        let start = self.inner.stream_position()?;
+
+        match self.inner.read_exact(buffer) {
+            Result::Ok(()) if buffer == block => {
+                self.hasher.update(buffer);
+                Result::Ok(true)
+            }
+            Result::Ok(()) => {
+                self.inner.seek(SeekFrom::Start(start))?;
+                Result::Ok(false)
+            }
+            Result::Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                self.inner.seek(SeekFrom::Start(start))?;
+                Result::Ok(false)
+            }
+            Result::Err(error) => Result::Err(error),
+        }
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buffer)?;
+        self.hasher.update(&buffer[..count]);
+        Result::Ok(count)
+    }
+}
+
+/// Returns whether every shard holds a valid chunk for `stripe`, reading the data chunks into
+/// `block` and the parity chunks into `chunk`.
+fn stripe_is_complete<S: Read + Seek>(
+    shards: &mut [S],
+    encoder: &ReedSolomonEncoder,
+    checksum: Checksum,
+    stripe: usize,
+    block: &mut [u8],
+    chunk: &mut [u8],
+) -> io::Result<bool> {
+    let mut data_chunks = block.chunks_mut(encoder.chunk_size);
+
+    for (shard, reader) in shards.iter_mut().enumerate() {
+        seek_to_chunk(reader, &encoder.layout(), stripe)?;
+
+        let chunk = match data_chunks.next() {
+            Some(data_chunk) => data_chunk,
+            None => &mut *chunk,
+        };
+        if read_chunk(reader, checksum, shard, stripe, chunk).is_err() {
+            return Result::Ok(false);
+        }
+    }
+
+    Result::Ok(true)
+}
+
+impl ReedSolomonEncoder {
+    /// Encodes `length` bytes read from `data` like `encode`, and records a checkpoint every
+    /// `interval` stripes through `on_checkpoint`, after flushing the shard writers. It has to
+    /// make the shards durable before storing the checkpoint, such as through
+    /// `ShardSink::checkpoints`, for `resume_encode` to rely on it.
+    pub fn encode_with_checkpoints<R: Read, W: Write>(
+        &self,
+        data: &mut R,
+        length: usize,
+        shard_writers: &mut [W],
+        interval: usize,
+        mut on_checkpoint: impl FnMut(Checkpoint) -> io::Result<()>,
+    ) -> io::Result<()> {
+        assert_eq!(shard_writers.len(), self.data_shards + self.parity_shards);
+        assert!(
+            interval > 0,
+            "Checkpoint interval must be at least one stripe"
+        );
+
+        let set = SetInfo {
+            set_id: self.new_set_id()?,
+            length,
+            framing: None,
+            sparse: false,
+            streamed: false,
+        };
+        self.write_headers(shard_writers, set)?;
+
+        let mut encoder = StripeEncoder::new(self, self.chunk_checksum(&set.set_id)?)
+            .with_monitor(self.monitor.start(Some(length)));
+        self.encode_checkpointed(
+            &mut encoder,
+            &mut HashingReader::new(data),
+            set,
+            shard_writers,
+            interval,
+            &mut on_checkpoint,
+        )
+    }
+
+    /// Continues an `encode_with_checkpoints` that was interrupted after recording `checkpoint`,
+    /// given the same input `data` from its start and the shards written so far. The input is
+    /// read again up to where encoding resumes, and refused if what was encoded up to the
+    /// checkpoint differs from it, so that shards of another input are not completed. The stripes
+    /// past the checkpoint that every shard already holds valid chunks of, and whose data still
+    /// matches the input, are kept, and encoding resumes with the first one that any shard lacks
+    /// or that differs, returning the input offset it resumed from. Without chunk checksums,
+    /// chunks past the checkpoint cannot be told apart from ones that were never made durable,
+    /// so encoding resumes at the checkpoint. Encrypted shard sets cannot be resumed, as that
+    /// might reuse nonces.
+    pub fn resume_encode<R: Read + Seek, S: Read + Write + Seek>(
+        &self,
+        data: &mut R,
+        checkpoint: &Checkpoint,
+        shards: &mut [S],
+        interval: usize,
+        mut on_checkpoint: impl FnMut(Checkpoint) -> io::Result<()>,
+    ) -> io::Result<usize> {
+        assert_eq!(shards.len(), self.data_shards + self.parity_shards);
+        assert!(
+            interval > 0,
+            "Checkpoint interval must be at least one stripe"
+        );
+        self.check_rewritable()?;
+
+        let set = SetInfo {
+            set_id: checkpoint.set_id,
+            length: checkpoint.length,
+            framing: None,
+            sparse: false,
+            streamed: false,
+        };
+
+        for (shard, file) in shards.iter_mut().enumerate() {
+            file.seek(SeekFrom::Start(0))?;
+
+            let expected = ShardHeader {
+                shard,
+                data_shards: self.data_shards,
+                parity_shards: self.parity_shards,
+                local_parity_shards: 0,
+                chunk_size: self.chunk_size,
+                checksum: self.checksum,
+                set,
+            };
+
+            if read_header(file, &self.key).ok() != Some(expected) {
+                return Result::Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Shard {} does not belong to the checkpointed shard set",
+                        shard
+                    ),
+                ));
+            }
+        }
+
+        let layout = self.layout();
+        let checksum = self.chunk_checksum(&set.set_id)?;
+        let stripes = layout.stripes(set.length);
+        let mut block = vec![0u8; layout.block_size()];
+        let mut input = vec![0u8; layout.block_size()];
+        let mut chunk = vec![0u8; self.chunk_size];
+
+        if checkpoint.stripes > stripes
+            || checkpoint.stripes > 0
+                && !stripe_is_complete(
+                    shards,
+                    self,
+                    checksum,
+                    checkpoint.stripes - 1,
+                    &mut block,
+                    &mut chunk,
+                )?
+        {
+            return Result::Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The shards do not hold the stripes recorded in the checkpoint",
+            ));
+        }
+
+        data.seek(SeekFrom::Start(0))?;
+        let mut data = HashingReader::new(data);
+        let checkpoint_offset = layout.stripe_start(checkpoint.stripes).min(set.length);
+        if data.skip(checkpoint_offset).is_err() || data.hasher.digest() != checkpoint.input_hash {
+            return Result::Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The input differs from the one encoded up to the checkpoint",
+            ));
+        }
+
+        // The stripes past the checkpoint are only kept as long as their data still matches the
+        // input, which may have changed after the checkpoint.
+        let mut first_stripe = checkpoint.stripes;
+        while self.checksum != ChecksumKind::None
+            && first_stripe < stripes
+            && stripe_is_complete(shards, self, checksum, first_stripe, &mut block, &mut chunk)?
+        {
+            let start = layout.stripe_start(first_stripe);
+            let count = layout.block_size().min(set.length - start);
+            if !data.skip_matching(&block[..count], &mut input)? {
+                break;
+            }
+
+            first_stripe += 1;
+        }
+
+        let offset = layout.stripe_start(first_stripe).min(set.length);
+        for shard in shards.iter_mut() {
+            seek_to_chunk(shard, &layout, first_stripe)?;
+        }
+
+        let mut encoder =
+            StripeEncoder::new(self, checksum).with_monitor(self.monitor.start(Some(set.length)));
+        encoder.seek_to_stripe(first_stripe);
+        self.encode_checkpointed(
+            &mut encoder,
+            &mut data,
+            set,
+            shards,
+            interval,
+            &mut on_checkpoint,
+        )?;
+
+        for shard in shards.iter_mut() {
+            shard.flush()?;
+        }
+
+        Result::Ok(offset)
+    }
+
+    /// Encodes the stripes of `set` from the current one of `encoder` on, pausing every
+    /// `interval` stripes to flush the shard writers and record a checkpoint.
+    fn encode_checkpointed<R: Read, W: Write>(
+        &self,
+        encoder: &mut StripeEncoder,
+        data: &mut HashingReader<R>,
+        set: SetInfo,
+        shard_writers: &mut [W],
+        interval: usize,
+        on_checkpoint: &mut impl FnMut(Checkpoint) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let layout = self.layout();
+        let stripes = layout.stripes(set.length);
+
+        while encoder.stripe() < stripes {
+            let start = layout.stripe_start(encoder.stripe());
+            let end = stripes.min(encoder.stripe().saturating_add(interval));
+            let count = layout.stripe_start(end).min(set.length) - start;
+
+            encoder.encode_stripes(data, 0, count, shard_writers)?;
+
+            if encoder.stripe() < stripes {
+                for writer in shard_writers.iter_mut() {
+                    writer.flush()?;
+                }
+
+                on_checkpoint(Checkpoint {
+                    set_id: set.set_id,
+                    length: set.length,
+                    stripes: encoder.stripe(),
+                    input_hash: data.hasher.digest(),
+                })?;
+            }
+        }
+
+        Result::Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trip() {
+        let checkpoint = Checkpoint {
+            set_id: SetId::random(),
+            length: 123456789,
+            stripes: 42,
+            input_hash: 0x0123456789abcdef,
+        };
+
+        let mut bytes = vec![];
+        checkpoint.write_to(&mut bytes).unwrap();
+        assert_eq!(
+            Checkpoint::read_from(&mut Cursor::new(&bytes)).unwrap(),
+            checkpoint
+        );
+
+        bytes[30] ^= 1;
+        assert!(Checkpoint::read_from(&mut Cursor::new(&bytes)).is_err());
+        assert!(Checkpoint::read_from(&mut Cursor::new(&bytes[..20])).is_err());
+    }
+
+    #[test]
+    fn resume() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024).with_set_id(SetId::random());
+        let mut buffer = vec![0u8; 20 * 4096 + 100];
+        rng.fill_bytes(&mut buffer);
+
+        let mut expected: Vec<Cursor<Vec<u8>>> = (0..6).map(|_| Cursor::new(vec![])).collect();
+        encoder
+            .encode(&mut Cursor::new(&buffer), buffer.len(), &mut expected)
+            .unwrap();
+
+        let mut writers: Vec<Cursor<Vec<u8>>> = (0..6).map(|_| Cursor::new(vec![])).collect();
+        let mut checkpoints = vec![];
+        encoder
+            .encode_with_checkpoints(
+                &mut Cursor::new(&buffer),
+                buffer.len(),
+                &mut writers,
+                5,
+                |checkpoint| {
+                    checkpoints.push(checkpoint);
+                    Result::Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            checkpoints
+                .iter()
+                .map(|checkpoint| checkpoint.stripes)
+                .collect::<Vec<_>>(),
+            vec![5, 10, 15, 20]
+        );
+        for (shard, expected) in writers.iter().zip(&expected) {
+            assert!(shard.get_ref() == expected.get_ref());
+        }
+
+        // The shards of an encode interrupted after the checkpoint at stripe 10, which got
+        // further on some shards than others. Shard 2 stops in the middle of stripe 13.
+        let layout = encoder.layout();
+        let interrupted: Vec<Vec<u8>> = [14, 15, 13, 17, 21, 16]
+            .iter()
+            .zip(&expected)
+            .enumerate()
+            .map(|(shard, (&stripe, contents))| {
+                let end = layout.chunk_offset(stripe) as usize + if shard == 2 { 500 } else { 0 };
+                contents.get_ref()[..end].to_vec()
+            })
+            .collect();
+
+        let mut shards: Vec<Cursor<Vec<u8>>> =
+            interrupted.iter().cloned().map(Cursor::new).collect();
+        let mut resumed_checkpoints = vec![];
+        let offset = encoder
+            .resume_encode(
+                &mut Cursor::new(&buffer),
+                &checkpoints[1],
+                &mut shards,
+                5,
+                |checkpoint| {
+                    resumed_checkpoints.push(checkpoint.stripes);
+                    Result::Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!(offset, 13 * 4096);
+        assert_eq!(resumed_checkpoints, vec![18]);
+        for (shard, expected) in shards.iter().zip(&expected) {
+            assert!(shard.get_ref() == expected.get_ref());
+        }
+
+        let other = Checkpoint {
+            set_id: SetId::random(),
+            ..checkpoints[1]
+        };
+        let mut shards: Vec<Cursor<Vec<u8>>> =
+            interrupted.iter().cloned().map(Cursor::new).collect();
+        let result =
+            encoder.resume_encode(&mut Cursor::new(&buffer), &other, &mut shards, 5, |_| {
+                Result::Ok(())
+            });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        // An input that changed after the checkpoint is encoded again from where it differs.
+        let mut modified = buffer.clone();
+        modified[12 * 4096 + 7] ^= 1;
+        let mut shards: Vec<Cursor<Vec<u8>>> =
+            interrupted.iter().cloned().map(Cursor::new).collect();
+        let offset = encoder
+            .resume_encode(
+                &mut Cursor::new(&modified),
+                &checkpoints[1],
+                &mut shards,
+                5,
+                |_| Result::Ok(()),
+            )
+            .unwrap();
+        assert_eq!(offset, 12 * 4096);
+
+        let mut readers: Vec<Option<Cursor<Vec<u8>>>> = shards
+            .iter()
+            .map(|shard| Some(Cursor::new(shard.get_ref().clone())))
+            .collect();
+        let mut output = vec![];
+        encoder.decode(&mut readers, &mut output).unwrap();
+        assert!(output == modified);
+
+        // An input that changed before the checkpoint is refused.
+        let mut modified = buffer.clone();
+        modified[5 * 4096 + 7] ^= 1;
+        let mut shards: Vec<Cursor<Vec<u8>>> =
+            interrupted.iter().cloned().map(Cursor::new).collect();
+        let result = encoder.resume_encode(
+            &mut Cursor::new(&modified),
+            &checkpoints[1],
+            &mut shards,
+            5,
+            |_| Result::Ok(()),
+        );
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(
+            shards
+                .iter()
+                .zip(&interrupted)
+                .all(|(shard, interrupted)| shard.get_ref() == interrupted)
+        );
+
+        let mut shards: Vec<Cursor<Vec<u8>>> =
+            interrupted.iter().cloned().map(Cursor::new).collect();
+        shards[1].get_mut()[layout.chunk_offset(9) as usize + 40] ^= 1;
+        let result = encoder.resume_encode(
+            &mut Cursor::new(&buffer),
+            &checkpoints[1],
+            &mut shards,
+            5,
+            |_| Result::Ok(()),
+        );
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn resume_without_checksums() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024)
+            .with_checksum(ChecksumKind::None)
+            .with_set_id(SetId::random());
+        let mut buffer = vec![0u8; 20 * 4096 + 100];
+        rng.fill_bytes(&mut buffer);
+
+        let mut expected: Vec<Cursor<Vec<u8>>> = (0..6).map(|_| Cursor::new(vec![])).collect();
+        let mut checkpoints = vec![];
+        encoder
+            .encode_with_checkpoints(
+                &mut Cursor::new(&buffer),
+                buffer.len(),
+                &mut expected,
+                5,
+                |checkpoint| {
+                    checkpoints.push(checkpoint);
+                    Result::Ok(())
+                },
+            )
+            .unwrap();
+
+        // The chunks written past the checkpoint at stripe 10 were lost, leaving zeros that look
+        // as valid as any other chunk.
+        let layout = encoder.layout();
+        let mut shards: Vec<Cursor<Vec<u8>>> = expected
+            .iter()
+            .map(|contents| {
+                let mut contents = contents.get_ref()[..layout.chunk_offset(14) as usize].to_vec();
+                contents[layout.chunk_offset(10) as usize..].fill(0);
+                Cursor::new(contents)
+            })
+            .collect();
+
+        let offset = encoder
+            .resume_encode(
+                &mut Cursor::new(&buffer),
+                &checkpoints[1],
+                &mut shards,
+                5,
+                |_| Result::Ok(()),
+            )
+            .unwrap();
+        assert_eq!(offset, 10 * 4096);
+        for (shard, expected) in shards.iter().zip(&expected) {
+            assert!(shard.get_ref() == expected.get_ref());
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+use xxhash_rust::xxh3::{xxh3_64, xxh3_128};
+
+use crate::encryption;
+use crate::encryption::{KeyProvider, TAG_SIZE};
+use crate::header::SetId;
+
+pub(crate) const MAX_CHECKSUM_SIZE: usize = 32;
+
+/// Size in bytes of the key used by keyed checksums and encryption.
+pub const KEY_SIZE: usize = 32;
+
+/// Algorithm used for the checksum stored in front of every chunk.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumKind {
+    /// No checksum; corrupted chunks go undetected.
+    None,
+    /// 4-byte CRC32C, hardware accelerated where available.
+    Crc32c,
+    /// 8-byte 64-bit xxh3.
+    Xxh3_64,
+    /// 16-byte 128-bit xxh3.
+    #[default]
+    Xxh3_128,
+    /// 32-byte BLAKE3 hash.
+    Blake3,
+    /// 32-byte keyed BLAKE3 MAC over the set ID, shard index, stripe number and chunk. Unlike
+    /// the other checksums it also detects deliberate tampering by anyone who lacks the key.
+    KeyedBlake3,
+    /// 16-byte Poly1305 tag of a chunk encrypted with XChaCha20-Poly1305.
+    XChaCha20Poly1305,
+}
+
+impl ChecksumKind {
+    pub fn size(self) -> usize {
+        match self {
+            ChecksumKind::None => 0,
+            ChecksumKind::Crc32c => 4,
+            ChecksumKind::Xxh3_64 => 8,
+            ChecksumKind::Xxh3_128 => 16,
+            ChecksumKind::Blake3 | ChecksumKind::KeyedBlake3 => 32,
+            ChecksumKind::XChaCha20Poly1305 => TAG_SIZE,
+        }
+    }
+
+    pub fn is_keyed(self) -> bool {
+        matches!(
+            self,
+            ChecksumKind::KeyedBlake3 | ChecksumKind::XChaCha20Poly1305
+        )
+    }
+
+    pub fn is_encrypted(self) -> bool {
+        self == ChecksumKind::XChaCha20Poly1305
+    }
+
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            ChecksumKind::None => 0,
+            ChecksumKind::Crc32c => 1,
+            ChecksumKind::Xxh3_64 => 2,
+            ChecksumKind::Xxh3_128 => 3,
+            ChecksumKind::Blake3 => 4,
+            ChecksumKind::KeyedBlake3 => 5,
+            ChecksumKind::XChaCha20Poly1305 => 6,
+        }
+    }
+
+    pub(crate) fn from_byte(byte: u8) -> Option<ChecksumKind> {
+        match byte {
+            0 => Some(ChecksumKind::None),
+            1 => Some(ChecksumKind::Crc32c),
+            2 => Some(ChecksumKind::Xxh3_64),
+            3 => Some(ChecksumKind::Xxh3_128),
+            4 => Some(ChecksumKind::Blake3),
+            5 => Some(ChecksumKind::KeyedBlake3),
+            6 => Some(ChecksumKind::XChaCha20Poly1305),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ChecksumKind {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ChecksumKind::None => "none",
+            ChecksumKind::Crc32c => "crc32c",
+            ChecksumKind::Xxh3_64 => "xxh3-64",
+            ChecksumKind::Xxh3_128 => "xxh3-128",
+            ChecksumKind::Blake3 => "blake3",
+            ChecksumKind::KeyedBlake3 => "blake3-keyed",
+            ChecksumKind::XChaCha20Poly1305 => "xchacha20-poly1305",
+        };
+
+        write!(formatter, "{}", name)
+    }
+}
+
+impl FromStr for ChecksumKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<ChecksumKind, String> {
+        match name {
+            "none" => Result::Ok(ChecksumKind::None),
+            "crc32c" => Result::Ok(ChecksumKind::Crc32c),
+            "xxh3-64" => Result::Ok(ChecksumKind::Xxh3_64),
+            "xxh3-128" => Result::Ok(ChecksumKind::Xxh3_128),
+            "blake3" => Result::Ok(ChecksumKind::Blake3),
+            "blake3-keyed" => Result::Ok(ChecksumKind::KeyedBlake3),
+            "xchacha20-poly1305" => Result::Ok(ChecksumKind::XChaCha20Poly1305),
+            _ => Result::Err(format!(
+                "Unknown checksum {:?}, expected one of none, crc32c, xxh3-64, xxh3-128, blake3, blake3-keyed, xchacha20-poly1305",
+                name
+            )),
+        }
+    }
+}
+
+/// Source of the key for keyed checksums and encryption.
+#[derive(Clone, Default)]
+pub(crate) enum ChunkKey {
+    #[default]
+    None,
+    Mac([u8; KEY_SIZE]),
+    Cipher(Arc<dyn KeyProvider>),
+}
+
+impl ChunkKey {
+    /// Resolves the key of the shard set `set_id` and pairs it with `kind`.
+    pub fn checksum(&self, kind: ChecksumKind, set_id: &SetId) -> io::Result<Checksum> {
+        let key = match self {
+            ChunkKey::None if kind.is_keyed() => {
+                return Result::Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} checksums need a key", kind),
+                ));
+            }
+            ChunkKey::None => None,
+            ChunkKey::Mac(key) => Some(*key),
+            ChunkKey::Cipher(provider) => Some(provider.key(set_id)?),
+        };
+
+        Result::Ok(Checksum::new(kind, key, *set_id))
+    }
+}
+
+/// A checksum algorithm together with its key, if it is keyed, and the ID of the shard set it
+/// protects, which encrypted chunks derive their nonces from.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Checksum {
+    kind: ChecksumKind,
+    key: [u8; KEY_SIZE],
+    set_id: SetId,
+}
+
+impl Checksum {
+    pub fn new(kind: ChecksumKind, key: Option<[u8; KEY_SIZE]>, set_id: SetId) -> Checksum {
+        assert!(
+            key.is_some() == kind.is_keyed(),
+            "A key must be given for keyed checksums, and only for keyed checksums"
+        );
+
+        Checksum {
+            kind,
+            key: key.unwrap_or_default(),
+            set_id,
+        }
+    }
+
+    pub fn kind(self) -> ChecksumKind {
+        self.kind
+    }
+
+    pub fn size(self) -> usize {
+        self.kind.size()
+    }
+
+    /// Computes the checksum of `chunk`, stored in stripe `stripe` of shard `shard`, into
+    /// `checksum`, which must be `size()` bytes long. Only keyed checksums depend on the
+    /// position of the chunk, so that a chunk cannot be moved to another shard, stripe or shard
+    /// set encoded under the same key.
+    pub fn compute(self, shard: usize, stripe: usize, chunk: &[u8], checksum: &mut [u8]) {
+        match self.kind {
+            ChecksumKind::XChaCha20Poly1305 => {
+                unreachable!("Encrypted chunks are authenticated by encrypt and decrypt")
+            }
+            ChecksumKind::None => {}
+            ChecksumKind::Crc32c => {
+                checksum.copy_from_slice(&crc32c::crc32c(chunk).to_be_bytes());
+            }
+            ChecksumKind::Xxh3_64 => checksum.copy_from_slice(&xxh3_64(chunk).to_be_bytes()),
+            ChecksumKind::Xxh3_128 => checksum.copy_from_slice(&xxh3_128(chunk).to_be_bytes()),
+            ChecksumKind::Blake3 => checksum.copy_from_slice(blake3::hash(chunk).as_bytes()),
+            ChecksumKind::KeyedBlake3 => {
+                let mut hasher = blake3::Hasher::new_keyed(&self.key);
+                hasher.update(self.set_id.as_bytes());
+                hasher.update(&(shard as u64).to_be_bytes());
+                hasher.update(&(stripe as u64).to_be_bytes());
+                hasher.update(chunk);
+                checksum.copy_from_slice(hasher.finalize().as_bytes());
+            }
+        }
+    }
+
+    /// Computes the MAC of the `bytes` of a shard header under a key derived from the key of
+    /// the set, for keyed checksums only.
+    pub fn authenticate_header(self, bytes: &[u8]) -> [u8; 16] {
+        let key = blake3::derive_key("parry shard header", &self.key);
+        let mut mac = [0u8; 16];
+        mac.copy_from_slice(&blake3::keyed_hash(&key, bytes).as_bytes()[..16]);
+        mac
+    }
+
+    /// Encrypts `chunk`, stored in stripe `stripe` of shard `shard`, in place and stores its tag
+    /// in `tag`, which must be `size()` bytes long.
+    pub fn encrypt(self, shard: usize, stripe: usize, chunk: &mut [u8], tag: &mut [u8]) {
+        encryption::encrypt(&self.key, &self.set_id, shard, stripe, chunk, tag);
+    }
+
+    /// Authenticates and decrypts `chunk` in place, returning false if `tag` does not match.
+    pub fn decrypt(self, shard: usize, stripe: usize, chunk: &mut [u8], tag: &[u8]) -> bool {
+        encryption::decrypt(&self.key, &self.set_id, shard, stripe, chunk, tag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_bytes() {
+        for kind in [
+            ChecksumKind::None,
+            ChecksumKind::Crc32c,
+            ChecksumKind::Xxh3_64,
+            ChecksumKind::Xxh3_128,
+            ChecksumKind::Blake3,
+            ChecksumKind::KeyedBlake3,
+            ChecksumKind::XChaCha20Poly1305,
+        ] {
+            assert_eq!(kind.to_string().parse::<ChecksumKind>(), Result::Ok(kind));
+            assert_eq!(ChecksumKind::from_byte(kind.to_byte()), Some(kind));
+        }
+
+        assert!("md5".parse::<ChecksumKind>().is_err());
+        assert_eq!(ChecksumKind::from_byte(7), None);
+    }
+
+    #[test]
+    fn keyed_checksum_is_bound_to_key_set_and_position() {
+        let chunk = [7u8; 100];
+        let checksum = Checksum::new(
+            ChecksumKind::KeyedBlake3,
+            Some([1u8; KEY_SIZE]),
+            SetId::default(),
+        );
+
+        let tag = |checksum: Checksum, shard, stripe| {
+            let mut tag = [0u8; 32];
+            checksum.compute(shard, stripe, &chunk, &mut tag);
+            tag
+        };
+
+        let expected = tag(checksum, 2, 3);
+        assert_eq!(tag(checksum, 2, 3), expected);
+        assert_ne!(tag(checksum, 3, 3), expected);
+        assert_ne!(tag(checksum, 2, 4), expected);
+
+        let other_key = Checksum::new(
+            ChecksumKind::KeyedBlake3,
+            Some([2u8; KEY_SIZE]),
+            SetId::default(),
+        );
+        assert_ne!(tag(other_key, 2, 3), expected);
+
+        let other_set = Checksum::new(
+            ChecksumKind::KeyedBlake3,
+            Some([1u8; KEY_SIZE]),
+            SetId::random(),
+        );
+        assert_ne!(tag(other_set, 2, 3), expected);
+    }
+}
@@ -0,0 +1,486 @@
+//! Compressed shard sets encode a payload of independently compressed frames instead of the data
+//! itself. Every frame but the last holds `frame_size` bytes of data, so the frames covering any
+//! range of the data can be located through a seek table at the end of the payload:
+//!
+//! - for each frame, its compressed size as a u32 followed by the compressed bytes
+//! - a u32 zero marking the end of the frames
+//! - the offset of each frame within the payload as a u64
+//! - the uncompressed data length as a u64
+//! - the number of frames as a u64
+//!
+//! All integers are big-endian.
+
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+const TRAILER_SIZE: usize = 16;
+
+pub const DEFAULT_FRAME_SIZE: usize = 1 << 20;
+
+/// Compression algorithm applied to each frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CompressionKind {
+    Zstd,
+    Lz4,
+}
+
+impl CompressionKind {
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            CompressionKind::Zstd => 1,
+            CompressionKind::Lz4 => 2,
+        }
+    }
+
+    pub(crate) fn from_byte(byte: u8) -> Option<CompressionKind> {
+        match byte {
+            1 => Some(CompressionKind::Zstd),
+            2 => Some(CompressionKind::Lz4),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CompressionKind {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            CompressionKind::Zstd => "zstd",
+            CompressionKind::Lz4 => "lz4",
+        };
+
+        write!(formatter, "{}", name)
+    }
+}
+
+impl FromStr for CompressionKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<CompressionKind, String> {
+        match name {
+            "zstd" => Result::Ok(CompressionKind::Zstd),
+            "lz4" => Result::Ok(CompressionKind::Lz4),
+            _ => Result::Err(format!(
+                "Unknown compression {:?}, expected one of zstd, lz4",
+                name
+            )),
+        }
+    }
+}
+
+/// Settings for compressing data ahead of encoding it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Compression {
+    kind: CompressionKind,
+    level: i32,
+    frame_size: usize,
+}
+
+impl Compression {
+    pub fn new(kind: CompressionKind) -> Compression {
+        Compression {
+            kind,
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            frame_size: DEFAULT_FRAME_SIZE,
+        }
+    }
+
+    /// Sets the zstd compression level. lz4 has no levels and ignores it.
+    pub fn with_level(self, level: i32) -> Compression {
+        Compression { level, ..self }
+    }
+
+    /// Sets the amount of data compressed into each frame. Smaller frames make random access
+    /// cheaper at the expense of the compression ratio.
+    pub fn with_frame_size(self, frame_size: usize) -> Compression {
+        assert!(frame_size > 0, "Frame size must be greater than zero");
+
+        assert!(
+            frame_size <= u32::MAX as usize,
+            "Frame size cannot exceed {} bytes",
+            u32::MAX
+        );
+
+        Compression { frame_size, ..self }
+    }
+
+    pub fn kind(&self) -> CompressionKind {
+        self.kind
+    }
+
+    pub fn level(&self) -> i32 {
+        self.level
+    }
+
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    pub(crate) fn framing(&self) -> Framing {
+        Framing {
+            compression: self.kind,
+            frame_size: self.frame_size,
+        }
+    }
+
+    fn compress(&self, frame: &[u8]) -> io::Result<Vec<u8>> {
+        match self.kind {
+            CompressionKind::Zstd => zstd::bulk::compress(frame, self.level),
+            CompressionKind::Lz4 => Result::Ok(lz4_flex::block::compress_prepend_size(frame)),
+        }
+    }
+}
+
+/// What decoders need to know about the frames of a compressed shard set, as recorded in the
+/// shard headers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Framing {
+    pub compression: CompressionKind,
+    pub frame_size: usize,
+}
+
+impl Framing {
+    /// Returns the largest a frame of `frame_size` bytes can be once compressed, which bounds the
+    /// compressed frame sizes recorded in the payload.
+    fn max_compressed_size(&self) -> usize {
+        match self.compression {
+            CompressionKind::Zstd => zstd::zstd_safe::compress_bound(self.frame_size),
+            CompressionKind::Lz4 => 4 + lz4_flex::block::get_maximum_output_size(self.frame_size),
+        }
+    }
+
+    fn decompress(&self, frame: &[u8]) -> io::Result<Vec<u8>> {
+        let decompressed = match self.compression {
+            CompressionKind::Zstd => zstd::bulk::decompress(frame, self.frame_size)?,
+            CompressionKind::Lz4 => {
+                // The size lz4 prepends is checked before it is trusted with an allocation.
+                let size = read_le_u32(&mut &frame[..])? as usize;
+                if size > self.frame_size {
+                    return Result::Err(corrupted("Frame is larger than the frame size"));
+                }
+
+                lz4_flex::block::decompress(&frame[4..], size)
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?
+            }
+        };
+
+        if decompressed.len() > self.frame_size {
+            return Result::Err(corrupted("Frame is larger than the frame size"));
+        }
+
+        Result::Ok(decompressed)
+    }
+}
+
+fn corrupted(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Result::Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_le_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Result::Ok(u32::from_le_bytes(bytes))
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> usize {
+    u64::from_be_bytes(bytes[offset..offset + 8].try_into().unwrap()) as usize
+}
+
+/// Presents `length` bytes read from `data` as the payload of a compressed shard set.
+pub(crate) struct CompressingReader<'a, R: Read> {
+    data: &'a mut R,
+    compression: Compression,
+    length: usize,
+    remaining: usize,
+    offsets: Vec<u64>,
+    position: u64,
+    frame: Vec<u8>,
+    buffer: Vec<u8>,
+    consumed: usize,
+    finished: bool,
+}
+
+impl<'a, R: Read> CompressingReader<'a, R> {
+    pub fn new(
+        data: &'a mut R,
+        compression: Compression,
+        length: usize,
+    ) -> CompressingReader<'a, R> {
+        CompressingReader {
+            data,
+            compression,
+            length,
+            remaining: length,
+            offsets: vec![],
+            position: 0,
+            frame: vec![],
+            buffer: vec![],
+            consumed: 0,
+            finished: false,
+        }
+    }
+
+    fn refill(&mut self) -> io::Result<()> {
+        self.buffer.clear();
+        self.consumed = 0;
+
+        if self.remaining > 0 {
+            let count = self.remaining.min(self.compression.frame_size);
+            self.frame.resize(count, 0);
+            self.data.read_exact(&mut self.frame)?;
+            self.remaining -= count;
+
+            let compressed = self.compression.compress(&self.frame)?;
+            self.offsets.push(self.position);
+            self.buffer
+                .extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+            self.buffer.extend_from_slice(&compressed);
+        } else if !self.finished {
+            self.buffer.extend_from_slice(&0u32.to_be_bytes());
+            for offset in self.offsets.iter() {
+                self.buffer.extend_from_slice(&offset.to_be_bytes());
+            }
+            self.buffer
+                .extend_from_slice(&(self.length as u64).to_be_bytes());
+            self.buffer
+                .extend_from_slice(&(self.offsets.len() as u64).to_be_bytes());
+            self.finished = true;
+        }
+
+        self.position += self.buffer.len() as u64;
+
+        Result::Ok(())
+    }
+}
+
+impl<R: Read> Read for CompressingReader<'_, R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.consumed == self.buffer.len() {
+            self.refill()?;
+        }
+
+        let count = buffer.len().min(self.buffer.len() - self.consumed);
+        buffer[..count].copy_from_slice(&self.buffer[self.consumed..self.consumed + count]);
+        self.consumed += count;
+
+        Result::Ok(count)
+    }
+}
+
+/// Decompresses every frame of the payload read from `payload` into `output`, then checks the
+/// amount of data against the seek table trailer.
+pub(crate) fn decompress<R: Read, W: Write>(
+    framing: Framing,
+    payload: &mut R,
+    output: &mut W,
+) -> io::Result<()> {
+    let mut frame = vec![];
+    let mut short_frame = false;
+    let mut frames = 0;
+    let mut data_length = 0;
+
+    loop {
+        let size = read_u32(payload)? as usize;
+        if size == 0 {
+            break;
+        }
+
+        if short_frame {
+            return Result::Err(corrupted("Only the final frame may be short"));
+        }
+
+        if size > framing.max_compressed_size() {
+            return Result::Err(corrupted("Frame is larger than the frame size allows"));
+        }
+
+        frame.resize(size, 0);
+        payload.read_exact(&mut frame)?;
+
+        let decompressed = framing.decompress(&frame)?;
+        short_frame = decompressed.len() < framing.frame_size;
+        frames += 1;
+        data_length += decompressed.len();
+        output.write_all(&decompressed)?;
+    }
+
+    io::copy(&mut payload.take(8 * frames as u64), &mut io::sink())?;
+    let mut trailer = [0u8; TRAILER_SIZE];
+    payload.read_exact(&mut trailer)?;
+
+    if u64_at(&trailer, 0) != data_length || u64_at(&trailer, 8) != frames {
+        return Result::Err(corrupted("Seek table does not match the decompressed data"));
+    }
+
+    Result::Ok(())
+}
+
+/// Writes `length` bytes of data starting at `offset` to `output`, reading only the parts of the
+/// `payload_length`-byte payload that hold the seek table and the covering frames through
+/// `read_payload`, which returns the given number of payload bytes at the given offset.
+pub(crate) fn decompress_at<F: FnMut(usize, usize) -> io::Result<Vec<u8>>, W: Write>(
+    framing: Framing,
+    payload_length: usize,
+    mut read_payload: F,
+    output: &mut W,
+    offset: usize,
+    length: usize,
+) -> io::Result<()> {
+    let trailer_start = payload_length
+        .checked_sub(TRAILER_SIZE)
+        .ok_or_else(|| corrupted("Payload is too short for a seek table"))?;
+
+    let trailer = read_payload(trailer_start, TRAILER_SIZE)?;
+    let data_length = u64_at(&trailer, 0);
+    let frames = u64_at(&trailer, 8);
+
+    if frames != data_length.div_ceil(framing.frame_size) {
+        return Result::Err(corrupted("Seek table does not match the data length"));
+    }
+
+    let table_start = frames
+        .checked_mul(8)
+        .and_then(|table_size| trailer_start.checked_sub(table_size))
+        .filter(|&table_start| table_start >= 4)
+        .ok_or_else(|| corrupted("Payload is too short for a seek table"))?;
+
+    if offset
+        .checked_add(length)
+        .is_none_or(|end| end > data_length)
+    {
+        return Result::Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Requested range extends past the end of the encoded data",
+        ));
+    }
+
+    if length == 0 {
+        return Result::Ok(());
+    }
+
+    let end = offset + length;
+    let first_frame = offset / framing.frame_size;
+    let last_frame = (end - 1) / framing.frame_size;
+
+    // Offsets of the covering frames, plus that of the frame after them, or the end marker.
+    let table_entries = (last_frame + 2).min(frames) - first_frame;
+    let table = read_payload(table_start + 8 * first_frame, 8 * table_entries)?;
+    let mut offsets: Vec<usize> = (0..table_entries).map(|i| u64_at(&table, 8 * i)).collect();
+    if offsets.len() == last_frame - first_frame + 1 {
+        offsets.push(table_start - 4);
+    }
+
+    if offsets.windows(2).any(|pair| pair[0] >= pair[1]) || offsets[offsets.len() - 1] > table_start
+    {
+        return Result::Err(corrupted("Seek table offsets are out of order"));
+    }
+
+    let span = read_payload(offsets[0], offsets[offsets.len() - 1] - offsets[0])?;
+    let mut span = &span[..];
+
+    for frame in first_frame..=last_frame {
+        let size = read_u32(&mut span)? as usize;
+        if size > span.len() {
+            return Result::Err(corrupted("Frame extends past the end of the payload"));
+        }
+
+        let (compressed, rest) = span.split_at(size);
+        span = rest;
+
+        let frame_start = frame * framing.frame_size;
+        let frame_end = data_length.min(frame_start + framing.frame_size);
+        let decompressed = framing.decompress(compressed)?;
+        if decompressed.len() != frame_end - frame_start {
+            return Result::Err(corrupted("Frame has the wrong size"));
+        }
+
+        let from = offset.max(frame_start) - frame_start;
+        let to = end.min(frame_end) - frame_start;
+        output.write_all(&decompressed[from..to])?;
+    }
+
+    Result::Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(data: &[u8], compression: Compression) -> Vec<u8> {
+        let mut payload = vec![];
+        CompressingReader::new(&mut &data[..], compression, data.len())
+            .read_to_end(&mut payload)
+            .unwrap();
+        payload
+    }
+
+    #[test]
+    fn seek_table() {
+        let data: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+        let compression = Compression::new(CompressionKind::Lz4).with_frame_size(1000);
+        let framing = compression.framing();
+        let payload = payload(&data, compression);
+
+        let mut output = vec![];
+        decompress(framing, &mut &payload[..], &mut output).unwrap();
+        assert!(output == data);
+
+        let read_payload =
+            |offset: usize, length: usize| Result::Ok(payload[offset..offset + length].to_vec());
+        let mut output = vec![];
+        decompress_at(
+            framing,
+            payload.len(),
+            read_payload,
+            &mut output,
+            1500,
+            3000,
+        )
+        .unwrap();
+        assert!(output == data[1500..4500]);
+
+        // Point the second frame at the start of the first.
+        let mut corrupted = payload.clone();
+        let table_start = payload.len() - TRAILER_SIZE - 8 * 10;
+        corrupted.copy_within(table_start..table_start + 8, table_start + 8);
+        let read_payload =
+            |offset: usize, length: usize| Result::Ok(corrupted[offset..offset + length].to_vec());
+        let error = decompress_at(framing, payload.len(), read_payload, &mut vec![], 0, 2000);
+        assert_eq!(error.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        // Record a smaller data length in the trailer.
+        let mut corrupted = payload.clone();
+        let trailer_start = payload.len() - TRAILER_SIZE;
+        corrupted[trailer_start..trailer_start + 8].copy_from_slice(&9999u64.to_be_bytes());
+        let error = decompress(framing, &mut &corrupted[..], &mut vec![]);
+        assert_eq!(error.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn oversized_frames() {
+        // An lz4 frame claiming to decompress to 4 GiB.
+        let framing = Compression::new(CompressionKind::Lz4)
+            .with_frame_size(1000)
+            .framing();
+        let mut frame = u32::MAX.to_le_bytes().to_vec();
+        frame.extend_from_slice(&[0u8; 16]);
+        assert_eq!(
+            framing.decompress(&frame).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        // A payload claiming a 4 GiB compressed frame.
+        for kind in [CompressionKind::Lz4, CompressionKind::Zstd] {
+            let framing = Compression::new(kind).with_frame_size(1000).framing();
+            let payload = u32::MAX.to_be_bytes();
+            let error = decompress(framing, &mut &payload[..], &mut vec![]);
+            assert_eq!(error.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
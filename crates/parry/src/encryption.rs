@@ -0,0 +1,115 @@
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{Key, Tag, XChaCha20Poly1305, XNonce};
+use std::io;
+
+use crate::checksum::KEY_SIZE;
+use crate::header::SetId;
+
+/// Size of the Poly1305 tag stored in place of the checksum of an encrypted chunk.
+pub(crate) const TAG_SIZE: usize = 16;
+
+/// Supplies the keys that shard sets are encrypted with. Each shard set is identified by the
+/// random set ID recorded in its headers, so a provider may hand out a distinct key per set.
+pub trait KeyProvider: Send + Sync {
+    fn key(&self, set_id: &SetId) -> io::Result<[u8; KEY_SIZE]>;
+}
+
+/// A single key used for every shard set.
+impl KeyProvider for [u8; KEY_SIZE] {
+    fn key(&self, _set_id: &SetId) -> io::Result<[u8; KEY_SIZE]> {
+        Result::Ok(*self)
+    }
+}
+
+/// Derives the nonce of a chunk from the set ID, shard index and stripe number, which are unique
+/// to every chunk ever written under a key as long as chunks are never rewritten in place.
+fn nonce(set_id: &SetId, shard: usize, stripe: usize) -> XNonce {
+    assert!(stripe < 1 << 48, "Stripe number exceeds the nonce space");
+
+    let mut nonce = [0u8; 24];
+    nonce[..16].copy_from_slice(set_id.as_bytes());
+    nonce[16..18].copy_from_slice(&(shard as u16).to_be_bytes());
+    nonce[18..].copy_from_slice(&(stripe as u64).to_be_bytes()[2..]);
+    XNonce::from(nonce)
+}
+
+/// Encrypts `chunk` in place, storing its authentication tag in `tag`.
+pub(crate) fn encrypt(
+    key: &[u8; KEY_SIZE],
+    set_id: &SetId,
+    shard: usize,
+    stripe: usize,
+    chunk: &mut [u8],
+    tag: &mut [u8],
+) {
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let computed_tag = cipher
+        .encrypt_in_place_detached(&nonce(set_id, shard, stripe), &[], chunk)
+        .expect("Chunks fit within the cipher's message size limit");
+
+    tag.copy_from_slice(&computed_tag);
+}
+
+/// Authenticates and decrypts `chunk` in place, returning false if `tag` does not match.
+pub(crate) fn decrypt(
+    key: &[u8; KEY_SIZE],
+    set_id: &SetId,
+    shard: usize,
+    stripe: usize,
+    chunk: &mut [u8],
+    tag: &[u8],
+) -> bool {
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    cipher
+        .decrypt_in_place_detached(
+            &nonce(set_id, shard, stripe),
+            &[],
+            chunk,
+            Tag::from_slice(tag),
+        )
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let key = [3u8; KEY_SIZE];
+        let set_id = SetId::random();
+        let plaintext = [7u8; 100];
+
+        let mut chunk = plaintext;
+        let mut tag = [0u8; TAG_SIZE];
+        encrypt(&key, &set_id, 2, 5, &mut chunk, &mut tag);
+        assert_ne!(chunk, plaintext);
+
+        let mut decrypted = chunk;
+        assert!(decrypt(&key, &set_id, 2, 5, &mut decrypted, &tag));
+        assert_eq!(decrypted, plaintext);
+
+        assert!(!decrypt(&key, &set_id, 3, 5, &mut chunk.clone(), &tag));
+        assert!(!decrypt(&key, &set_id, 2, 6, &mut chunk.clone(), &tag));
+        assert!(!decrypt(
+            &key,
+            &SetId::random(),
+            2,
+            5,
+            &mut chunk.clone(),
+            &tag
+        ));
+        assert!(!decrypt(
+            &[4u8; KEY_SIZE],
+            &set_id,
+            2,
+            5,
+            &mut chunk.clone(),
+            &tag
+        ));
+
+        let mut tampered = chunk;
+        tampered[50] ^= 0x01;
+        assert!(!decrypt(&key, &set_id, 2, 5, &mut tampered, &tag));
+    }
+}
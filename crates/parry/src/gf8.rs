@@ -51,6 +51,31 @@ impl Gf8 {
         197u8, 49u8, 254u8, 24u8, 13u8, 99u8, 140u8, 128u8, 192u8, 247u8, 112u8, 7u8,
     ];
 
+    /// Adds the product of `self` and each byte of `input` to the corresponding byte of `output`.
+    pub fn mul_add_slice(self, input: &[u8], output: &mut [u8]) {
+        assert_eq!(input.len(), output.len());
+
+        match self.0 {
+            0 => {}
+            1 => {
+                for (y, &x) in output.iter_mut().zip(input) {
+                    *y ^= x;
+                }
+            }
+            _ => {
+                let mut table = [0u8; 256];
+                for (x, product) in table.iter_mut().enumerate() {
+                    *product = (self * Gf8(x as u8)).0;
+                }
+
+                for (y, &x) in output.iter_mut().zip(input) {
+                    *y ^= table[x as usize];
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
     pub fn elements() -> Box<[Gf8]> {
         (0u8..=255u8)
             .map(Gf8)
@@ -78,6 +103,7 @@ impl Field for Gf8 {
 impl Add for Gf8 {
     type Output = Gf8;
 
+    #[allow(clippy::suspicious_arithmetic_impl)]
     fn add(self, y: Gf8) -> Gf8 {
         Gf8(self.0 ^ y.0)
     }
@@ -92,6 +118,7 @@ impl AddAssign for Gf8 {
 impl Sub for Gf8 {
     type Output = Gf8;
 
+    #[allow(clippy::suspicious_arithmetic_impl)]
     fn sub(self, y: Gf8) -> Gf8 {
         Gf8(self.0 ^ y.0)
     }
@@ -311,4 +338,18 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn mul_add_slice() {
+        let input: Vec<u8> = (0u8..=255u8).collect();
+
+        for x in Gf8::elements() {
+            let mut output: Vec<u8> = input.iter().rev().copied().collect();
+            x.mul_add_slice(&input, &mut output);
+
+            for (i, &y) in output.iter().enumerate() {
+                assert!(Gf8(y) == x * Gf8(i as u8) + Gf8(255 - i as u8));
+            }
+        }
+    }
 }
@@ -0,0 +1,338 @@
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use xxhash_rust::xxh3::xxh3_128;
+
+use crate::checksum::{ChecksumKind, ChunkKey};
+use crate::compression::{CompressionKind, Framing};
+
+pub(crate) const HEADER_SIZE: usize = 64;
+const HASH_SIZE: usize = 16;
+
+/// Set in the checksum byte of sparse shard sets.
+const SPARSE_FLAG: u8 = 0x80;
+/// Set in the checksum byte of shard sets whose length is recorded in a trailer.
+const STREAMED_FLAG: u8 = 0x40;
+
+const MAGIC: [u8; 4] = *b"PRRY";
+const VERSION: u16 = 1;
+
+/// Random identifier shared by every shard of a shard set, formatted as a UUID.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SetId([u8; 16]);
+
+impl SetId {
+    /// Generates a random (version 4) set ID.
+    pub fn random() -> SetId {
+        let mut bytes: [u8; 16] = rand::random();
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        SetId(bytes)
+    }
+
+    pub fn from_bytes(bytes: [u8; 16]) -> SetId {
+        SetId(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
+/// Where an encoder takes the IDs of the shard sets it creates from: random IDs, or the one it
+/// was given.
+#[derive(Debug, Default)]
+pub(crate) struct SetIdSource {
+    set_id: Option<SetId>,
+    used: AtomicBool,
+}
+
+impl SetIdSource {
+    pub fn fixed(set_id: SetId) -> SetIdSource {
+        SetIdSource {
+            set_id: Some(set_id),
+            used: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the ID of a new shard set. A fixed ID only serves one encrypted set, since the
+    /// nonces of its chunks are derived from it and encrypting another set would reuse them.
+    pub fn next(&self, encrypted: bool) -> io::Result<SetId> {
+        match self.set_id {
+            None => Result::Ok(SetId::random()),
+            Some(_) if encrypted && self.used.swap(true, Ordering::Relaxed) => {
+                Result::Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Encrypting another shard set with the same set ID would reuse nonces",
+                ))
+            }
+            Some(set_id) => Result::Ok(set_id),
+        }
+    }
+}
+
+impl fmt::Display for SetId {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                write!(formatter, "-")?;
+            }
+
+            write!(formatter, "{:02x}", byte)?;
+        }
+
+        Result::Ok(())
+    }
+}
+
+/// Properties of a shard set that every shard records alongside the encoding parameters.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct SetInfo {
+    pub set_id: SetId,
+    /// Length of the encoded payload, which for compressed sets is the compressed frames and
+    /// their seek table rather than the data itself.
+    pub length: usize,
+    pub framing: Option<Framing>,
+    /// Whether all-zero chunks may have been left as holes.
+    pub sparse: bool,
+    /// Whether the data was encoded without knowing its length, which is then recorded in a
+    /// trailer stripe following the data stripes rather than in the header.
+    pub streamed: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct ShardHeader {
+    pub shard: usize,
+    pub data_shards: usize,
+    pub parity_shards: usize,
+    pub local_parity_shards: usize,
+    pub chunk_size: usize,
+    pub checksum: ChecksumKind,
+    pub set: SetInfo,
+}
+
+impl ShardHeader {
+    fn to_bytes(self, key: &ChunkKey) -> io::Result<[u8; HEADER_SIZE]> {
+        let mut bytes = [0u8; HEADER_SIZE];
+
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4..6].copy_from_slice(&VERSION.to_be_bytes());
+        bytes[6..8].copy_from_slice(&(self.shard as u16).to_be_bytes());
+        bytes[8..10].copy_from_slice(&(self.data_shards as u16).to_be_bytes());
+        bytes[10..12].copy_from_slice(&(self.parity_shards as u16).to_be_bytes());
+        bytes[12..16].copy_from_slice(&(self.chunk_size as u32).to_be_bytes());
+        bytes[16..24].copy_from_slice(&(self.set.length as u64).to_be_bytes());
+        bytes[24..26].copy_from_slice(&(self.local_parity_shards as u16).to_be_bytes());
+        bytes[26] = self.checksum.to_byte()
+            | if self.set.sparse { SPARSE_FLAG } else { 0 }
+            | if self.set.streamed { STREAMED_FLAG } else { 0 };
+        if let Some(framing) = self.set.framing {
+            bytes[27] = framing.compression.to_byte();
+            bytes[28..32].copy_from_slice(&(framing.frame_size as u32).to_be_bytes());
+        }
+        bytes[32..48].copy_from_slice(self.set.set_id.as_bytes());
+
+        let hash = self.hash(&bytes[..HEADER_SIZE - HASH_SIZE], key)?;
+        bytes[HEADER_SIZE - HASH_SIZE..].copy_from_slice(&hash);
+
+        Result::Ok(bytes)
+    }
+
+    /// Parses a header without checking its hash.
+    fn parse(bytes: &[u8; HEADER_SIZE]) -> Option<ShardHeader> {
+        if bytes[0..4] != MAGIC || u16::from_be_bytes([bytes[4], bytes[5]]) != VERSION {
+            return None;
+        }
+
+        let framing = match bytes[27] {
+            0 => None,
+            byte => Some(Framing {
+                compression: CompressionKind::from_byte(byte)?,
+                frame_size: match u32::from_be_bytes(bytes[28..32].try_into().unwrap()) {
+                    0 => return None,
+                    frame_size => frame_size as usize,
+                },
+            }),
+        };
+
+        let checksum = ChecksumKind::from_byte(bytes[26] & !(SPARSE_FLAG | STREAMED_FLAG))?;
+        let sparse = bytes[26] & SPARSE_FLAG != 0;
+
+        // Sparse shard sets are only written with unkeyed checksums.
+        if sparse && checksum.is_keyed() {
+            return None;
+        }
+
+        Some(ShardHeader {
+            shard: u16::from_be_bytes([bytes[6], bytes[7]]) as usize,
+            data_shards: u16::from_be_bytes([bytes[8], bytes[9]]) as usize,
+            parity_shards: u16::from_be_bytes([bytes[10], bytes[11]]) as usize,
+            chunk_size: u32::from_be_bytes(bytes[12..16].try_into().unwrap()) as usize,
+            local_parity_shards: u16::from_be_bytes([bytes[24], bytes[25]]) as usize,
+            checksum,
+            set: SetInfo {
+                set_id: SetId(bytes[32..48].try_into().unwrap()),
+                length: u64::from_be_bytes(bytes[16..24].try_into().unwrap()) as usize,
+                framing,
+                sparse,
+                streamed: bytes[26] & STREAMED_FLAG != 0,
+            },
+        })
+    }
+
+    /// Computes the hash protecting the rest of the header. Headers of shard sets with keyed
+    /// checksums carry a MAC under the key of the set instead of an xxh3 hash, so that their
+    /// length and flags cannot be altered without the key either.
+    fn hash(&self, bytes: &[u8], key: &ChunkKey) -> io::Result<[u8; HASH_SIZE]> {
+        if !self.checksum.is_keyed() {
+            return Result::Ok(xxh3_128(bytes).to_be_bytes());
+        }
+
+        let checksum = key.checksum(self.checksum, &self.set.set_id)?;
+        Result::Ok(checksum.authenticate_header(bytes))
+    }
+}
+
+/// Writes `header`, authenticated with `key` if its checksum is keyed.
+pub(crate) fn write_header<W: Write>(
+    writer: &mut W,
+    header: ShardHeader,
+    key: &ChunkKey,
+) -> io::Result<()> {
+    writer.write_all(&header.to_bytes(key)?)
+}
+
+/// Reads a header, authenticating it with `key` if its checksum is keyed.
+pub(crate) fn read_header<R: Read>(reader: &mut R, key: &ChunkKey) -> io::Result<ShardHeader> {
+    let mut bytes = [0u8; HEADER_SIZE];
+    reader.read_exact(&mut bytes)?;
+
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid shard header");
+    let header = ShardHeader::parse(&bytes).ok_or_else(invalid)?;
+
+    if header.hash(&bytes[..HEADER_SIZE - HASH_SIZE], key)? != bytes[HEADER_SIZE - HASH_SIZE..] {
+        return Result::Err(invalid());
+    }
+
+    Result::Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trip() {
+        let mut header = ShardHeader {
+            shard: 5,
+            data_shards: 10,
+            parity_shards: 4,
+            local_parity_shards: 2,
+            chunk_size: 4096,
+            checksum: ChecksumKind::Blake3,
+            set: SetInfo {
+                set_id: SetId::random(),
+                length: 123456789,
+                framing: None,
+                sparse: false,
+                streamed: false,
+            },
+        };
+
+        let mut buffer = vec![];
+        write_header(&mut buffer, header, &ChunkKey::None).unwrap();
+        assert_eq!(
+            read_header(&mut Cursor::new(&buffer), &ChunkKey::None).unwrap(),
+            header
+        );
+
+        header.set.framing = Some(Framing {
+            compression: CompressionKind::Lz4,
+            frame_size: 65536,
+        });
+        header.set.sparse = true;
+        header.set.streamed = true;
+
+        let mut buffer = vec![];
+        write_header(&mut buffer, header, &ChunkKey::None).unwrap();
+        assert_eq!(buffer.len(), HEADER_SIZE);
+
+        assert_eq!(
+            read_header(&mut Cursor::new(&buffer), &ChunkKey::None).unwrap(),
+            header
+        );
+
+        for i in 0..HEADER_SIZE {
+            let mut corrupted = buffer.clone();
+            corrupted[i] ^= 0x04;
+            assert!(read_header(&mut Cursor::new(&corrupted), &ChunkKey::None).is_err());
+        }
+
+        assert!(
+            read_header(
+                &mut Cursor::new(&buffer[..HEADER_SIZE - 1]),
+                &ChunkKey::None
+            )
+            .is_err()
+        );
+
+        // Frames hold at least one byte.
+        header.set.framing = Some(Framing {
+            compression: CompressionKind::Zstd,
+            frame_size: 0,
+        });
+        let mut buffer = vec![];
+        write_header(&mut buffer, header, &ChunkKey::None).unwrap();
+        let error = read_header(&mut Cursor::new(&buffer), &ChunkKey::None).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn keyed_sets_are_never_sparse() {
+        let key = ChunkKey::Mac([3u8; crate::checksum::KEY_SIZE]);
+        let mut header = ShardHeader {
+            shard: 1,
+            data_shards: 4,
+            parity_shards: 2,
+            local_parity_shards: 0,
+            chunk_size: 1024,
+            checksum: ChecksumKind::KeyedBlake3,
+            set: SetInfo {
+                set_id: SetId::random(),
+                length: 5000,
+                framing: None,
+                sparse: false,
+                streamed: false,
+            },
+        };
+
+        let mut buffer = vec![];
+        write_header(&mut buffer, header, &key).unwrap();
+        assert_eq!(
+            read_header(&mut Cursor::new(&buffer), &key).unwrap(),
+            header
+        );
+
+        header.set.sparse = true;
+        let mut buffer = vec![];
+        write_header(&mut buffer, header, &key).unwrap();
+        let error = read_header(&mut Cursor::new(&buffer), &key).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn set_id_format() {
+        let set_id = SetId::from_bytes([
+            0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab,
+            0xcd, 0xef,
+        ]);
+        assert_eq!(set_id.to_string(), "12345678-9abc-def0-0123-456789abcdef");
+
+        let random = SetId::random().to_string();
+        assert_eq!(random.len(), 36);
+        assert_eq!(&random[14..15], "4");
+        assert_ne!(SetId::random(), SetId::random());
+    }
+}
@@ -0,0 +1,515 @@
+use std::io;
+use std::io::{Read, Seek, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::ReedSolomonEncoder;
+use crate::checksum::Checksum;
+use crate::compression;
+use crate::io::{ChunkReadError, read_chunk, seek_to_chunk};
+use crate::layout::Layout;
+use crate::stripe::StripeDecoder;
+
+/// Weight of the latest read in the moving average of the latency of a shard.
+const LATENCY_WEIGHT: f64 = 0.25;
+
+/// Moving averages of how long chunk reads from each shard took, kept across hedged decodes so
+/// that shards on chronically slow sources are read last. Clones share the same averages.
+#[derive(Clone, Debug, Default)]
+pub struct ShardLatencies {
+    averages: Arc<Mutex<Vec<Option<f64>>>>,
+}
+
+impl ShardLatencies {
+    pub fn new() -> ShardLatencies {
+        ShardLatencies::default()
+    }
+
+    /// Returns the average latency of chunk reads from `shard`, or None if none was made.
+    pub fn latency(&self, shard: usize) -> Option<Duration> {
+        let averages = self.averages.lock().unwrap();
+        averages
+            .get(shard)
+            .copied()
+            .flatten()
+            .map(Duration::from_secs_f64)
+    }
+
+    fn record(&self, shard: usize, latency: Duration) {
+        let mut averages = self.averages.lock().unwrap();
+        if averages.len() <= shard {
+            averages.resize(shard + 1, None);
+        }
+
+        let sample = latency.as_secs_f64();
+        averages[shard] = Some(match averages[shard] {
+            Some(average) => average + LATENCY_WEIGHT * (sample - average),
+            None => sample,
+        });
+    }
+
+    /// Orders `shards` from the fastest to the slowest. Shards never read from come first, so
+    /// that they get measured, and ties go to the lower index, so that data shards, which need
+    /// no reconstruction, are preferred.
+    fn rank(&self, shards: &mut [usize]) {
+        let averages = self.averages.lock().unwrap();
+        let average = |shard: usize| averages.get(shard).copied().flatten().unwrap_or(0.0);
+        shards.sort_by(|&a, &b| average(a).total_cmp(&average(b)).then(a.cmp(&b)));
+    }
+}
+
+/// The outcome of reading one chunk, sent back by the thread reading its shard.
+struct ChunkRead {
+    shard: usize,
+    stripe: usize,
+    result: Result<Vec<u8>, ChunkReadError>,
+    elapsed: Duration,
+}
+
+/// Reads the chunks of `shard` for the stripes requested through `requests`, skipping those of
+/// stripes that were already decoded. Returns once the requests end or after a read error, as
+/// the shard is unusable from then on.
+fn read_chunks<R: Read + Seek>(
+    shard: usize,
+    reader: &mut R,
+    layout: Layout,
+    checksum: Checksum,
+    requests: Receiver<usize>,
+    results: Sender<ChunkRead>,
+    current_stripe: &AtomicUsize,
+) {
+    let mut next_stripe = None;
+
+    for stripe in requests {
+        if stripe < current_stripe.load(Ordering::Acquire) {
+            continue;
+        }
+
+        let start = Instant::now();
+        let mut chunk = vec![0u8; layout.chunk_size()];
+        let result = if next_stripe == Some(stripe) {
+            Result::Ok(())
+        } else {
+            seek_to_chunk(reader, &layout, stripe).map_err(ChunkReadError::IoError)
+        }
+        .and_then(|()| read_chunk(reader, checksum, shard, stripe, &mut chunk));
+
+        let failed = matches!(
+            result,
+            Result::Err(ChunkReadError::Truncated | ChunkReadError::IoError(_))
+        );
+        next_stripe = (!failed).then_some(stripe + 1);
+
+        let read = ChunkRead {
+            shard,
+            stripe,
+            result: result.map(|()| chunk),
+            elapsed: start.elapsed(),
+        };
+
+        if results.send(read).is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// Presents the data decoded by hedged reads as a byte stream, reconstructing one stripe at a
+/// time from the first chunks to arrive.
+struct HedgedReader<'a> {
+    decoder: StripeDecoder,
+    data_shards: usize,
+    extra_reads: usize,
+    latencies: &'a ShardLatencies,
+    requests: Vec<Option<Sender<usize>>>,
+    results: Receiver<ChunkRead>,
+    current_stripe: &'a AtomicUsize,
+    block_size: usize,
+    remaining: usize,
+    position: usize,
+    available: usize,
+}
+
+impl HedgedReader<'_> {
+    fn request(&self, shard: usize, stripe: usize) -> bool {
+        self.requests[shard]
+            .as_ref()
+            .is_some_and(|requests| requests.send(stripe).is_ok())
+    }
+
+    /// Requests the chunks of the next stripe from the fastest `data_shards + extra_reads`
+    /// shards and reconstructs it from the first `data_shards` valid chunks to arrive. Every
+    /// failed read is replaced by one from the next shard in line.
+    fn decode_stripe(&mut self) -> io::Result<()> {
+        let stripe = self.decoder.stripe();
+        self.current_stripe.store(stripe, Ordering::Release);
+        self.decoder.begin_stripe();
+
+        let mut candidates: Vec<usize> = (0..self.requests.len())
+            .filter(|&shard| self.requests[shard].is_some())
+            .collect();
+        self.latencies.rank(&mut candidates);
+        let mut candidates = candidates.into_iter();
+
+        let mut pending: Vec<(usize, Instant)> = vec![];
+        let mut valid = 0;
+        let wanted = self.data_shards + self.extra_reads;
+
+        while valid < self.data_shards {
+            while valid + pending.len() < wanted {
+                let Some(shard) = candidates.next() else {
+                    break;
+                };
+                if self.request(shard, stripe) {
+                    pending.push((shard, Instant::now()));
+                }
+            }
+
+            if pending.is_empty() {
+                break;
+            }
+
+            let Result::Ok(read) = self.results.recv() else {
+                break;
+            };
+
+            self.latencies.record(read.shard, read.elapsed);
+            let failed = matches!(
+                read.result,
+                Result::Err(ChunkReadError::Truncated | ChunkReadError::IoError(_))
+            );
+
+            // A shard that failed reading an earlier stripe stops reading, so it will not answer
+            // for this one either.
+            if failed {
+                self.requests[read.shard] = None;
+                pending.retain(|&(shard, _)| shard != read.shard);
+            }
+
+            if read.stripe != stripe {
+                continue;
+            }
+
+            pending.retain(|&(shard, _)| shard != read.shard);
+            if let Result::Ok(chunk) = read.result {
+                self.decoder.set_chunk(read.shard, &chunk);
+                valid += 1;
+            }
+        }
+
+        // The reads still in flight are abandoned, and the shards they wait on are charged for
+        // the time spent so far, as their actual latency is only known once they complete.
+        self.current_stripe.store(stripe + 1, Ordering::Release);
+        for (shard, start) in pending {
+            self.latencies.record(shard, start.elapsed());
+        }
+
+        self.decoder.reconstruct()
+    }
+}
+
+impl Read for HedgedReader<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.available {
+            if self.remaining == 0 {
+                return Result::Ok(0);
+            }
+
+            self.decode_stripe()?;
+
+            self.available = self.remaining.min(self.block_size);
+            self.remaining -= self.available;
+            self.position = 0;
+        }
+
+        let count = buffer.len().min(self.available - self.position);
+        buffer[..count]
+            .copy_from_slice(&self.decoder.block()[self.position..self.position + count]);
+        self.position += count;
+
+        Result::Ok(count)
+    }
+}
+
+impl ReedSolomonEncoder {
+    /// Decodes a whole shard set into `output` while reading every shard from its own thread.
+    /// The chunks of each stripe are requested from `data_shards + extra_reads` shards at once,
+    /// the fastest first according to `latencies`, and the stripe is reconstructed from the
+    /// first valid chunks to arrive, so that a slow or stalled source does not hold up the
+    /// decode. The reads that lose the race are abandoned and their chunks discarded. Their
+    /// latencies are recorded in `latencies`, which can be shared by later decodes to keep slow
+    /// shards out of the first requests.
+    ///
+    /// Reads cannot be interrupted, so although the output is written without waiting for
+    /// stalled sources, this only returns once the read in flight on every shard completes or
+    /// fails. Sources that can stall indefinitely need a timeout of their own, such as
+    /// `RemoteStore::with_timeout`, to bound how long that takes.
+    pub fn decode_hedged<R: Read + Seek + Send, W: Write>(
+        &self,
+        shard_readers: &mut [Option<R>],
+        output: &mut W,
+        extra_reads: usize,
+        latencies: &ShardLatencies,
+    ) -> io::Result<()> {
+        assert_eq!(shard_readers.len(), self.data_shards + self.parity_shards);
+
+        let mut decoder = StripeDecoder::new(self).with_monitor(self.monitor.start(None));
+        decoder.read_headers(shard_readers)?;
+        let length = decoder.read_trailer(shard_readers)?;
+
+        let layout = decoder.layout();
+        let checksum = decoder.checksum();
+        let framing = decoder.set().framing;
+        let current_stripe = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            let (sender, results) = mpsc::channel();
+            let requests = shard_readers
+                .iter_mut()
+                .enumerate()
+                .map(|(shard, shard_reader)| {
+                    let reader = shard_reader.as_mut()?;
+                    let (requests, receiver) = mpsc::channel();
+                    let sender = sender.clone();
+                    let current_stripe = &current_stripe;
+                    scope.spawn(move || {
+                        read_chunks(
+                            shard,
+                            reader,
+                            layout,
+                            checksum,
+                            receiver,
+                            sender,
+                            current_stripe,
+                        )
+                    });
+                    Some(requests)
+                })
+                .collect();
+
+            let mut payload = HedgedReader {
+                decoder,
+                data_shards: self.data_shards,
+                extra_reads,
+                latencies,
+                requests,
+                results,
+                current_stripe: &current_stripe,
+                block_size: layout.block_size(),
+                remaining: length,
+                position: 0,
+                available: 0,
+            };
+
+            match framing {
+                Some(framing) => compression::decompress(framing, &mut payload, output),
+                None => io::copy(&mut payload, output).map(|_| ()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{encode_shards, shard_readers};
+    use crate::{Compression, CompressionKind};
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+    use std::io::{Cursor, SeekFrom};
+
+    /// Shard contents that take `delay` to produce on every read and count the reads made.
+    struct SlowReader {
+        inner: Cursor<Vec<u8>>,
+        delay: Duration,
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl Read for SlowReader {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(self.delay);
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.inner.read(buffer)
+        }
+    }
+
+    impl Seek for SlowReader {
+        fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(position)
+        }
+    }
+
+    #[test]
+    fn decode_hedged() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+
+        for length in [0, 1, 4096, 16 * 1024 + 17] {
+            let (buffer, shards) = encode_shards(&encoder, &mut rng, length);
+
+            for missing in [vec![], vec![0], vec![3, 5], vec![1, 2]] {
+                for extra_reads in [0, 1, 2, 5] {
+                    let mut output = vec![];
+                    encoder
+                        .decode_hedged(
+                            &mut shard_readers(&shards, &missing),
+                            &mut output,
+                            extra_reads,
+                            &ShardLatencies::new(),
+                        )
+                        .unwrap();
+                    assert!(output == buffer, "length {}, missing {:?}", length, missing);
+                }
+            }
+
+            let mut output = vec![];
+            let result = encoder.decode_hedged(
+                &mut shard_readers(&shards, &[0, 1, 2]),
+                &mut output,
+                1,
+                &ShardLatencies::new(),
+            );
+            assert!(result.is_err() == (length > 0));
+        }
+    }
+
+    #[test]
+    fn damaged_chunks_are_replaced() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let (buffer, mut shards) = encode_shards(&encoder, &mut rng, 40000);
+        let layout = encoder.layout();
+        for (shard, stripe) in [(0, 1), (1, 1), (2, 3), (5, 3)] {
+            shards[shard][layout.chunk_offset(stripe) as usize + 20] ^= 1;
+        }
+        shards[3].truncate(layout.chunk_offset(6) as usize);
+
+        for extra_reads in [0, 1, 2] {
+            let mut output = vec![];
+            encoder
+                .decode_hedged(
+                    &mut shard_readers(&shards, &[]),
+                    &mut output,
+                    extra_reads,
+                    &ShardLatencies::new(),
+                )
+                .unwrap();
+            assert!(output == buffer);
+        }
+    }
+
+    #[test]
+    fn compressed_and_streamed() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let mut buffer = vec![0u8; 30000];
+        rng.fill_bytes(&mut buffer[..10000]);
+
+        let mut writers: Vec<Cursor<Vec<u8>>> = (0..6).map(|_| Cursor::new(vec![])).collect();
+        encoder
+            .encode_compressed(
+                &mut Cursor::new(&buffer),
+                buffer.len(),
+                Compression::new(CompressionKind::Zstd).with_frame_size(3000),
+                &mut writers,
+            )
+            .unwrap();
+        let compressed: Vec<Vec<u8>> = writers.into_iter().map(Cursor::into_inner).collect();
+
+        let mut writers: Vec<Cursor<Vec<u8>>> = (0..6).map(|_| Cursor::new(vec![])).collect();
+        encoder
+            .encode_stream(&mut Cursor::new(&buffer), &mut writers)
+            .unwrap();
+        let streamed: Vec<Vec<u8>> = writers.into_iter().map(Cursor::into_inner).collect();
+
+        for shards in [compressed, streamed] {
+            let mut output = vec![];
+            encoder
+                .decode_hedged(
+                    &mut shard_readers(&shards, &[1, 4]),
+                    &mut output,
+                    1,
+                    &ShardLatencies::new(),
+                )
+                .unwrap();
+            assert!(output == buffer);
+        }
+    }
+
+    #[test]
+    fn abandoned_shard_fails_later() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+
+        // The slow shard loses the race for the first stripe and is the only one left for the
+        // second, but its read of the first stripe turns out to be truncated.
+        let (_, mut shards) = encode_shards(&encoder, &mut rng, 20 * 4096);
+        let layout = encoder.layout();
+        shards[0].truncate(layout.chunk_offset(0) as usize);
+        for shard in [1, 2] {
+            shards[shard][layout.chunk_offset(1) as usize + 20] ^= 1;
+        }
+
+        let shard_readers: Vec<Option<SlowReader>> = shards
+            .into_iter()
+            .enumerate()
+            .map(|(shard, contents)| {
+                Some(SlowReader {
+                    inner: Cursor::new(contents),
+                    delay: Duration::from_millis(if shard == 0 { 200 } else { 0 }),
+                    reads: Arc::default(),
+                })
+            })
+            .collect();
+
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut shard_readers = shard_readers;
+            let result =
+                encoder.decode_hedged(&mut shard_readers, &mut vec![], 1, &ShardLatencies::new());
+            let _ = sender.send(result);
+        });
+
+        let result = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(
+            result.err().map(|error| error.kind()),
+            Some(io::ErrorKind::InvalidData)
+        );
+    }
+
+    #[test]
+    fn slow_shards_are_deprioritized() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let (buffer, shards) = encode_shards(&encoder, &mut rng, 20 * 4096);
+
+        let reads: Vec<Arc<AtomicUsize>> = (0..6).map(|_| Arc::default()).collect();
+        let mut shard_readers: Vec<Option<SlowReader>> = shards
+            .into_iter()
+            .enumerate()
+            .map(|(shard, contents)| {
+                Some(SlowReader {
+                    inner: Cursor::new(contents),
+                    delay: Duration::from_millis(if shard == 0 { 200 } else { 0 }),
+                    reads: reads[shard].clone(),
+                })
+            })
+            .collect();
+
+        // Reading the header of the slow shard takes one read, its first chunk two more.
+        let latencies = ShardLatencies::new();
+        let start = Instant::now();
+        let mut output = vec![];
+        encoder
+            .decode_hedged(&mut shard_readers, &mut output, 1, &latencies)
+            .unwrap();
+        assert!(output == buffer);
+        assert!(start.elapsed() < Duration::from_secs(3));
+        assert!(reads[0].load(Ordering::Relaxed) <= 5);
+
+        let slowest = (0..6).max_by_key(|&shard| latencies.latency(shard));
+        assert_eq!(slowest, Some(0));
+    }
+}
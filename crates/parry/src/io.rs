@@ -1,6 +1,8 @@
 use std::io;
 use std::io::{Read, Seek, SeekFrom, Write};
-use xxhash_rust::xxh3::xxh3_128;
+
+use crate::checksum::{Checksum, MAX_CHECKSUM_SIZE};
+use crate::layout::Layout;
 
 pub(crate) enum ChunkReadError {
     IoError(io::Error),
@@ -8,24 +10,129 @@ pub(crate) enum ChunkReadError {
     ChecksumValidationFailure,
 }
 
-pub(crate) fn write_chunk<W: Write>(writer: &mut W, chunk: &[u8]) -> io::Result<()> {
-    writer.write_all(&xxh3_128(chunk).to_be_bytes())?;
+impl From<ChunkReadError> for io::Error {
+    fn from(error: ChunkReadError) -> io::Error {
+        match error {
+            ChunkReadError::IoError(error) => error,
+            ChunkReadError::Truncated => {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated chunk")
+            }
+            ChunkReadError::ChecksumValidationFailure => io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Chunk checksum validation failed",
+            ),
+        }
+    }
+}
+
+pub(crate) fn is_zero(bytes: &[u8]) -> bool {
+    bytes.iter().all(|&byte| byte == 0)
+}
+
+/// Writes `chunk`, stored in stripe `stripe` of shard `shard`, preceded by its checksum. Chunks
+/// of encrypted sets are written encrypted, preceded by their tag.
+pub(crate) fn write_chunk<W: Write>(
+    writer: &mut W,
+    checksum: Checksum,
+    shard: usize,
+    stripe: usize,
+    chunk: &[u8],
+) -> io::Result<()> {
+    if checksum.kind().is_encrypted() {
+        let mut tag = [0u8; MAX_CHECKSUM_SIZE];
+        let mut ciphertext = chunk.to_vec();
+        checksum.encrypt(shard, stripe, &mut ciphertext, &mut tag[..checksum.size()]);
+
+        writer.write_all(&tag[..checksum.size()])?;
+        writer.write_all(&ciphertext)?;
+        return Result::Ok(());
+    }
+
+    let mut computed_checksum = [0u8; MAX_CHECKSUM_SIZE];
+    checksum.compute(
+        shard,
+        stripe,
+        chunk,
+        &mut computed_checksum[..checksum.size()],
+    );
+
+    writer.write_all(&computed_checksum[..checksum.size()])?;
     writer.write_all(chunk)?;
     Result::Ok(())
 }
 
-pub(crate) fn read_chunk<R: Read>(reader: &mut R, chunk: &mut [u8]) -> Result<(), ChunkReadError> {
-    let mut hash_be_bytes = [0u8; 16];
+/// Writes the all-zero `chunk` like `write_chunk`, but seeks over its contents instead of
+/// writing them, leaving a hole that reads back as zeros. The checksum in front of the hole is
+/// what marks it as a zero chunk, so that a chunk zeroed out by damage still fails validation.
+pub(crate) fn write_hole<W: Write + Seek>(
+    writer: &mut W,
+    checksum: Checksum,
+    shard: usize,
+    stripe: usize,
+    chunk: &[u8],
+) -> io::Result<()> {
+    let mut computed_checksum = [0u8; MAX_CHECKSUM_SIZE];
+    checksum.compute(
+        shard,
+        stripe,
+        chunk,
+        &mut computed_checksum[..checksum.size()],
+    );
 
-    reader.read_exact(&mut hash_be_bytes).map_err(|error| {
-        if error.kind() == io::ErrorKind::UnexpectedEof {
-            ChunkReadError::Truncated
-        } else {
-            ChunkReadError::IoError(error)
-        }
-    })?;
+    writer.write_all(&computed_checksum[..checksum.size()])?;
+    writer.seek(SeekFrom::Current(chunk.len() as i64))?;
+    Result::Ok(())
+}
+
+/// Fills in the checksum at the start of `bytes`, an in-memory chunk laid out as by
+/// `write_chunk` whose contents have already been written after the checksum.
+#[cfg(feature = "mmap")]
+pub(crate) fn seal_chunk(bytes: &mut [u8], checksum: Checksum, shard: usize, stripe: usize) {
+    let (stored_checksum, chunk) = bytes.split_at_mut(checksum.size());
+    checksum.compute(shard, stripe, chunk, stored_checksum);
+}
+
+/// Validates an in-memory chunk laid out as by `write_chunk`, returning its contents.
+#[cfg(feature = "mmap")]
+pub(crate) fn open_chunk(
+    bytes: &[u8],
+    checksum: Checksum,
+    shard: usize,
+    stripe: usize,
+) -> Option<&[u8]> {
+    let (stored_checksum, chunk) = bytes.split_at(checksum.size());
 
-    let hash = u128::from_be_bytes(hash_be_bytes);
+    let mut computed_checksum = [0u8; MAX_CHECKSUM_SIZE];
+    checksum.compute(
+        shard,
+        stripe,
+        chunk,
+        &mut computed_checksum[..checksum.size()],
+    );
+
+    (stored_checksum == &computed_checksum[..checksum.size()]).then_some(chunk)
+}
+
+/// Reads a chunk written by `write_chunk` for stripe `stripe` of shard `shard` and validates its
+/// checksum, decrypting it if the set is encrypted.
+pub(crate) fn read_chunk<R: Read>(
+    reader: &mut R,
+    checksum: Checksum,
+    shard: usize,
+    stripe: usize,
+    chunk: &mut [u8],
+) -> Result<(), ChunkReadError> {
+    let mut stored_checksum = [0u8; MAX_CHECKSUM_SIZE];
+
+    reader
+        .read_exact(&mut stored_checksum[..checksum.size()])
+        .map_err(|error| {
+            if error.kind() == io::ErrorKind::UnexpectedEof {
+                ChunkReadError::Truncated
+            } else {
+                ChunkReadError::IoError(error)
+            }
+        })?;
 
     reader.read_exact(chunk).map_err(|error| {
         if error.kind() == io::ErrorKind::UnexpectedEof {
@@ -35,9 +142,23 @@ pub(crate) fn read_chunk<R: Read>(reader: &mut R, chunk: &mut [u8]) -> Result<()
         }
     })?;
 
-    let computed_hash = xxh3_128(chunk);
+    if checksum.kind().is_encrypted() {
+        return if checksum.decrypt(shard, stripe, chunk, &stored_checksum[..checksum.size()]) {
+            Result::Ok(())
+        } else {
+            Result::Err(ChunkReadError::ChecksumValidationFailure)
+        };
+    }
+
+    let mut computed_checksum = [0u8; MAX_CHECKSUM_SIZE];
+    checksum.compute(
+        shard,
+        stripe,
+        chunk,
+        &mut computed_checksum[..checksum.size()],
+    );
 
-    if hash == computed_hash {
+    if stored_checksum == computed_checksum {
         Result::Ok(())
     } else {
         Result::Err(ChunkReadError::ChecksumValidationFailure)
@@ -46,9 +167,9 @@ pub(crate) fn read_chunk<R: Read>(reader: &mut R, chunk: &mut [u8]) -> Result<()
 
 pub(crate) fn seek_to_chunk<R: Seek>(
     reader: &mut R,
-    chunk_number: usize,
-    chunk_size: usize,
+    layout: &Layout,
+    stripe: usize,
 ) -> io::Result<()> {
-    reader.seek(SeekFrom::Start((chunk_size as u64) * (chunk_number as u64)))?;
+    reader.seek(SeekFrom::Start(layout.chunk_offset(stripe)))?;
     Result::Ok(())
 }
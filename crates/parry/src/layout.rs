@@ -0,0 +1,208 @@
+use crate::checksum::ChecksumKind;
+use crate::header::HEADER_SIZE;
+
+/// Where data lives in a shard set. Data is split into stripes of `data_shards` chunks, and
+/// every shard file holds a header followed by one chunk per stripe, each preceded by its
+/// checksum. The final stripe is padded with zeros.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Layout {
+    data_shards: usize,
+    chunk_size: usize,
+    checksum: ChecksumKind,
+}
+
+/// Position of a byte of data within a shard set.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ChunkPosition {
+    pub stripe: usize,
+    /// The data shard holding the byte.
+    pub shard: usize,
+    /// Offset of the byte within its chunk.
+    pub offset: usize,
+}
+
+impl Layout {
+    pub fn new(data_shards: usize, chunk_size: usize, checksum: ChecksumKind) -> Layout {
+        assert!(
+            data_shards > 0,
+            "Number of data shards must be greater than zero"
+        );
+        assert!(chunk_size > 0, "Chunk size must be greater than zero");
+
+        Layout {
+            data_shards,
+            chunk_size,
+            checksum,
+        }
+    }
+
+    pub fn data_shards(&self) -> usize {
+        self.data_shards
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    pub fn checksum(&self) -> ChecksumKind {
+        self.checksum
+    }
+
+    /// Returns the amount of data held by a stripe.
+    pub fn block_size(&self) -> usize {
+        self.data_shards * self.chunk_size
+    }
+
+    /// Returns the size of a chunk in a shard file, including its checksum.
+    pub fn chunk_stride(&self) -> usize {
+        self.checksum.size() + self.chunk_size
+    }
+
+    /// Returns the number of stripes needed for `length` bytes of data.
+    pub fn stripes(&self, length: usize) -> usize {
+        length.div_ceil(self.block_size())
+    }
+
+    /// Returns the offset of the first byte of data in `stripe`.
+    pub fn stripe_start(&self, stripe: usize) -> usize {
+        stripe * self.block_size()
+    }
+
+    /// Returns the stripe, data shard and offset within the chunk of the byte of data at
+    /// `offset`.
+    pub fn locate(&self, offset: usize) -> ChunkPosition {
+        let within_stripe = offset % self.block_size();
+
+        ChunkPosition {
+            stripe: offset / self.block_size(),
+            shard: within_stripe / self.chunk_size,
+            offset: within_stripe % self.chunk_size,
+        }
+    }
+
+    /// Returns the offset in every shard file of the chunk of `stripe`, i.e. of its checksum.
+    pub fn chunk_offset(&self, stripe: usize) -> u64 {
+        HEADER_SIZE as u64 + stripe as u64 * self.chunk_stride() as u64
+    }
+
+    /// Returns the offset of the byte of data at `offset` within the file of the data shard
+    /// holding it.
+    pub fn physical_offset(&self, offset: usize) -> u64 {
+        let position = self.locate(offset);
+
+        self.chunk_offset(position.stripe) + (self.checksum.size() + position.offset) as u64
+    }
+
+    /// Returns the size of every shard file of a set holding `length` bytes of data.
+    pub fn shard_file_size(&self, length: usize) -> u64 {
+        self.chunk_offset(self.stripes(length))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ReedSolomonEncoder;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+    use std::io::Cursor;
+
+    #[test]
+    fn boundaries() {
+        let layout = Layout::new(4, 1024, ChecksumKind::Xxh3_128);
+        assert_eq!(layout.block_size(), 4096);
+        assert_eq!(layout.chunk_stride(), 1040);
+
+        for (offset, stripe, shard, chunk_offset) in [
+            (0, 0, 0, 0),
+            (1023, 0, 0, 1023),
+            (1024, 0, 1, 0),
+            (4095, 0, 3, 1023),
+            (4096, 1, 0, 0),
+            (4097, 1, 0, 1),
+            (3 * 4096 + 2 * 1024 + 5, 3, 2, 5),
+        ] {
+            let position = layout.locate(offset);
+            assert_eq!(
+                position,
+                ChunkPosition {
+                    stripe,
+                    shard,
+                    offset: chunk_offset
+                },
+                "offset {}",
+                offset
+            );
+            assert_eq!(
+                layout.physical_offset(offset),
+                (HEADER_SIZE + stripe * 1040 + 16 + chunk_offset) as u64
+            );
+        }
+
+        for (length, stripes) in [(0, 0), (1, 1), (4095, 1), (4096, 1), (4097, 2), (8192, 2)] {
+            assert_eq!(layout.stripes(length), stripes);
+            assert_eq!(
+                layout.shard_file_size(length),
+                (HEADER_SIZE + stripes * 1040) as u64
+            );
+        }
+
+        assert_eq!(layout.stripe_start(2), 8192);
+        assert_eq!(layout.chunk_offset(0), HEADER_SIZE as u64);
+
+        let layout = Layout::new(1, 1, ChecksumKind::None);
+        assert_eq!(
+            layout.locate(7),
+            ChunkPosition {
+                stripe: 7,
+                shard: 0,
+                offset: 0
+            }
+        );
+        assert_eq!(layout.physical_offset(7), (HEADER_SIZE + 7) as u64);
+
+        let layout = Layout::new(3, usize::MAX / 4, ChecksumKind::Blake3);
+        assert_eq!(
+            layout.chunk_offset(3),
+            HEADER_SIZE as u64 + 3 * (32 + usize::MAX as u64 / 4)
+        );
+    }
+
+    #[test]
+    fn matches_encoded_shards() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+
+        for checksum in [
+            ChecksumKind::None,
+            ChecksumKind::Crc32c,
+            ChecksumKind::Blake3,
+        ] {
+            let encoder = ReedSolomonEncoder::new(3, 2, 100).with_checksum(checksum);
+            let layout = encoder.layout();
+
+            for length in [0, 1, 299, 300, 301, 1000] {
+                let mut buffer = vec![0u8; length];
+                rng.fill_bytes(&mut buffer);
+
+                let mut writers: Vec<Cursor<Vec<u8>>> =
+                    (0..5).map(|_| Cursor::new(Vec::<u8>::new())).collect();
+                encoder
+                    .encode(&mut Cursor::new(&buffer), length, &mut writers)
+                    .unwrap();
+
+                for writer in writers.iter() {
+                    assert_eq!(
+                        writer.get_ref().len() as u64,
+                        layout.shard_file_size(length)
+                    );
+                }
+
+                for (offset, &byte) in buffer.iter().enumerate() {
+                    let shard = layout.locate(offset).shard;
+                    let physical_offset = layout.physical_offset(offset) as usize;
+                    assert_eq!(writers[shard].get_ref()[physical_offset], byte);
+                }
+            }
+        }
+    }
+}
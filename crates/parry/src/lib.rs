@@ -1,20 +1,72 @@
+mod archive;
+mod checkpoint;
+mod checksum;
+mod compression;
+mod encryption;
 mod field;
 mod gf8;
+mod header;
+mod hedged;
 mod io;
+mod layout;
+mod lrc;
 mod matrix;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod naming;
+mod placement;
+mod policy;
+mod progress;
+mod remote;
+#[cfg(feature = "s3")]
+mod s3;
+mod sink;
+mod sparse;
+mod store;
+mod stripe;
+#[cfg(test)]
+mod test_util;
 
-use std::io::{Read, Seek, Write};
-use std::slice;
-use xxhash_rust::xxh3::xxh3_128;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
 
+use crate::checksum::{Checksum, ChunkKey};
+use crate::compression::CompressingReader;
 use crate::gf8::Gf8;
-use crate::io::{ChunkReadError, read_chunk, seek_to_chunk, write_chunk};
+use crate::header::{SetIdSource, SetInfo, ShardHeader, read_header, write_header};
+use crate::io::{read_chunk, seek_to_chunk, write_chunk};
 use crate::matrix::Matrix;
+use crate::progress::Monitor;
+use crate::stripe::{StripeDecoder, StripeEncoder, StripeReader};
+
+pub use crate::archive::{EntryKind, Manifest, ManifestEntry};
+pub use crate::checkpoint::Checkpoint;
+pub use crate::checksum::{ChecksumKind, KEY_SIZE};
+pub use crate::compression::{Compression, CompressionKind};
+pub use crate::encryption::KeyProvider;
+pub use crate::header::SetId;
+pub use crate::hedged::ShardLatencies;
+pub use crate::layout::{ChunkPosition, Layout};
+pub use crate::lrc::LrcEncoder;
+pub use crate::naming::{NameContext, NameTemplate};
+pub use crate::placement::Placement;
+pub use crate::policy::{PolicyWriter, WritePolicy};
+pub use crate::progress::{CancellationToken, Progress, ProgressObserver, is_cancellation};
+pub use crate::remote::RemoteStore;
+#[cfg(feature = "s3")]
+pub use crate::s3::{S3Bucket, S3Store};
+pub use crate::sink::{CheckpointRecorder, ShardFile, ShardSink};
+pub use crate::sparse::SparseReader;
+pub use crate::store::{FileStore, MemoryStore, ShardStore, ShardWriter};
 
 pub struct ReedSolomonEncoder {
     data_shards: usize,
     parity_shards: usize,
     chunk_size: usize,
+    checksum: ChecksumKind,
+    key: ChunkKey,
+    set_id: SetIdSource,
+    monitor: Monitor,
 }
 
 impl ReedSolomonEncoder {
@@ -26,11 +78,165 @@ impl ReedSolomonEncoder {
 
         assert!(chunk_size > 0, "Chunk size must be greater than zero");
 
+        assert!(
+            chunk_size <= u32::MAX as usize,
+            "Chunk size cannot exceed {} bytes",
+            u32::MAX
+        );
+
         ReedSolomonEncoder {
             data_shards,
             parity_shards,
             chunk_size,
+            checksum: ChecksumKind::default(),
+            key: ChunkKey::None,
+            set_id: SetIdSource::default(),
+            monitor: Monitor::default(),
+        }
+    }
+
+    /// Selects the algorithm used to checksum each chunk. Defaults to 128-bit xxh3. Keyed
+    /// checksums and encryption are selected with `with_key` and `with_encryption` instead.
+    pub fn with_checksum(self, checksum: ChecksumKind) -> ReedSolomonEncoder {
+        assert!(
+            !checksum.is_keyed(),
+            "Keyed checksums are selected with with_key or with_encryption"
+        );
+
+        ReedSolomonEncoder {
+            checksum,
+            key: ChunkKey::None,
+            ..self
+        }
+    }
+
+    /// Authenticates each chunk with a keyed BLAKE3 MAC bound to its set ID, shard index and
+    /// stripe number. Chunks that were modified, or moved to another shard, stripe or shard set,
+    /// by anyone without the key fail validation and are treated as erasures when decoding. The
+    /// shard headers are authenticated with the key as well.
+    pub fn with_key(self, key: [u8; KEY_SIZE]) -> ReedSolomonEncoder {
+        ReedSolomonEncoder {
+            checksum: ChecksumKind::KeyedBlake3,
+            key: ChunkKey::Mac(key),
+            ..self
+        }
+    }
+
+    /// Encrypts each chunk with XChaCha20-Poly1305 under the key `key_provider` supplies for the
+    /// shard set, using a nonce derived from the set ID, shard index and stripe number. The
+    /// Poly1305 tag takes the place of the checksum, so chunks that fail authentication are
+    /// treated as erasures when decoding. Since every nonce must only be used once, encrypted
+    /// shard sets cannot be appended to or have their parity updated in place.
+    pub fn with_encryption(self, key_provider: Arc<dyn KeyProvider>) -> ReedSolomonEncoder {
+        ReedSolomonEncoder {
+            checksum: ChecksumKind::XChaCha20Poly1305,
+            key: ChunkKey::Cipher(key_provider),
+            ..self
+        }
+    }
+
+    /// Uses `set_id` for the shard sets this encoder creates instead of a random ID. Encrypting
+    /// encoders only create one shard set with it and fail to create more, as their chunks
+    /// would be encrypted under the same nonces.
+    pub fn with_set_id(self, set_id: SetId) -> ReedSolomonEncoder {
+        ReedSolomonEncoder {
+            set_id: SetIdSource::fixed(set_id),
+            ..self
+        }
+    }
+
+    /// Reports the progress of `encode`, `decode`, `verify` and the other operations that process
+    /// whole shard sets to `observer` after every stripe.
+    pub fn with_progress(self, observer: Arc<dyn ProgressObserver>) -> ReedSolomonEncoder {
+        ReedSolomonEncoder {
+            monitor: self.monitor.with_observer(observer),
+            ..self
+        }
+    }
+
+    /// Makes operations that process whole shard sets fail between stripes once `cancellation`
+    /// is cancelled.
+    pub fn with_cancellation(self, cancellation: CancellationToken) -> ReedSolomonEncoder {
+        ReedSolomonEncoder {
+            monitor: self.monitor.with_cancellation(cancellation),
+            ..self
+        }
+    }
+
+    pub fn data_shards(&self) -> usize {
+        self.data_shards
+    }
+
+    pub fn parity_shards(&self) -> usize {
+        self.parity_shards
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    pub fn checksum(&self) -> ChecksumKind {
+        self.checksum
+    }
+
+    pub(crate) fn chunk_key(&self) -> &ChunkKey {
+        &self.key
+    }
+
+    pub(crate) fn chunk_checksum(&self, set_id: &SetId) -> std::io::Result<Checksum> {
+        self.key.checksum(self.checksum, set_id)
+    }
+
+    /// Returns the ID of a new shard set.
+    pub(crate) fn new_set_id(&self) -> std::io::Result<SetId> {
+        self.set_id.next(self.checksum.is_encrypted())
+    }
+
+    fn check_rewritable(&self) -> std::io::Result<()> {
+        if self.checksum.is_encrypted() {
+            return Result::Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Rewriting chunks of an encrypted shard set would reuse nonces",
+            ));
+        }
+
+        Result::Ok(())
+    }
+
+    /// Returns where data and chunks live in the shard sets this encoder creates.
+    pub fn layout(&self) -> Layout {
+        Layout::new(self.data_shards, self.chunk_size, self.checksum)
+    }
+
+    pub(crate) fn write_header<W: Write>(
+        &self,
+        writer: &mut W,
+        shard: usize,
+        set: SetInfo,
+    ) -> std::io::Result<()> {
+        let header = ShardHeader {
+            shard,
+            data_shards: self.data_shards,
+            parity_shards: self.parity_shards,
+            local_parity_shards: 0,
+            chunk_size: self.chunk_size,
+            checksum: self.checksum,
+            set,
+        };
+
+        write_header(writer, header, &self.key)
+    }
+
+    fn write_headers<W: Write>(
+        &self,
+        shard_writers: &mut [W],
+        set: SetInfo,
+    ) -> std::io::Result<()> {
+        for (shard, writer) in shard_writers.iter_mut().enumerate() {
+            self.write_header(writer, shard, set)?;
         }
+
+        Result::Ok(())
     }
 
     pub fn encode<R: Read, W: Write>(
@@ -41,103 +247,1659 @@ impl ReedSolomonEncoder {
     ) -> std::io::Result<()> {
         assert_eq!(shard_writers.len(), self.data_shards + self.parity_shards);
 
-        let encoding_matrix = Matrix::<Gf8>::encoding_matrix(self.data_shards, self.parity_shards);
-        let encoding_matrix_excluding_identity =
-            encoding_matrix.slice(self.data_shards..self.data_shards + self.parity_shards);
-
-        let block_size = self.data_shards * self.chunk_size;
-        let loops = (length + 8).div_ceil(block_size);
-        let final_loop_block_size = if (length + 8).is_multiple_of(block_size) {
-            block_size
-        } else {
-            (length + 8) % block_size
+        let set = SetInfo {
+            set_id: self.new_set_id()?,
+            length,
+            framing: None,
+            sparse: false,
+            streamed: false,
         };
+        self.write_headers(shard_writers, set)?;
 
-        let mut data_matrix = Matrix::<Gf8>::with_dimensions(self.data_shards, self.chunk_size);
+        StripeEncoder::new(self, self.chunk_checksum(&set.set_id)?)
+            .with_monitor(self.monitor.start(Some(length)))
+            .encode_stripes(data, 0, length, shard_writers)
+    }
 
-        for i in 0..loops {
-            {
-                let mut buffer: &mut [u8] = unsafe {
-                    slice::from_raw_parts_mut(
-                        data_matrix.elements.as_mut_ptr() as *mut u8,
-                        block_size,
-                    )
-                };
-
-                if i == 0 {
-                    let encoded_length = (length as u64).to_be_bytes();
-                    buffer[0..8].copy_from_slice(&encoded_length);
-                    data.read_exact(&mut buffer[8..block_size])?;
-                } else if i == loops - 1 {
-                    data.read_exact(&mut buffer[0..final_loop_block_size])?;
-                    buffer[final_loop_block_size..block_size].fill(0);
-                } else {
-                    data.read_exact(&mut buffer)?;
-                }
+    /// Encodes everything read from `data` until it is exhausted, for input whose length is not
+    /// known in advance such as a pipe, and returns the number of bytes encoded. The shard
+    /// writers need not be seekable: the length is recorded in a trailer stripe following the
+    /// data stripes instead of in the headers. Decoding detects streamed shard sets from their
+    /// headers.
+    pub fn encode_stream<R: Read, W: Write>(
+        &self,
+        data: &mut R,
+        shard_writers: &mut [W],
+    ) -> std::io::Result<usize> {
+        assert_eq!(shard_writers.len(), self.data_shards + self.parity_shards);
 
-                for shard in 0..self.data_shards {
-                    let chunk = &buffer[shard * self.chunk_size..(shard + 1) * self.chunk_size];
-                    write_chunk(&mut shard_writers[shard], chunk)?;
-                }
-            }
+        let set = SetInfo {
+            set_id: self.new_set_id()?,
+            length: 0,
+            framing: None,
+            sparse: false,
+            streamed: true,
+        };
+        self.write_headers(shard_writers, set)?;
 
-            let encoded_parity_shard_matrix = &encoding_matrix_excluding_identity * &data_matrix;
+        let mut encoder = StripeEncoder::new(self, self.chunk_checksum(&set.set_id)?)
+            .with_monitor(self.monitor.start(None));
+        let length = encoder.encode_stream(data, shard_writers)?;
+        encoder.encode_trailer(length, shard_writers)?;
 
-            {
-                let buffer: &[u8] = unsafe {
-                    slice::from_raw_parts(
-                        encoded_parity_shard_matrix.elements.as_ptr() as *mut u8,
-                        self.parity_shards * self.chunk_size,
-                    )
-                };
+        for writer in shard_writers.iter_mut() {
+            writer.flush()?;
+        }
 
-                for parity_shard in 0..self.parity_shards {
-                    let shard = self.data_shards + parity_shard;
-                    let chunk = &buffer
-                        [parity_shard * self.chunk_size..(parity_shard + 1) * self.chunk_size];
-                    write_chunk(&mut shard_writers[shard], chunk)?;
-                }
+        Result::Ok(length)
+    }
+
+    /// Encodes `length` bytes read from `data` like `encode`, but only writes the checksums of
+    /// all-zero chunks and leaves holes in the shard files in place of their contents, which
+    /// saves space for inputs that are mostly zeros on file systems supporting sparse files.
+    /// Holes read back as zeros that their checksums validate, so chunks zeroed out after
+    /// encoding are still detected. Keyed checksums are not supported.
+    pub fn encode_sparse<R: Read, W: Write + Seek>(
+        &self,
+        data: &mut R,
+        length: usize,
+        shard_writers: &mut [W],
+    ) -> std::io::Result<()> {
+        assert_eq!(shard_writers.len(), self.data_shards + self.parity_shards);
+
+        if self.checksum.is_keyed() {
+            return Result::Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Sparse shard sets do not support keyed checksums",
+            ));
+        }
+
+        let layout = self.layout();
+        let set = SetInfo {
+            set_id: self.new_set_id()?,
+            length,
+            framing: None,
+            sparse: true,
+            streamed: false,
+        };
+        self.write_headers(shard_writers, set)?;
+
+        let mut encoder = StripeEncoder::new(self, self.chunk_checksum(&set.set_id)?)
+            .with_monitor(self.monitor.start(Some(length)));
+        let mut remaining = length;
+
+        while remaining > 0 {
+            let count = remaining.min(layout.block_size());
+
+            let block = encoder.block_mut();
+            data.read_exact(&mut block[..count])?;
+            block[count..].fill(0);
+
+            encoder.encode_sparse_stripe(shard_writers)?;
+            remaining -= count;
+        }
+
+        // A trailing hole only extends the file once something is written after it.
+        let shard_file_size = layout.shard_file_size(length);
+        for writer in shard_writers.iter_mut() {
+            if writer.seek(SeekFrom::End(0))? < shard_file_size {
+                writer.seek(SeekFrom::Start(shard_file_size - 1))?;
+                writer.write_all(&[0])?;
             }
+
+            writer.flush()?;
+        }
+
+        Result::Ok(())
+    }
+
+    /// Compresses `length` bytes read from `data` into independently compressed frames and
+    /// encodes them along with a seek table, so that `decode_at` only needs to decompress the
+    /// frames covering the requested range. The compressed length is only known once all frames
+    /// are written, after which the shard headers are rewritten. Decoding detects compressed
+    /// shard sets from their headers.
+    pub fn encode_compressed<R: Read, W: Write + Seek>(
+        &self,
+        data: &mut R,
+        length: usize,
+        compression: Compression,
+        shard_writers: &mut [W],
+    ) -> std::io::Result<()> {
+        assert_eq!(shard_writers.len(), self.data_shards + self.parity_shards);
+
+        let mut set = SetInfo {
+            set_id: self.new_set_id()?,
+            length: 0,
+            framing: Some(compression.framing()),
+            sparse: false,
+            streamed: false,
+        };
+        self.write_headers(shard_writers, set)?;
+
+        let mut payload = CompressingReader::new(data, compression, length);
+        set.length = StripeEncoder::new(self, self.chunk_checksum(&set.set_id)?)
+            .with_monitor(self.monitor.start(None))
+            .encode_stream(&mut payload, shard_writers)?;
+
+        for writer in shard_writers.iter_mut() {
+            writer.flush()?;
+            writer.seek(SeekFrom::Start(0))?;
+        }
+
+        self.write_headers(shard_writers, set)?;
+
+        for writer in shard_writers.iter_mut() {
+            writer.flush()?;
         }
 
         Result::Ok(())
     }
 
-    pub fn decode<R: Read, W: Write>(data: &[Option<R>], output: W) -> std::io::Result<()> {
+    /// Appends `length` bytes read from `data` to an existing shard set. The partially filled
+    /// final stripe is reconstructed and re-encoded, new stripes are written after it, and the
+    /// shard headers are rewritten with the new length only once all stripes are in place. The
+    /// trailer stripe of streamed sets is rewritten after the new stripes instead.
+    pub fn append<R: Read, S: Read + Write + Seek>(
+        &self,
+        data: &mut R,
+        length: usize,
+        shards: &mut [S],
+    ) -> std::io::Result<()> {
+        assert_eq!(shards.len(), self.data_shards + self.parity_shards);
+        self.check_rewritable()?;
+
+        let layout = self.layout();
+        let mut decoder = StripeDecoder::new(self);
+
+        let (old_length, first_stripe, filled, mut encoder) = {
+            let mut shard_readers: Vec<Option<&mut S>> = shards.iter_mut().map(Some).collect();
+
+            for reader in shard_readers.iter_mut().flatten() {
+                reader.seek(SeekFrom::Start(0))?;
+            }
+
+            decoder.read_headers(&mut shard_readers)?;
+            let old_length = decoder.read_trailer(&mut shard_readers)?;
+            if decoder.set().framing.is_some() {
+                return Result::Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "Appending to a compressed shard set is not supported",
+                ));
+            }
+
+            let first_stripe = layout.locate(old_length).stripe;
+            let filled = old_length - layout.stripe_start(first_stripe);
+            let mut encoder = StripeEncoder::new(self, decoder.checksum())
+                .with_monitor(self.monitor.start(Some(filled + length)));
+
+            if filled > 0 {
+                decoder.seek_to_stripe(&mut shard_readers, first_stripe);
+                decoder.decode_stripe(&mut shard_readers)?;
+                encoder.block_mut()[..filled].copy_from_slice(&decoder.block()[..filled]);
+            }
+
+            (old_length, first_stripe, filled, encoder)
+        };
+
+        encoder.seek_to_stripe(first_stripe);
+
+        for shard in shards.iter_mut() {
+            seek_to_chunk(shard, &layout, first_stripe)?;
+        }
+
+        encoder.encode_stripes(data, filled, length, shards)?;
+
+        if decoder.set().streamed {
+            let trailer_stripe = layout.stripes(old_length + length);
+            encoder.seek_to_stripe(trailer_stripe);
+
+            for shard in shards.iter_mut() {
+                seek_to_chunk(shard, &layout, trailer_stripe)?;
+            }
+
+            encoder.encode_trailer(old_length + length, shards)?;
+        }
+
+        for shard in shards.iter_mut() {
+            shard.flush()?;
+            shard.seek(SeekFrom::Start(0))?;
+        }
+
+        let mut set = decoder.set();
+        if !set.streamed {
+            set.length = old_length + length;
+        }
+        self.write_headers(shards, set)?;
+
+        for shard in shards.iter_mut() {
+            shard.flush()?;
+        }
+
         Result::Ok(())
     }
 
+    pub fn decode<R: Read, W: Write>(
+        &self,
+        shard_readers: &mut [Option<R>],
+        output: &mut W,
+    ) -> std::io::Result<()> {
+        assert_eq!(shard_readers.len(), self.data_shards + self.parity_shards);
+
+        let decoder = StripeDecoder::new(self).with_monitor(self.monitor.start(None));
+        stripe::decode(decoder, shard_readers, output)
+    }
+
+    /// Reads every chunk of a shard set and validates it without decoding anything, returning
+    /// the number of damaged chunks of each shard. All chunks of a missing shard or of one whose
+    /// header is unusable count as damaged.
+    pub fn verify<R: Read>(&self, shard_readers: &mut [Option<R>]) -> std::io::Result<Vec<usize>> {
+        assert_eq!(shard_readers.len(), self.data_shards + self.parity_shards);
+
+        let decoder = StripeDecoder::new(self).with_monitor(self.monitor.start(None));
+        stripe::verify(decoder, shard_readers)
+    }
+
+    /// Rebuilds a single lost shard into `output` from the other shards, decoding every stripe
+    /// and encoding the chunk of the lost shard again. The reader for the lost shard itself, if
+    /// any, is ignored.
+    pub fn repair<R: Read + Seek, W: Write>(
+        &self,
+        shard: usize,
+        shard_readers: &mut [Option<R>],
+        output: &mut W,
+    ) -> std::io::Result<()> {
+        assert_eq!(shard_readers.len(), self.data_shards + self.parity_shards);
+        assert!(shard < self.data_shards + self.parity_shards);
+
+        shard_readers[shard] = None;
+
+        let mut decoder = StripeDecoder::new(self);
+
+        for shard_reader in shard_readers.iter_mut() {
+            if let Some(reader) = shard_reader
+                && reader.seek(SeekFrom::Start(0)).is_err()
+            {
+                *shard_reader = None;
+            }
+        }
+
+        decoder.read_headers(shard_readers)?;
+        let length = decoder.read_trailer(shard_readers)?;
+        let set = decoder.set();
+        self.write_header(output, shard, set)?;
+
+        let encoding_row = Matrix::<Gf8>::encoding_matrix(self.data_shards, self.parity_shards)
+            .select_rows(&[shard]);
+        let checksum = decoder.checksum();
+        let mut monitor = self.monitor.start(Some(length));
+
+        // The trailer stripe of streamed sets is rebuilt as well.
+        for stripe in 0..self.layout().stripes(length) + set.streamed as usize {
+            decoder.decode_stripe(shard_readers)?;
+
+            let rebuilt = &encoding_row * decoder.data_matrix();
+            write_chunk(output, checksum, shard, stripe, rebuilt.as_bytes())?;
+
+            monitor.stripe_done(self.layout().block_size(), 1)?;
+        }
+
+        output.flush()
+    }
+
+    /// Decodes `length` bytes of the data starting at `offset`, reading only the stripes that
+    /// hold them, or, for compressed sets, the frames that do.
     pub fn decode_at<R: Read + Seek, W: Write>(
-        data: &[Option<R>],
-        output: W,
+        &self,
+        shard_readers: &mut [Option<R>],
+        output: &mut W,
         offset: usize,
         length: usize,
     ) -> std::io::Result<()> {
+        assert_eq!(shard_readers.len(), self.data_shards + self.parity_shards);
+
+        let decoder = StripeDecoder::new(self).with_monitor(self.monitor.start(None));
+        stripe::decode_at(decoder, shard_readers, output, offset, length)
+    }
+
+    /// Orders shard files found without knowing which shard each of them holds, such as through a
+    /// glob, by the shard index recorded in their headers. Files without a valid header for this
+    /// encoding are dropped, as are files of other shard sets than the one most files belong to
+    /// and duplicates of a shard. The remaining readers are left at the start of their files.
+    pub fn arrange_shards<R: Read + Seek>(
+        &self,
+        readers: Vec<R>,
+    ) -> std::io::Result<Vec<Option<R>>> {
+        let decoder = StripeDecoder::new(self);
+        let mut found = Vec::with_capacity(readers.len());
+        let mut tallies: Vec<(SetId, usize)> = vec![];
+
+        for mut reader in readers {
+            reader.seek(SeekFrom::Start(0))?;
+
+            let header = match read_header(&mut reader, &self.key) {
+                Result::Ok(header) if decoder.matches(header.shard, &header) => header,
+                _ => continue,
+            };
+
+            reader.seek(SeekFrom::Start(0))?;
+
+            match tallies
+                .iter_mut()
+                .find(|(set_id, _)| *set_id == header.set.set_id)
+            {
+                Some((_, count)) => *count += 1,
+                None => tallies.push((header.set.set_id, 1)),
+            }
+
+            found.push((header.shard, header.set.set_id, reader));
+        }
+
+        let mut shards: Vec<Option<R>> = (0..self.data_shards + self.parity_shards)
+            .map(|_| None)
+            .collect();
+
+        let Some(&(set_id, _)) = tallies.iter().max_by_key(|&&(_, count)| count) else {
+            return Result::Ok(shards);
+        };
+
+        for (shard, shard_set_id, reader) in found {
+            if shard_set_id == set_id && shards[shard].is_none() {
+                shards[shard] = Some(reader);
+            }
+        }
+
+        Result::Ok(shards)
+    }
+
+    /// Re-encodes a shard set under the parameters of `target`, streaming one stripe at a time.
+    /// Erased or corrupted source chunks are reconstructed along the way. The length of streamed
+    /// shard sets is read from their trailer and recorded in the headers of the new shards.
+    pub fn transcode<R: Read + Seek, W: Write>(
+        &self,
+        shard_readers: &mut [Option<R>],
+        target: &ReedSolomonEncoder,
+        shard_writers: &mut [W],
+    ) -> std::io::Result<()> {
+        assert_eq!(shard_readers.len(), self.data_shards + self.parity_shards);
+        assert_eq!(
+            shard_writers.len(),
+            target.data_shards + target.parity_shards
+        );
+
+        let mut decoder = StripeDecoder::new(self).with_monitor(self.monitor.start(None));
+        decoder.read_headers(shard_readers)?;
+        let length = decoder.read_trailer(shard_readers)?;
+
+        let set = SetInfo {
+            set_id: target.new_set_id()?,
+            length,
+            sparse: false,
+            streamed: false,
+            ..decoder.set()
+        };
+        target.write_headers(shard_writers, set)?;
+
+        let mut encoder = StripeEncoder::new(target, target.chunk_checksum(&set.set_id)?);
+        let mut data = StripeReader::new(decoder, shard_readers, length);
+        encoder.encode_stripes(&mut data, 0, length, shard_writers)
+    }
+
+    /// Transcodes a shard set to `target` when it only adds parity shards, i.e. the number of
+    /// data shards, the chunk size and the checksum are unchanged. Since the encoding matrix for
+    /// a larger number of parity shards extends the existing one, only the new parity shards are
+    /// written to `parity_writers`; the existing shards merely have their headers rewritten
+    /// afterwards. The key of keyed shard sets cannot change either, as the existing chunks
+    /// keep theirs.
+    pub fn add_parity<S: Read + Write + Seek, W: Write>(
+        &self,
+        shards: &mut [Option<S>],
+        target: &ReedSolomonEncoder,
+        parity_writers: &mut [W],
+    ) -> std::io::Result<()> {
+        assert_eq!(shards.len(), self.data_shards + self.parity_shards);
+        if target.data_shards != self.data_shards
+            || target.chunk_size != self.chunk_size
+            || target.checksum != self.checksum
+            || target.parity_shards < self.parity_shards
+        {
+            return Result::Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Adding parity shards cannot change the data shards, chunk size or checksum",
+            ));
+        }
+        assert_eq!(
+            parity_writers.len(),
+            target.parity_shards - self.parity_shards
+        );
+
+        let mut decoder = StripeDecoder::new(self).with_monitor(self.monitor.start(None));
+
+        for shard in shards.iter_mut() {
+            if let Some(reader) = shard
+                && reader.seek(SeekFrom::Start(0)).is_err()
+            {
+                *shard = None;
+            }
+        }
+
+        decoder.read_headers(shards)?;
+        let length = decoder.read_trailer(shards)?;
+        let set = decoder.set();
+
+        let checksum = target.chunk_checksum(&set.set_id)?;
+        if checksum != self.chunk_checksum(&set.set_id)? {
+            return Result::Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Adding parity shards cannot change the key of the shard set",
+            ));
+        }
+
+        let mut encoder = StripeEncoder::with_parity_shards(
+            target,
+            self.parity_shards..target.parity_shards,
+            checksum,
+        );
+
+        for (i, writer) in parity_writers.iter_mut().enumerate() {
+            let shard = self.data_shards + self.parity_shards + i;
+            target.write_header(writer, shard, set)?;
+        }
+
+        // The trailer stripe of streamed sets needs parity chunks as well.
+        for _ in 0..self.layout().stripes(length) + set.streamed as usize {
+            decoder.decode_stripe(shards)?;
+            encoder.block_mut().copy_from_slice(decoder.block());
+            encoder.encode_parity(parity_writers)?;
+        }
+
+        for writer in parity_writers.iter_mut() {
+            writer.flush()?;
+        }
+
+        for (index, shard) in shards.iter_mut().enumerate() {
+            if let Some(shard) = shard {
+                shard.seek(SeekFrom::Start(0))?;
+                target.write_header(shard, index, set)?;
+                shard.flush()?;
+            }
+        }
+
         Result::Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rand::rngs::StdRng;
-    use rand::{RngCore, SeedableRng};
-    use std::io::Cursor;
+    pub fn update_parity<S: Read + Write + Seek>(
+        &self,
+        stripe: usize,
+        data_shard: usize,
+        old_chunk: &[u8],
+        new_chunk: &[u8],
+        parity_shards: &mut [S],
+    ) -> std::io::Result<()> {
+        assert!(data_shard < self.data_shards);
+        assert_eq!(old_chunk.len(), self.chunk_size);
+        assert_eq!(new_chunk.len(), self.chunk_size);
+        assert_eq!(parity_shards.len(), self.parity_shards);
+        self.check_rewritable()?;
 
-    #[test]
-    fn encode() {
-        let mut rng = StdRng::from_seed([42u8; 32]);
+        let layout = self.layout();
+        let encoding_matrix = Matrix::<Gf8>::encoding_matrix(self.data_shards, self.parity_shards);
 
-        let mut buffer = vec![0u8; 16 * 1024];
-        rng.fill_bytes(&mut buffer);
+        let delta: Vec<u8> = old_chunk
+            .iter()
+            .zip(new_chunk)
+            .map(|(&old, &new)| old ^ new)
+            .collect();
 
-        let mut reader = Cursor::new(buffer);
+        // Every parity shard must be the one of its position in a set of this encoder, and they
+        // must all belong to the same set, before any of them is modified.
+        let mut set = None;
+        for (parity_shard, shard) in parity_shards.iter_mut().enumerate() {
+            shard.seek(SeekFrom::Start(0))?;
+            let header = read_header(shard, &self.key)?;
 
-        let mut writers: Vec<Cursor<Vec<u8>>> =
-            (0..6).map(|_| Cursor::new(Vec::<u8>::new())).collect();
+            let expected = ShardHeader {
+                shard: self.data_shards + parity_shard,
+                data_shards: self.data_shards,
+                parity_shards: self.parity_shards,
+                local_parity_shards: 0,
+                chunk_size: self.chunk_size,
+                checksum: self.checksum,
+                set: *set.get_or_insert(header.set),
+            };
 
-        ReedSolomonEncoder::new(4, 2, 1024)
-            .encode(&mut reader, 16 * 1024, &mut writers)
+            if header != expected {
+                return Result::Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "Shard {} does not belong to the shard set of this encoder",
+                        self.data_shards + parity_shard
+                    ),
+                ));
+            }
+        }
+
+        let Some(set) = set else {
+            return Result::Ok(());
+        };
+
+        // Keyed checksums depend on the set ID.
+        let checksum = self.chunk_checksum(&set.set_id)?;
+        let mut chunk = vec![0u8; self.chunk_size];
+
+        for (parity_shard, shard) in parity_shards.iter_mut().enumerate() {
+            let coefficient = encoding_matrix[self.data_shards + parity_shard][data_shard];
+
+            if coefficient == Gf8(0) {
+                continue;
+            }
+
+            let shard_index = self.data_shards + parity_shard;
+
+            seek_to_chunk(shard, &layout, stripe)?;
+            read_chunk(shard, checksum, shard_index, stripe, &mut chunk)?;
+
+            coefficient.mul_add_slice(&delta, &mut chunk);
+
+            seek_to_chunk(shard, &layout, stripe)?;
+            write_chunk(shard, checksum, shard_index, stripe, &chunk)?;
+        }
+
+        Result::Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::HEADER_SIZE;
+    use crate::io::is_zero;
+    use crate::test_util::{encode_shards, shard_readers};
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+    use std::io::Cursor;
+
+    /// Size of the default 128-bit xxh3 chunk checksum.
+    const HASH_SIZE: usize = 16;
+
+    #[test]
+    fn encode() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+
+        let mut buffer = vec![0u8; 16 * 1024];
+        rng.fill_bytes(&mut buffer);
+
+        let mut reader = Cursor::new(buffer);
+
+        let mut writers: Vec<Cursor<Vec<u8>>> =
+            (0..6).map(|_| Cursor::new(Vec::<u8>::new())).collect();
+
+        ReedSolomonEncoder::new(4, 2, 1024)
+            .encode(&mut reader, 16 * 1024, &mut writers)
+            .unwrap();
+    }
+
+    #[test]
+    fn decode() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+
+        for length in [0, 1, 4095, 4096, 4097, 16 * 1024 + 17] {
+            let (buffer, shards) = encode_shards(&encoder, &mut rng, length);
+
+            for missing in [vec![], vec![0], vec![3, 5], vec![1, 2]] {
+                let mut output = vec![];
+                encoder
+                    .decode(&mut shard_readers(&shards, &missing), &mut output)
+                    .unwrap();
+                assert!(output == buffer, "length {}, missing {:?}", length, missing);
+            }
+
+            let mut output = vec![];
+            let result = encoder.decode(&mut shard_readers(&shards, &[0, 1, 2]), &mut output);
+            assert!(result.is_err() == (length > 0));
+        }
+    }
+
+    #[test]
+    fn decode_corrupted_chunks() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let (buffer, mut shards) = encode_shards(&encoder, &mut rng, 16 * 1024);
+
+        shards[0][HEADER_SIZE + HASH_SIZE + 3] ^= 0x01;
+        shards[1][10] ^= 0x20;
+        shards[2][HEADER_SIZE + 2 * (HASH_SIZE + 1024) + 100] ^= 0x80;
+        shards[5].truncate(HEADER_SIZE + 3 * (HASH_SIZE + 1024) + 5);
+
+        let mut output = vec![];
+        encoder
+            .decode(&mut shard_readers(&shards, &[]), &mut output)
+            .unwrap();
+        assert!(output == buffer);
+    }
+
+    #[test]
+    fn decode_damaged_and_healthy_stripes() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let length = 8 * 4096 - 100;
+        let (buffer, mut shards) = encode_shards(&encoder, &mut rng, length);
+
+        let stride = HASH_SIZE + 1024;
+        for (shard, stripe) in [(1, 1), (1, 2), (4, 3), (0, 5), (3, 5), (5, 6)] {
+            shards[shard][HEADER_SIZE + stripe * stride + HASH_SIZE + 7] ^= 0x04;
+        }
+
+        let mut output = vec![];
+        encoder
+            .decode(&mut shard_readers(&shards, &[]), &mut output)
+            .unwrap();
+        assert!(output == buffer);
+
+        for (offset, range_length) in [(0, length), (3000, 10000), (20000, 4097)] {
+            let mut output = vec![];
+            encoder
+                .decode_at(
+                    &mut shard_readers(&shards, &[]),
+                    &mut output,
+                    offset,
+                    range_length,
+                )
+                .unwrap();
+            assert!(output == buffer[offset..offset + range_length]);
+        }
+    }
+
+    #[test]
+    fn verify() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let (_, mut shards) = encode_shards(&encoder, &mut rng, 16 * 1024);
+
+        let damaged = encoder.verify(&mut shard_readers(&shards, &[])).unwrap();
+        assert_eq!(damaged, vec![0; 6]);
+
+        shards[0][HEADER_SIZE + HASH_SIZE + 3] ^= 0x01;
+        shards[1][10] ^= 0x20;
+        shards[2][HEADER_SIZE + 2 * (HASH_SIZE + 1024) + 100] ^= 0x80;
+        shards[5].truncate(HEADER_SIZE + 3 * (HASH_SIZE + 1024) + 5);
+
+        let damaged = encoder.verify(&mut shard_readers(&shards, &[4])).unwrap();
+        assert_eq!(damaged, vec![1, 4, 1, 0, 4, 1]);
+    }
+
+    #[test]
+    fn checksums() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+
+        for checksum in [
+            ChecksumKind::None,
+            ChecksumKind::Crc32c,
+            ChecksumKind::Xxh3_64,
+            ChecksumKind::Xxh3_128,
+            ChecksumKind::Blake3,
+        ] {
+            let encoder = ReedSolomonEncoder::new(4, 2, 1024).with_checksum(checksum);
+            let (buffer, mut shards) = encode_shards(&encoder, &mut rng, 16 * 1024 + 17);
+
+            let stride = checksum.size() + 1024;
+            assert_eq!(shards[0].len(), HEADER_SIZE + 5 * stride);
+
+            let mut output = vec![];
+            encoder
+                .decode(&mut shard_readers(&shards, &[1, 4]), &mut output)
+                .unwrap();
+            assert!(output == buffer, "checksum {}", checksum);
+
+            shards[0][HEADER_SIZE + stride + checksum.size() + 3] ^= 0x01;
+            shards[3][HEADER_SIZE + 2 * stride + checksum.size() + 9] ^= 0x40;
+
+            let mut output = vec![];
+            encoder
+                .decode(&mut shard_readers(&shards, &[]), &mut output)
+                .unwrap();
+            assert!(
+                (output == buffer) == (checksum != ChecksumKind::None),
+                "checksum {}",
+                checksum
+            );
+
+            let mismatched = ReedSolomonEncoder::new(4, 2, 1024).with_checksum(
+                if checksum == ChecksumKind::Blake3 {
+                    ChecksumKind::Crc32c
+                } else {
+                    ChecksumKind::Blake3
+                },
+            );
+            let mut output = vec![];
+            assert!(
+                mismatched
+                    .decode(&mut shard_readers(&shards, &[]), &mut output)
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn keyed_checksum_detects_tampering() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024).with_key([9u8; KEY_SIZE]);
+        let (buffer, shards) = encode_shards(&encoder, &mut rng, 16 * 1024);
+
+        let stride = HASH_SIZE * 2 + 1024;
+        let chunk =
+            |stripe: usize| HEADER_SIZE + stripe * stride..HEADER_SIZE + (stripe + 1) * stride;
+
+        let mut moved = shards.clone();
+        let stripe_2 = moved[0][chunk(2)].to_vec();
+        moved[0][chunk(1)].copy_from_slice(&stripe_2);
+        let shard_2 = moved[2][chunk(3)].to_vec();
+        moved[1][chunk(3)].copy_from_slice(&shard_2);
+
+        let mut output = vec![];
+        encoder
+            .decode(&mut shard_readers(&moved, &[]), &mut output)
+            .unwrap();
+        assert!(output == buffer);
+
+        let mut rehashed = shards.clone();
+        let start = chunk(2).start;
+        rehashed[3][start + 2 * HASH_SIZE] ^= 0x01;
+        let hash = blake3::hash(&rehashed[3][start + 2 * HASH_SIZE..chunk(2).end]);
+        rehashed[3][start..start + 2 * HASH_SIZE].copy_from_slice(hash.as_bytes());
+
+        let mut output = vec![];
+        encoder
+            .decode(&mut shard_readers(&rehashed, &[]), &mut output)
+            .unwrap();
+        assert!(output == buffer);
+
+        let mut output = vec![];
+        assert!(
+            ReedSolomonEncoder::new(4, 2, 1024)
+                .with_key([8u8; KEY_SIZE])
+                .decode(&mut shard_readers(&shards, &[]), &mut output)
+                .is_err()
+        );
+
+        // Chunks of another shard set under the same key do not verify.
+        let (_, other_shards) = encode_shards(&encoder, &mut rng, 16 * 1024);
+        let mut spliced = shards.clone();
+        for shard in 0..3 {
+            spliced[shard][chunk(1)].copy_from_slice(&other_shards[shard][chunk(1)]);
+        }
+
+        let mut output = vec![];
+        assert!(
+            encoder
+                .decode(&mut shard_readers(&spliced, &[]), &mut output)
+                .is_err()
+        );
+
+        // Nor do headers whose length was changed and whose xxh3 hash was recomputed.
+        let mut truncated = shards.clone();
+        for shard in truncated.iter_mut() {
+            shard[16..24].copy_from_slice(&100u64.to_be_bytes());
+            let hash = xxhash_rust::xxh3::xxh3_128(&shard[..HEADER_SIZE - 16]);
+            shard[HEADER_SIZE - 16..HEADER_SIZE].copy_from_slice(&hash.to_be_bytes());
+        }
+
+        let mut output = vec![];
+        assert!(
+            encoder
+                .decode(&mut shard_readers(&truncated, &[]), &mut output)
+                .is_err()
+        );
+
+        // Nor do chunks zeroed out as if they were holes in a sparse set.
+        let mut zeroed = shards.clone();
+        for shard in zeroed.iter_mut() {
+            shard[26] |= 0x80;
+            let hash = xxhash_rust::xxh3::xxh3_128(&shard[..HEADER_SIZE - 16]);
+            shard[HEADER_SIZE - 16..HEADER_SIZE].copy_from_slice(&hash.to_be_bytes());
+        }
+        for shard in zeroed.iter_mut().take(3) {
+            shard[chunk(1)].fill(0);
+        }
+
+        let mut output = vec![];
+        assert!(
+            encoder
+                .decode(&mut shard_readers(&zeroed, &[]), &mut output)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn decode_at() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let length = 16 * 1024 + 17;
+        let (buffer, shards) = encode_shards(&encoder, &mut rng, length);
+
+        for (offset, range_length) in [(0, 0), (0, 10), (4000, 200), (10000, 6401), (0, length)] {
+            let mut output = vec![];
+            encoder
+                .decode_at(
+                    &mut shard_readers(&shards, &[1, 4]),
+                    &mut output,
+                    offset,
+                    range_length,
+                )
+                .unwrap();
+            assert!(output == buffer[offset..offset + range_length]);
+        }
+
+        let mut output = vec![];
+        assert!(
+            encoder
+                .decode_at(&mut shard_readers(&shards, &[]), &mut output, length - 1, 2)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn repair() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let (_, mut shards) = encode_shards(&encoder, &mut rng, 16 * 1024 + 17);
+
+        for shard in 0..6 {
+            let mut output = vec![];
+            encoder
+                .repair(shard, &mut shard_readers(&shards, &[]), &mut output)
+                .unwrap();
+            assert!(output == shards[shard], "shard {}", shard);
+        }
+
+        shards[2][HEADER_SIZE + 3 * (HASH_SIZE + 1024) + 40] ^= 0x10;
+        let mut output = vec![];
+        encoder
+            .repair(5, &mut shard_readers(&shards, &[]), &mut output)
+            .unwrap();
+        assert!(output == shards[5]);
+
+        let mut output = vec![];
+        let result = encoder.repair(5, &mut shard_readers(&shards, &[0]), &mut output);
+        assert!(result.is_err());
+
+        let encoder =
+            ReedSolomonEncoder::new(4, 2, 1024).with_encryption(Arc::new([6u8; KEY_SIZE]));
+        let mut buffer = vec![0u8; 10000];
+        rng.fill_bytes(&mut buffer);
+        let shards: Vec<Vec<u8>> = encode_streamed_shards(&encoder, &buffer)
+            .into_iter()
+            .map(Cursor::into_inner)
+            .collect();
+
+        for shard in [0, 3, 4] {
+            let mut output = vec![];
+            encoder
+                .repair(shard, &mut shard_readers(&shards, &[1]), &mut output)
+                .unwrap();
+            assert!(output == shards[shard], "shard {}", shard);
+        }
+    }
+
+    #[test]
+    fn update_parity() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+
+        for encoder in [
+            ReedSolomonEncoder::new(4, 2, 1024),
+            ReedSolomonEncoder::new(4, 2, 1024).with_key([5u8; KEY_SIZE]),
+        ] {
+            let (mut buffer, mut shards) = encode_shards(&encoder, &mut rng, 16 * 1024);
+
+            let stripe = 2;
+            let data_shard = 1;
+            let checksum_size = encoder.checksum().size();
+            let chunk_start = HEADER_SIZE + stripe * (checksum_size + 1024) + checksum_size;
+
+            let old_chunk = shards[data_shard][chunk_start..chunk_start + 1024].to_vec();
+            let mut new_chunk = old_chunk.clone();
+            rng.fill_bytes(&mut new_chunk[100..300]);
+
+            // Parity shards out of order or of another set are refused untouched.
+            let (_, other_shards) = encode_shards(&encoder, &mut rng, 16 * 1024);
+            for mut parity_shards in [
+                vec![shards[5].clone(), shards[4].clone()],
+                vec![shards[4].clone(), other_shards[5].clone()],
+            ] {
+                let untouched = parity_shards.clone();
+                let mut cursors: Vec<Cursor<Vec<u8>>> =
+                    parity_shards.drain(..).map(Cursor::new).collect();
+                let error = encoder
+                    .update_parity(stripe, data_shard, &old_chunk, &new_chunk, &mut cursors)
+                    .unwrap_err();
+                assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+                assert!(
+                    cursors
+                        .iter()
+                        .zip(&untouched)
+                        .all(|(cursor, untouched)| cursor.get_ref() == untouched)
+                );
+            }
+
+            let mut parity_shards: Vec<Cursor<Vec<u8>>> =
+                shards[4..].iter().cloned().map(Cursor::new).collect();
+            encoder
+                .update_parity(
+                    stripe,
+                    data_shard,
+                    &old_chunk,
+                    &new_chunk,
+                    &mut parity_shards,
+                )
+                .unwrap();
+
+            for (parity_shard, cursor) in parity_shards.into_iter().enumerate() {
+                shards[4 + parity_shard] = cursor.into_inner();
+            }
+
+            let buffer_start = stripe * 4 * 1024 + data_shard * 1024;
+            buffer[buffer_start..buffer_start + 1024].copy_from_slice(&new_chunk);
+
+            let mut output = vec![];
+            encoder
+                .decode(&mut shard_readers(&shards, &[data_shard, 3]), &mut output)
+                .unwrap();
+            assert!(output == buffer);
+        }
+    }
+
+    #[test]
+    fn append() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+
+        for (length, appended_lengths) in [
+            (0, vec![0, 1, 4095]),
+            (4096, vec![4096, 10]),
+            (5000, vec![3000, 200, 20000]),
+        ] {
+            let (mut buffer, shards) = encode_shards(&encoder, &mut rng, length);
+            let mut shards: Vec<Cursor<Vec<u8>>> = shards.into_iter().map(Cursor::new).collect();
+
+            for appended_length in appended_lengths {
+                let mut appended = vec![0u8; appended_length];
+                rng.fill_bytes(&mut appended);
+
+                let first_stripe = buffer.len() / 4096;
+                let untouched: Vec<Vec<u8>> = shards
+                    .iter()
+                    .map(|shard| {
+                        shard.get_ref()
+                            [HEADER_SIZE..HEADER_SIZE + first_stripe * (HASH_SIZE + 1024)]
+                            .to_vec()
+                    })
+                    .collect();
+
+                encoder
+                    .append(&mut Cursor::new(&appended), appended_length, &mut shards)
+                    .unwrap();
+                buffer.extend_from_slice(&appended);
+
+                for (shard, untouched) in shards.iter().zip(untouched) {
+                    assert!(shard.get_ref()[HEADER_SIZE..].starts_with(&untouched));
+                }
+
+                let contents: Vec<Vec<u8>> =
+                    shards.iter().map(|shard| shard.get_ref().clone()).collect();
+
+                for missing in [vec![], vec![0, 3]] {
+                    let mut output = vec![];
+                    encoder
+                        .decode(&mut shard_readers(&contents, &missing), &mut output)
+                        .unwrap();
+                    assert!(output == buffer);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn append_with_erased_final_stripe() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let (mut buffer, mut shards) = encode_shards(&encoder, &mut rng, 6000);
+
+        shards[1][HEADER_SIZE + HASH_SIZE + 1024 + 7] ^= 0x01;
+
+        let mut shards: Vec<Cursor<Vec<u8>>> = shards.into_iter().map(Cursor::new).collect();
+
+        let mut appended = vec![0u8; 1000];
+        rng.fill_bytes(&mut appended);
+        encoder
+            .append(&mut Cursor::new(&appended), 1000, &mut shards)
+            .unwrap();
+        buffer.extend_from_slice(&appended);
+
+        let contents: Vec<Vec<u8>> = shards.into_iter().map(Cursor::into_inner).collect();
+
+        let mut output = vec![];
+        encoder
+            .decode(&mut shard_readers(&contents, &[4, 5]), &mut output)
+            .unwrap();
+        assert!(output == buffer);
+    }
+
+    #[test]
+    fn transcode() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let source = ReedSolomonEncoder::new(4, 2, 1024);
+        let (buffer, shards) = encode_shards(&source, &mut rng, 50000);
+
+        for target in [
+            ReedSolomonEncoder::new(10, 4, 1024),
+            ReedSolomonEncoder::new(3, 1, 100),
+            ReedSolomonEncoder::new(4, 2, 1024),
+        ] {
+            let mut writers: Vec<Cursor<Vec<u8>>> = (0..target.data_shards + target.parity_shards)
+                .map(|_| Cursor::new(Vec::<u8>::new()))
+                .collect();
+
+            source
+                .transcode(&mut shard_readers(&shards, &[0, 5]), &target, &mut writers)
+                .unwrap();
+
+            let transcoded: Vec<Vec<u8>> = writers.into_iter().map(Cursor::into_inner).collect();
+
+            let mut output = vec![];
+            target
+                .decode(&mut shard_readers(&transcoded, &[1]), &mut output)
+                .unwrap();
+            assert!(output == buffer);
+        }
+    }
+
+    #[test]
+    fn add_parity() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let source = ReedSolomonEncoder::new(4, 2, 1024);
+        let target = ReedSolomonEncoder::new(4, 5, 1024);
+        let (buffer, shards) = encode_shards(&source, &mut rng, 50000);
+
+        let mut existing: Vec<Option<Cursor<Vec<u8>>>> = shard_readers(&shards, &[2]);
+        let mut writers: Vec<Cursor<Vec<u8>>> =
+            (0..3).map(|_| Cursor::new(Vec::<u8>::new())).collect();
+
+        source
+            .add_parity(&mut existing, &target, &mut writers)
+            .unwrap();
+
+        let mut contents: Vec<Vec<u8>> = existing
+            .into_iter()
+            .map(|shard| shard.map(Cursor::into_inner).unwrap_or_default())
+            .collect();
+        contents.extend(writers.into_iter().map(Cursor::into_inner));
+
+        for (shard, contents) in contents.iter().enumerate().take(6) {
+            if shard != 2 {
+                assert!(contents[HEADER_SIZE..] == shards[shard][HEADER_SIZE..]);
+            }
+        }
+
+        let mut output = vec![];
+        target
+            .decode(&mut shard_readers(&contents, &[0, 1, 2, 4, 7]), &mut output)
+            .unwrap();
+        assert!(output == buffer);
+    }
+
+    #[test]
+    fn keyed_append_and_add_parity() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let key = [5u8; KEY_SIZE];
+        let source = ReedSolomonEncoder::new(4, 2, 1024).with_key(key);
+        let target = ReedSolomonEncoder::new(4, 4, 1024).with_key(key);
+        let (mut buffer, shards) = encode_shards(&source, &mut rng, 5000);
+
+        let mut appended = vec![0u8; 20000];
+        rng.fill_bytes(&mut appended);
+        buffer.extend_from_slice(&appended);
+
+        let mut shards: Vec<Cursor<Vec<u8>>> = shards.into_iter().map(Cursor::new).collect();
+        source
+            .append(&mut Cursor::new(&appended), appended.len(), &mut shards)
+            .unwrap();
+
+        let mut existing: Vec<Option<Cursor<Vec<u8>>>> = shards.into_iter().map(Some).collect();
+        let mut writers: Vec<Cursor<Vec<u8>>> =
+            (0..2).map(|_| Cursor::new(Vec::<u8>::new())).collect();
+
+        // Neither the key nor the other parameters of the set can change in place.
+        let untouched: Vec<Vec<u8>> = existing
+            .iter()
+            .map(|shard| shard.as_ref().unwrap().get_ref().clone())
+            .collect();
+        for other in [
+            ReedSolomonEncoder::new(4, 4, 1024).with_key([6u8; KEY_SIZE]),
+            ReedSolomonEncoder::new(4, 4, 512).with_key(key),
+            ReedSolomonEncoder::new(4, 1, 1024).with_key(key),
+        ] {
+            let error = source
+                .add_parity(&mut existing, &other, &mut writers)
+                .unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        }
+        assert!(
+            existing
+                .iter()
+                .zip(&untouched)
+                .all(|(shard, untouched)| shard.as_ref().unwrap().get_ref() == untouched)
+        );
+        assert!(writers.iter().all(|writer| writer.get_ref().is_empty()));
+
+        source
+            .add_parity(&mut existing, &target, &mut writers)
+            .unwrap();
+
+        let mut contents: Vec<Vec<u8>> = existing
+            .into_iter()
+            .map(|shard| shard.unwrap().into_inner())
+            .collect();
+        contents.extend(writers.into_iter().map(Cursor::into_inner));
+
+        let mut output = vec![];
+        target
+            .decode(&mut shard_readers(&contents, &[0, 1, 2, 4]), &mut output)
+            .unwrap();
+        assert!(output == buffer);
+    }
+
+    #[test]
+    fn encryption() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder =
+            ReedSolomonEncoder::new(4, 2, 1024).with_encryption(Arc::new([7u8; KEY_SIZE]));
+        let length = 16 * 1024 + 17;
+        let (buffer, mut shards) = encode_shards(&encoder, &mut rng, length);
+
+        let stride = HASH_SIZE + 1024;
+        let first_chunk = HEADER_SIZE + HASH_SIZE..HEADER_SIZE + stride;
+        assert!(shards[0][first_chunk] != buffer[..1024]);
+
+        let mut output = vec![];
+        encoder
+            .decode(&mut shard_readers(&shards, &[1, 4]), &mut output)
+            .unwrap();
+        assert!(output == buffer);
+
+        shards[2][HEADER_SIZE + stride + HASH_SIZE + 5] ^= 0x01;
+        shards[5][HEADER_SIZE + 3 * stride + 2] ^= 0x01;
+
+        let mut output = vec![];
+        encoder
+            .decode_at(&mut shard_readers(&shards, &[0]), &mut output, 3000, 10000)
+            .unwrap();
+        assert!(output == buffer[3000..13000]);
+
+        let mut output = vec![];
+        assert!(
+            ReedSolomonEncoder::new(4, 2, 1024)
+                .with_encryption(Arc::new([8u8; KEY_SIZE]))
+                .decode(&mut shard_readers(&shards, &[]), &mut output)
+                .is_err()
+        );
+
+        let target = ReedSolomonEncoder::new(3, 1, 512);
+        let mut writers: Vec<Cursor<Vec<u8>>> =
+            (0..4).map(|_| Cursor::new(Vec::<u8>::new())).collect();
+        encoder
+            .transcode(&mut shard_readers(&shards, &[]), &target, &mut writers)
+            .unwrap();
+        let transcoded: Vec<Vec<u8>> = writers.into_iter().map(Cursor::into_inner).collect();
+
+        let mut output = vec![];
+        target
+            .decode(&mut shard_readers(&transcoded, &[]), &mut output)
+            .unwrap();
+        assert!(output == buffer);
+
+        let mut cursors: Vec<Cursor<Vec<u8>>> = shards.into_iter().map(Cursor::new).collect();
+        let error = encoder
+            .append(&mut Cursor::new(vec![0u8; 10]), 10, &mut cursors)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+
+        let chunk = vec![0u8; 1024];
+        let error = encoder
+            .update_parity(0, 0, &chunk, &chunk, &mut cursors[4..])
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+
+        // A fixed set ID only serves one encrypted shard set, whichever builder came first.
+        for encoder in [
+            ReedSolomonEncoder::new(4, 2, 1024)
+                .with_encryption(Arc::new([7u8; KEY_SIZE]))
+                .with_set_id(SetId::random()),
+            ReedSolomonEncoder::new(4, 2, 1024)
+                .with_set_id(SetId::random())
+                .with_encryption(Arc::new([7u8; KEY_SIZE])),
+        ] {
+            let mut writers: Vec<Cursor<Vec<u8>>> = (0..6).map(|_| Cursor::new(vec![])).collect();
+            encoder
+                .encode(&mut Cursor::new(&buffer), buffer.len(), &mut writers)
+                .unwrap();
+            let error = encoder
+                .encode(&mut Cursor::new(&buffer), buffer.len(), &mut writers)
+                .unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+        }
+    }
+
+    #[test]
+    fn decode_erases_shards_of_other_sets() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let (buffer, mut shards) = encode_shards(&encoder, &mut rng, 10000);
+        let (_, other_shards) = encode_shards(&encoder, &mut rng, 10000);
+
+        shards[1] = other_shards[1].clone();
+        shards[4] = other_shards[4].clone();
+
+        let mut output = vec![];
+        encoder
+            .decode(&mut shard_readers(&shards, &[]), &mut output)
+            .unwrap();
+        assert!(output == buffer);
+    }
+
+    fn encode_compressed_shards(
+        encoder: &ReedSolomonEncoder,
+        compression: Compression,
+        buffer: &[u8],
+    ) -> Vec<Vec<u8>> {
+        let mut writers: Vec<Cursor<Vec<u8>>> = (0..encoder.data_shards + encoder.parity_shards)
+            .map(|_| Cursor::new(Vec::<u8>::new()))
+            .collect();
+
+        encoder
+            .encode_compressed(
+                &mut Cursor::new(buffer),
+                buffer.len(),
+                compression,
+                &mut writers,
+            )
+            .unwrap();
+
+        writers.into_iter().map(Cursor::into_inner).collect()
+    }
+
+    #[test]
+    fn compression() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+
+        for kind in [CompressionKind::Zstd, CompressionKind::Lz4] {
+            let compression = Compression::new(kind).with_frame_size(5000);
+
+            for length in [0, 1, 4999, 5000, 5001, 40000] {
+                // Random bytes interleaved with runs of zeros so that frames do compress.
+                let mut buffer = vec![0u8; length];
+                for block in buffer.chunks_mut(100).step_by(2) {
+                    rng.fill_bytes(block);
+                }
+
+                let shards = encode_compressed_shards(&encoder, compression, &buffer);
+
+                for missing in [vec![], vec![0], vec![3, 5]] {
+                    let mut output = vec![];
+                    encoder
+                        .decode(&mut shard_readers(&shards, &missing), &mut output)
+                        .unwrap();
+                    assert!(output == buffer, "{} length {}", kind, length);
+                }
+
+                if length == 40000 {
+                    let encoded = encode_shards(&encoder, &mut rng, length).1;
+                    assert!(shards[0].len() < encoded[0].len());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn compressed_decode_at() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let compression = Compression::new(CompressionKind::Zstd).with_frame_size(3000);
+
+        let length = 16 * 1024 + 17;
+        let mut buffer = vec![0u8; length];
+        rng.fill_bytes(&mut buffer);
+        let shards = encode_compressed_shards(&encoder, compression, &buffer);
+
+        for (offset, range_length) in [
+            (0, 0),
+            (0, 10),
+            (2990, 20),
+            (3000, 3000),
+            (5000, 7000),
+            (length - 1, 1),
+            (0, length),
+        ] {
+            let mut output = vec![];
+            encoder
+                .decode_at(
+                    &mut shard_readers(&shards, &[1, 4]),
+                    &mut output,
+                    offset,
+                    range_length,
+                )
+                .unwrap();
+            assert!(output == buffer[offset..offset + range_length]);
+        }
+
+        let mut output = vec![];
+        let error = encoder
+            .decode_at(&mut shard_readers(&shards, &[]), &mut output, length - 1, 2)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+        let mut cursors: Vec<Cursor<Vec<u8>>> = shards.into_iter().map(Cursor::new).collect();
+        let error = encoder
+            .append(&mut Cursor::new(vec![0u8; 10]), 10, &mut cursors)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn sparse() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let layout = encoder.layout();
+
+        // Zero stripes, partially zero stripes and a final stripe that is mostly padding.
+        let length = 10 * 4096 + 100;
+        let mut buffer = vec![0u8; length];
+        rng.fill_bytes(&mut buffer[3 * 4096 + 1024..3 * 4096 + 2048]);
+        rng.fill_bytes(&mut buffer[7 * 4096 + 5..7 * 4096 + 9]);
+        rng.fill_bytes(&mut buffer[10 * 4096 + 50..]);
+
+        let mut writers: Vec<Cursor<Vec<u8>>> =
+            (0..6).map(|_| Cursor::new(Vec::<u8>::new())).collect();
+        encoder
+            .encode_sparse(&mut Cursor::new(&buffer), length, &mut writers)
+            .unwrap();
+        let mut shards: Vec<Vec<u8>> = writers.into_iter().map(Cursor::into_inner).collect();
+
+        // Only the checksums of zero chunks are written.
+        for shard in shards.iter() {
+            assert_eq!(shard.len() as u64, layout.shard_file_size(length));
+
+            let chunk = layout.chunk_offset(0) as usize..layout.chunk_offset(1) as usize;
+            assert!(!is_zero(&shard[chunk.start..chunk.start + HASH_SIZE]));
+            assert!(is_zero(&shard[chunk.start + HASH_SIZE..chunk.end]));
+        }
+
+        for missing in [vec![], vec![0], vec![1, 4], vec![3, 5]] {
+            let mut output = vec![];
+            encoder
+                .decode(&mut shard_readers(&shards, &missing), &mut output)
+                .unwrap();
+            assert!(output == buffer, "missing {:?}", missing);
+        }
+
+        let old_chunk = buffer[3 * 4096..3 * 4096 + 1024].to_vec();
+        let mut new_chunk = vec![0u8; 1024];
+        rng.fill_bytes(&mut new_chunk);
+
+        let mut parity_shards: Vec<Cursor<Vec<u8>>> =
+            shards[4..].iter().cloned().map(Cursor::new).collect();
+        encoder
+            .update_parity(3, 0, &old_chunk, &new_chunk, &mut parity_shards)
+            .unwrap();
+        for (parity_shard, cursor) in parity_shards.into_iter().enumerate() {
+            shards[4 + parity_shard] = cursor.into_inner();
+        }
+        buffer[3 * 4096..3 * 4096 + 1024].copy_from_slice(&new_chunk);
+
+        let mut output = vec![];
+        encoder
+            .decode(&mut shard_readers(&shards, &[0, 1]), &mut output)
+            .unwrap();
+        assert!(output == buffer);
+
+        // Fill in the hole that held the old chunk.
+        let mut data_shard = Cursor::new(std::mem::take(&mut shards[0]));
+        let checksum = encoder.chunk_checksum(&SetId::default()).unwrap();
+        seek_to_chunk(&mut data_shard, &layout, 3).unwrap();
+        write_chunk(&mut data_shard, checksum, 0, 3, &new_chunk).unwrap();
+        shards[0] = data_shard.into_inner();
+
+        let mut appended = vec![0u8; 5000];
+        rng.fill_bytes(&mut appended[4000..]);
+        let mut cursors: Vec<Cursor<Vec<u8>>> = shards.into_iter().map(Cursor::new).collect();
+        encoder
+            .append(&mut Cursor::new(&appended), appended.len(), &mut cursors)
+            .unwrap();
+        buffer.extend_from_slice(&appended);
+        let shards: Vec<Vec<u8>> = cursors.into_iter().map(Cursor::into_inner).collect();
+
+        let mut output = vec![];
+        encoder
+            .decode(&mut shard_readers(&shards, &[2, 5]), &mut output)
+            .unwrap();
+        assert!(output == buffer);
+
+        let keyed = ReedSolomonEncoder::new(4, 2, 1024).with_key([7u8; KEY_SIZE]);
+        let mut writers: Vec<Cursor<Vec<u8>>> =
+            (0..6).map(|_| Cursor::new(Vec::<u8>::new())).collect();
+        let error = keyed
+            .encode_sparse(&mut Cursor::new(&buffer), 100, &mut writers)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn zeroed_chunks_are_rejected() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let layout = encoder.layout();
+        let mut buffer = vec![0u8; 8192];
+        rng.fill_bytes(&mut buffer);
+
+        for sparse in [false, true] {
+            let mut writers: Vec<Cursor<Vec<u8>>> =
+                (0..6).map(|_| Cursor::new(Vec::<u8>::new())).collect();
+            if sparse {
+                encoder
+                    .encode_sparse(&mut Cursor::new(&buffer), buffer.len(), &mut writers)
+                    .unwrap();
+            } else {
+                encoder
+                    .encode(&mut Cursor::new(&buffer), buffer.len(), &mut writers)
+                    .unwrap();
+            }
+            let shards: Vec<Vec<u8>> = writers.into_iter().map(Cursor::into_inner).collect();
+            let chunk = layout.chunk_offset(1) as usize..layout.chunk_offset(2) as usize;
+
+            let mut zeroed = shards.clone();
+            for shard in zeroed.iter_mut().take(3) {
+                shard[chunk.clone()].fill(0);
+            }
+
+            let mut output = vec![];
+            let result = encoder.decode(&mut shard_readers(&zeroed, &[]), &mut output);
+            assert!(result.is_err(), "sparse {}", sparse);
+
+            // A zeroed parity chunk is not used in place of a missing data shard either.
+            let mut zeroed = shards.clone();
+            zeroed[4][chunk.clone()].fill(0);
+
+            let mut output = vec![];
+            encoder
+                .decode(&mut shard_readers(&zeroed, &[0]), &mut output)
+                .unwrap();
+            assert!(output == buffer, "sparse {}", sparse);
+        }
+    }
+
+    #[test]
+    fn arrange_shards() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let (buffer, shards) = encode_shards(&encoder, &mut rng, 10000);
+        let (_, other_shards) = encode_shards(&encoder, &mut rng, 10000);
+
+        let mut readers: Vec<Cursor<Vec<u8>>> = [5, 1, 3, 0, 1, 2]
+            .iter()
+            .map(|&shard| Cursor::new(shards[shard].clone()))
+            .collect();
+        readers.push(Cursor::new(other_shards[4].clone()));
+        readers.push(Cursor::new(vec![0u8; 100]));
+        readers[2].set_position(500);
+
+        let mut arranged = encoder.arrange_shards(readers).unwrap();
+        for (shard, reader) in arranged.iter().enumerate() {
+            match reader {
+                Some(reader) => assert!(*reader.get_ref() == shards[shard]),
+                None => assert_eq!(shard, 4),
+            }
+        }
+
+        let mut output = vec![];
+        encoder.decode(&mut arranged, &mut output).unwrap();
+        assert!(output == buffer);
+    }
+
+    fn encode_streamed_shards(encoder: &ReedSolomonEncoder, buffer: &[u8]) -> Vec<Cursor<Vec<u8>>> {
+        let mut writers: Vec<Cursor<Vec<u8>>> = (0..encoder.data_shards + encoder.parity_shards)
+            .map(|_| Cursor::new(Vec::<u8>::new()))
+            .collect();
+
+        let length = encoder
+            .encode_stream(&mut Cursor::new(buffer), &mut writers)
+            .unwrap();
+        assert_eq!(length, buffer.len());
+
+        writers
+    }
+
+    #[test]
+    fn streamed() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let layout = encoder.layout();
+
+        for length in [0, 1, 4095, 4096, 4097, 16 * 1024 + 17] {
+            let mut buffer = vec![0u8; length];
+            rng.fill_bytes(&mut buffer);
+
+            let shards: Vec<Vec<u8>> = encode_streamed_shards(&encoder, &buffer)
+                .into_iter()
+                .map(Cursor::into_inner)
+                .collect();
+            for shard in shards.iter() {
+                assert_eq!(
+                    shard.len() as u64,
+                    layout.shard_file_size(length) + layout.chunk_stride() as u64
+                );
+            }
+
+            for missing in [vec![], vec![0], vec![3, 5], vec![1, 2]] {
+                let mut output = vec![];
+                encoder
+                    .decode(&mut shard_readers(&shards, &missing), &mut output)
+                    .unwrap();
+                assert!(output == buffer, "length {}, missing {:?}", length, missing);
+
+                let offset = length / 3;
+                let mut output = vec![];
+                encoder
+                    .decode_at(
+                        &mut shard_readers(&shards, &missing),
+                        &mut output,
+                        offset,
+                        length - offset,
+                    )
+                    .unwrap();
+                assert!(output == buffer[offset..]);
+            }
+
+            let damaged = encoder.verify(&mut shard_readers(&shards, &[4])).unwrap();
+            let stripes = layout.stripes(length) + 1;
+            assert_eq!(damaged, vec![0, 0, 0, 0, stripes, 0]);
+
+            let mut truncated = shards.clone();
+            truncated[1].truncate(HEADER_SIZE + 100);
+            let shortened = truncated[5].len() - 1;
+            truncated[5].truncate(shortened);
+            let mut output = vec![];
+            encoder
+                .decode(&mut shard_readers(&truncated, &[]), &mut output)
+                .unwrap();
+            assert!(output == buffer);
+
+            let mut output = vec![];
+            let result = encoder.decode(&mut shard_readers(&shards, &[0, 1, 2]), &mut output);
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn streamed_append_and_add_parity() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+
+        let mut buffer = vec![0u8; 5000];
+        rng.fill_bytes(&mut buffer);
+        let mut shards = encode_streamed_shards(&encoder, &buffer);
+
+        for appended_length in [0, 3000, 20000] {
+            let mut appended = vec![0u8; appended_length];
+            rng.fill_bytes(&mut appended);
+
+            encoder
+                .append(&mut Cursor::new(&appended), appended_length, &mut shards)
+                .unwrap();
+            buffer.extend_from_slice(&appended);
+
+            let contents: Vec<Vec<u8>> =
+                shards.iter().map(|shard| shard.get_ref().clone()).collect();
+            let mut output = vec![];
+            encoder
+                .decode(&mut shard_readers(&contents, &[1]), &mut output)
+                .unwrap();
+            assert!(output == buffer);
+        }
+
+        let target = ReedSolomonEncoder::new(4, 3, 1024);
+        let mut existing: Vec<Option<Cursor<Vec<u8>>>> = shards.into_iter().map(Some).collect();
+        let mut writers = vec![Cursor::new(Vec::<u8>::new())];
+        encoder
+            .add_parity(&mut existing, &target, &mut writers)
+            .unwrap();
+
+        let mut contents: Vec<Vec<u8>> = existing
+            .into_iter()
+            .map(|shard| shard.unwrap().into_inner())
+            .collect();
+        contents.extend(writers.into_iter().map(Cursor::into_inner));
+
+        let mut output = vec![];
+        target
+            .decode(&mut shard_readers(&contents, &[0, 2, 5]), &mut output)
+            .unwrap();
+        assert!(output == buffer);
+
+        let transcoded = ReedSolomonEncoder::new(3, 2, 500);
+        let mut writers: Vec<Cursor<Vec<u8>>> = (0..5).map(|_| Cursor::new(vec![])).collect();
+        target
+            .transcode(
+                &mut shard_readers(&contents, &[1, 6]),
+                &transcoded,
+                &mut writers,
+            )
+            .unwrap();
+
+        let contents: Vec<Vec<u8>> = writers.into_iter().map(Cursor::into_inner).collect();
+        let mut output = vec![];
+        transcoded
+            .decode(&mut shard_readers(&contents, &[4]), &mut output)
             .unwrap();
+        assert!(output == buffer);
     }
 }
@@ -0,0 +1,524 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+use crate::checksum::{Checksum, ChecksumKind, ChunkKey, KEY_SIZE};
+use crate::encryption::KeyProvider;
+use crate::field::Field;
+use crate::gf8::Gf8;
+use crate::header::{SetId, SetIdSource, SetInfo, ShardHeader, write_header};
+use crate::io::{read_chunk, seek_to_chunk, write_chunk};
+use crate::layout::Layout;
+use crate::matrix::Matrix;
+use crate::progress::{CancellationToken, Monitor, ProgressObserver};
+use crate::stripe;
+use crate::stripe::{StripeDecoder, StripeEncoder};
+
+/// Encoder for Local Reconstruction Codes. Data shards are split into local groups, each
+/// protected by a local parity shard holding the XOR of the group, and the whole stripe is
+/// additionally protected by Reed-Solomon global parity shards. Shards are numbered data shards
+/// first, then local parity shards, then global parity shards.
+pub struct LrcEncoder {
+    data_shards: usize,
+    local_parity_shards: usize,
+    global_parity_shards: usize,
+    chunk_size: usize,
+    checksum: ChecksumKind,
+    key: ChunkKey,
+    set_id: SetIdSource,
+    monitor: Monitor,
+}
+
+impl LrcEncoder {
+    pub fn new(
+        data_shards: usize,
+        local_parity_shards: usize,
+        global_parity_shards: usize,
+        chunk_size: usize,
+    ) -> LrcEncoder {
+        assert!(
+            data_shards + local_parity_shards + global_parity_shards <= 256,
+            "Total number of shards cannot exceed 256"
+        );
+
+        assert!(
+            local_parity_shards > 0 && local_parity_shards <= data_shards,
+            "Number of local parity shards must be between one and the number of data shards"
+        );
+
+        assert!(chunk_size > 0, "Chunk size must be greater than zero");
+
+        assert!(
+            chunk_size <= u32::MAX as usize,
+            "Chunk size cannot exceed {} bytes",
+            u32::MAX
+        );
+
+        LrcEncoder {
+            data_shards,
+            local_parity_shards,
+            global_parity_shards,
+            chunk_size,
+            checksum: ChecksumKind::default(),
+            key: ChunkKey::None,
+            set_id: SetIdSource::default(),
+            monitor: Monitor::default(),
+        }
+    }
+
+    /// Selects the algorithm used to checksum each chunk. Defaults to 128-bit xxh3. Keyed
+    /// checksums and encryption are selected with `with_key` and `with_encryption` instead.
+    pub fn with_checksum(self, checksum: ChecksumKind) -> LrcEncoder {
+        assert!(
+            !checksum.is_keyed(),
+            "Keyed checksums are selected with with_key or with_encryption"
+        );
+
+        LrcEncoder {
+            checksum,
+            key: ChunkKey::None,
+            ..self
+        }
+    }
+
+    /// Authenticates each chunk with a keyed BLAKE3 MAC bound to its set ID, shard index and
+    /// stripe number, as with `ReedSolomonEncoder::with_key`.
+    pub fn with_key(self, key: [u8; KEY_SIZE]) -> LrcEncoder {
+        LrcEncoder {
+            checksum: ChecksumKind::KeyedBlake3,
+            key: ChunkKey::Mac(key),
+            ..self
+        }
+    }
+
+    /// Encrypts each chunk with XChaCha20-Poly1305, as with
+    /// `ReedSolomonEncoder::with_encryption`.
+    pub fn with_encryption(self, key_provider: Arc<dyn KeyProvider>) -> LrcEncoder {
+        LrcEncoder {
+            checksum: ChecksumKind::XChaCha20Poly1305,
+            key: ChunkKey::Cipher(key_provider),
+            ..self
+        }
+    }
+
+    /// Uses `set_id` for the shard sets this encoder creates instead of a random ID. Encrypting
+    /// encoders only create one shard set with it and fail to create more, as their chunks
+    /// would be encrypted under the same nonces.
+    pub fn with_set_id(self, set_id: SetId) -> LrcEncoder {
+        LrcEncoder {
+            set_id: SetIdSource::fixed(set_id),
+            ..self
+        }
+    }
+
+    /// Reports the progress of `encode`, `decode`, `verify` and `repair` to `observer` after
+    /// every stripe.
+    pub fn with_progress(self, observer: Arc<dyn ProgressObserver>) -> LrcEncoder {
+        LrcEncoder {
+            monitor: self.monitor.with_observer(observer),
+            ..self
+        }
+    }
+
+    /// Makes `encode`, `decode`, `verify` and `repair` fail between stripes once `cancellation`
+    /// is cancelled.
+    pub fn with_cancellation(self, cancellation: CancellationToken) -> LrcEncoder {
+        LrcEncoder {
+            monitor: self.monitor.with_cancellation(cancellation),
+            ..self
+        }
+    }
+
+    pub fn data_shards(&self) -> usize {
+        self.data_shards
+    }
+
+    pub fn local_parity_shards(&self) -> usize {
+        self.local_parity_shards
+    }
+
+    pub fn global_parity_shards(&self) -> usize {
+        self.global_parity_shards
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    pub fn checksum(&self) -> ChecksumKind {
+        self.checksum
+    }
+
+    /// Returns where data and chunks live in the shard sets this encoder creates.
+    pub fn layout(&self) -> Layout {
+        Layout::new(self.data_shards, self.chunk_size, self.checksum)
+    }
+
+    pub(crate) fn shards(&self) -> usize {
+        self.data_shards + self.local_parity_shards + self.global_parity_shards
+    }
+
+    fn local_group(&self, data_shard: usize) -> usize {
+        data_shard * self.local_parity_shards / self.data_shards
+    }
+
+    /// Returns the shards of the local group containing `shard`, including its local parity
+    /// shard, or `None` if `shard` is a global parity shard.
+    pub fn local_group_shards(&self, shard: usize) -> Option<Vec<usize>> {
+        let group = if shard < self.data_shards {
+            self.local_group(shard)
+        } else if shard < self.data_shards + self.local_parity_shards {
+            shard - self.data_shards
+        } else {
+            return None;
+        };
+
+        let mut shards: Vec<usize> = (0..self.data_shards)
+            .filter(|&data_shard| self.local_group(data_shard) == group)
+            .collect();
+        shards.push(self.data_shards + group);
+
+        Some(shards)
+    }
+
+    fn generator_matrix(&self) -> Matrix<Gf8> {
+        let encoding_matrix =
+            Matrix::<Gf8>::encoding_matrix(self.data_shards, self.global_parity_shards);
+
+        let mut matrix = Matrix::<Gf8>::with_dimensions(self.shards(), self.data_shards);
+
+        for data_shard in 0..self.data_shards {
+            matrix[data_shard][data_shard] = Gf8::one();
+            matrix[self.data_shards + self.local_group(data_shard)][data_shard] = Gf8::one();
+        }
+
+        for global_parity_shard in 0..self.global_parity_shards {
+            let row = self.data_shards + self.local_parity_shards + global_parity_shard;
+            matrix[row].copy_from_slice(&encoding_matrix[self.data_shards + global_parity_shard]);
+        }
+
+        matrix
+    }
+
+    fn decoder(&self) -> StripeDecoder {
+        StripeDecoder::with_generator_matrix(
+            self.generator_matrix(),
+            self.local_parity_shards,
+            self.chunk_size,
+            self.checksum,
+            self.key.clone(),
+        )
+    }
+
+    fn write_header<W: Write>(&self, writer: &mut W, shard: usize, set: SetInfo) -> io::Result<()> {
+        let header = ShardHeader {
+            shard,
+            data_shards: self.data_shards,
+            parity_shards: self.local_parity_shards + self.global_parity_shards,
+            local_parity_shards: self.local_parity_shards,
+            chunk_size: self.chunk_size,
+            checksum: self.checksum,
+            set,
+        };
+
+        write_header(writer, header, &self.key)
+    }
+
+    pub fn encode<R: Read, W: Write>(
+        &self,
+        data: &mut R,
+        length: usize,
+        shard_writers: &mut [W],
+    ) -> io::Result<()> {
+        assert_eq!(shard_writers.len(), self.shards());
+
+        let set = SetInfo {
+            set_id: self.set_id.next(self.checksum.is_encrypted())?,
+            length,
+            framing: None,
+            sparse: false,
+            streamed: false,
+        };
+        for (shard, writer) in shard_writers.iter_mut().enumerate() {
+            self.write_header(writer, shard, set)?;
+        }
+
+        let parity_matrix = self
+            .generator_matrix()
+            .slice(self.data_shards..self.shards());
+        let checksum = self.key.checksum(self.checksum, &set.set_id)?;
+
+        StripeEncoder::with_parity_matrix(parity_matrix, self.chunk_size, checksum)
+            .with_monitor(self.monitor.start(Some(length)))
+            .encode_stripes(data, 0, length, shard_writers)
+    }
+
+    pub fn decode<R: Read, W: Write>(
+        &self,
+        shard_readers: &mut [Option<R>],
+        output: &mut W,
+    ) -> io::Result<()> {
+        assert_eq!(shard_readers.len(), self.shards());
+
+        let decoder = self.decoder().with_monitor(self.monitor.start(None));
+        stripe::decode(decoder, shard_readers, output)
+    }
+
+    /// Validates every chunk of a shard set without decoding anything, as with
+    /// `ReedSolomonEncoder::verify`.
+    pub fn verify<R: Read>(&self, shard_readers: &mut [Option<R>]) -> io::Result<Vec<usize>> {
+        assert_eq!(shard_readers.len(), self.shards());
+
+        let decoder = self.decoder().with_monitor(self.monitor.start(None));
+        stripe::verify(decoder, shard_readers)
+    }
+
+    pub fn decode_at<R: Read + Seek, W: Write>(
+        &self,
+        shard_readers: &mut [Option<R>],
+        output: &mut W,
+        offset: usize,
+        length: usize,
+    ) -> io::Result<()> {
+        assert_eq!(shard_readers.len(), self.shards());
+
+        let decoder = self.decoder().with_monitor(self.monitor.start(None));
+        stripe::decode_at(decoder, shard_readers, output, offset, length)
+    }
+
+    /// Rebuilds a single lost shard into `output`. Data and local parity shards are rebuilt from
+    /// the rest of their local group alone; a stripe where any other chunk of the group is
+    /// unreadable, as well as any global parity shard, is rebuilt from the whole stripe instead.
+    /// The reader for the lost shard itself, if any, is ignored.
+    pub fn repair<R: Read + Seek, W: Write>(
+        &self,
+        shard: usize,
+        shard_readers: &mut [Option<R>],
+        output: &mut W,
+    ) -> io::Result<()> {
+        assert_eq!(shard_readers.len(), self.shards());
+        assert!(shard < self.shards());
+
+        shard_readers[shard] = None;
+
+        let mut decoder = self.decoder();
+
+        for shard_reader in shard_readers.iter_mut() {
+            if let Some(reader) = shard_reader
+                && reader.seek(SeekFrom::Start(0)).is_err()
+            {
+                *shard_reader = None;
+            }
+        }
+
+        let length = decoder.read_headers(shard_readers)?;
+        self.write_header(output, shard, decoder.set())?;
+
+        let group = self.local_group_shards(shard).map(|group| {
+            group
+                .into_iter()
+                .filter(|&member| member != shard)
+                .collect::<Vec<usize>>()
+        });
+
+        let generator_row = self.generator_matrix().select_rows(&[shard]);
+
+        let checksum = decoder.checksum();
+        let mut chunk = vec![0u8; self.chunk_size];
+        let mut member_chunk = vec![0u8; self.chunk_size];
+        let mut monitor = self.monitor.start(Some(length));
+
+        for stripe in 0..self.layout().stripes(length) {
+            let rebuilt_locally = match &group {
+                Some(group) => self.rebuild_from_group(
+                    group,
+                    shard_readers,
+                    checksum,
+                    stripe,
+                    &mut chunk,
+                    &mut member_chunk,
+                ),
+                None => false,
+            };
+
+            if rebuilt_locally {
+                write_chunk(output, checksum, shard, stripe, &chunk)?;
+            } else {
+                decoder.seek_to_stripe(shard_readers, stripe);
+                decoder.decode_stripe(shard_readers)?;
+
+                let rebuilt = &generator_row * decoder.data_matrix();
+                write_chunk(output, checksum, shard, stripe, rebuilt.as_bytes())?;
+            }
+
+            monitor.stripe_done(self.layout().block_size(), 1)?;
+        }
+
+        Result::Ok(())
+    }
+
+    fn rebuild_from_group<R: Read + Seek>(
+        &self,
+        group: &[usize],
+        shard_readers: &mut [Option<R>],
+        checksum: Checksum,
+        stripe: usize,
+        chunk: &mut [u8],
+        member_chunk: &mut [u8],
+    ) -> bool {
+        chunk.fill(0);
+
+        for &member in group {
+            let Some(reader) = &mut shard_readers[member] else {
+                return false;
+            };
+
+            if seek_to_chunk(reader, &self.layout(), stripe).is_err()
+                || read_chunk(reader, checksum, member, stripe, member_chunk).is_err()
+            {
+                return false;
+            }
+
+            for (byte, &member_byte) in chunk.iter_mut().zip(member_chunk.iter()) {
+                *byte ^= member_byte;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::HEADER_SIZE;
+    use crate::test_util::{encode_shards, shard_readers};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    /// Size of the default 128-bit xxh3 chunk checksum.
+    const HASH_SIZE: usize = 16;
+
+    #[test]
+    fn local_groups() {
+        let encoder = LrcEncoder::new(5, 2, 2, 16);
+        assert_eq!(encoder.local_group_shards(0), Some(vec![0, 1, 2, 5]));
+        assert_eq!(encoder.local_group_shards(4), Some(vec![3, 4, 6]));
+        assert_eq!(encoder.local_group_shards(5), Some(vec![0, 1, 2, 5]));
+        assert_eq!(encoder.local_group_shards(6), Some(vec![3, 4, 6]));
+        assert_eq!(encoder.local_group_shards(7), None);
+    }
+
+    #[test]
+    fn decode() {
+        let encoder = LrcEncoder::new(6, 2, 2, 512);
+        let (buffer, shards) = encode_shards(&encoder, &mut StdRng::from_seed([42u8; 32]), 20000);
+
+        for missing in [
+            vec![],
+            vec![0],
+            vec![6],
+            vec![0, 3],
+            vec![0, 3, 9],
+            vec![0, 1, 9],
+            vec![0, 1, 7, 9],
+            vec![3, 4, 8],
+        ] {
+            let mut output = vec![];
+            encoder
+                .decode(&mut shard_readers(&shards, &missing), &mut output)
+                .unwrap();
+            assert!(output == buffer, "missing {:?}", missing);
+        }
+
+        let mut output = vec![];
+        assert!(
+            encoder
+                .decode(&mut shard_readers(&shards, &[0, 1, 2, 6, 8]), &mut output)
+                .is_err()
+        );
+
+        let mut output = vec![];
+        encoder
+            .decode_at(
+                &mut shard_readers(&shards, &[2, 8]),
+                &mut output,
+                3000,
+                9000,
+            )
+            .unwrap();
+        assert!(output == buffer[3000..12000]);
+    }
+
+    #[test]
+    fn repair() {
+        let encoder = LrcEncoder::new(6, 2, 2, 512);
+        let (_, shards) = encode_shards(&encoder, &mut StdRng::from_seed([42u8; 32]), 20000);
+
+        for shard in 0..encoder.shards() {
+            let mut output = vec![];
+            encoder
+                .repair(shard, &mut shard_readers(&shards, &[]), &mut output)
+                .unwrap();
+            assert!(output == shards[shard], "shard {}", shard);
+        }
+    }
+
+    #[test]
+    fn repair_encrypted() {
+        let encoder = LrcEncoder::new(6, 2, 2, 512).with_encryption(Arc::new([6u8; KEY_SIZE]));
+        let (buffer, mut shards) =
+            encode_shards(&encoder, &mut StdRng::from_seed([42u8; 32]), 20000);
+
+        shards[4][HEADER_SIZE + 3 * (HASH_SIZE + 512) + 40] ^= 0x10;
+
+        for shard in [1, 7, 9] {
+            let mut output = vec![];
+            encoder
+                .repair(shard, &mut shard_readers(&shards, &[0]), &mut output)
+                .unwrap();
+            assert!(output == shards[shard], "shard {}", shard);
+        }
+
+        let mut output = vec![];
+        encoder
+            .decode_at(
+                &mut shard_readers(&shards, &[0, 8]),
+                &mut output,
+                3000,
+                9000,
+            )
+            .unwrap();
+        assert!(output == buffer[3000..12000]);
+    }
+
+    #[test]
+    fn repair_reads_local_group_only() {
+        let encoder = LrcEncoder::new(6, 2, 2, 512);
+        let (_, shards) = encode_shards(&encoder, &mut StdRng::from_seed([42u8; 32]), 20000);
+
+        let mut output = vec![];
+        encoder
+            .repair(
+                1,
+                &mut shard_readers(&shards, &[3, 4, 5, 7, 8, 9]),
+                &mut output,
+            )
+            .unwrap();
+        assert!(output == shards[1]);
+    }
+
+    #[test]
+    fn repair_falls_back_to_global_parity() {
+        let encoder = LrcEncoder::new(6, 2, 2, 512);
+        let (_, mut shards) = encode_shards(&encoder, &mut StdRng::from_seed([42u8; 32]), 20000);
+
+        shards[0][HEADER_SIZE + 2 * (HASH_SIZE + 512) + 40] ^= 0x10;
+
+        let mut output = vec![];
+        encoder
+            .repair(1, &mut shard_readers(&shards, &[]), &mut output)
+            .unwrap();
+        assert!(output == shards[1]);
+    }
+}
@@ -2,6 +2,7 @@ use crate::field::Field;
 use crate::gf8::Gf8;
 use std::fmt;
 use std::ops::{Index, IndexMut, Mul, Range};
+use std::slice;
 use std::vec::Vec;
 
 #[derive(Clone, PartialEq)]
@@ -16,19 +17,19 @@ impl<F: Field> Matrix<F> {
         let mut elements = vec![];
 
         for i in 0..rows {
-            for j in 0..i {
+            for _ in 0..i {
                 elements.push(F::zero());
             }
 
             elements.push(F::one());
 
-            for j in i + 1..rows {
+            for _ in i + 1..rows {
                 elements.push(F::zero());
             }
         }
 
         Matrix {
-            rows: rows,
+            rows,
             columns: rows,
             elements: elements.into_boxed_slice(),
         }
@@ -166,7 +167,7 @@ impl<F: Field> Matrix<F> {
             let mut element = Gf8::one();
             let base = Gf8(row as u8);
 
-            for column in 0..columns {
+            for _ in 0..columns {
                 elements.push(element);
                 element *= base;
             }
@@ -201,6 +202,78 @@ impl<F: Field> Matrix<F> {
         }
     }
 
+    pub fn select_rows(&self, rows: &[usize]) -> Self {
+        let mut elements: Vec<F> = Vec::with_capacity(rows.len() * self.columns);
+
+        for &row in rows {
+            assert!(row < self.rows);
+            elements.extend_from_slice(&self[row]);
+        }
+
+        Matrix {
+            rows: rows.len(),
+            columns: self.columns,
+            elements: elements.into_boxed_slice(),
+        }
+    }
+
+    /// Greedily picks `count` linearly independent rows from `candidates`, in order, or returns
+    /// `None` if the candidates do not span enough dimensions.
+    pub fn independent_rows(&self, candidates: &[usize], count: usize) -> Option<Vec<usize>> {
+        let mut basis: Vec<(usize, Vec<F>)> = vec![];
+        let mut rows = Vec::with_capacity(count);
+
+        for &row in candidates {
+            if rows.len() == count {
+                break;
+            }
+
+            let mut vector = self[row].to_vec();
+
+            for (pivot, basis_vector) in basis.iter() {
+                let u = vector[*pivot];
+
+                if u == F::zero() {
+                    continue;
+                }
+
+                for (element, &basis_element) in vector.iter_mut().zip(basis_vector) {
+                    *element -= u * basis_element;
+                }
+            }
+
+            let Some(pivot) = vector.iter().position(|&element| element != F::zero()) else {
+                continue;
+            };
+
+            let u = vector[pivot];
+            for element in vector.iter_mut() {
+                *element /= u;
+            }
+
+            for (_, basis_vector) in basis.iter_mut() {
+                let v = basis_vector[pivot];
+
+                if v == F::zero() {
+                    continue;
+                }
+
+                for (element, &pivot_element) in basis_vector.iter_mut().zip(&vector) {
+                    *element -= v * pivot_element;
+                }
+            }
+
+            basis.push((pivot, vector));
+            rows.push(row);
+        }
+
+        if rows.len() == count {
+            Some(rows)
+        } else {
+            None
+        }
+    }
+
     pub fn slice(&self, index: Range<usize>) -> Self {
         let start_row = index.start.clamp(0, self.rows);
         let end_row = index.end.clamp(start_row, self.rows);
@@ -215,6 +288,18 @@ impl<F: Field> Matrix<F> {
     }
 }
 
+impl Matrix<Gf8> {
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.elements.as_ptr() as *const u8, self.elements.len()) }
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut(self.elements.as_mut_ptr() as *mut u8, self.elements.len())
+        }
+    }
+}
+
 impl<F: Field> fmt::Debug for Matrix<F> {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
         writeln!(formatter, "[")?;
@@ -360,4 +445,37 @@ mod tests {
             matrix
         )
     }
+
+    #[test]
+    fn encoding_matrix_extends_with_parity_shards() {
+        for data_shards in 1..12 {
+            let matrix = Matrix::<Gf8>::encoding_matrix(data_shards, 4);
+            let extended = Matrix::<Gf8>::encoding_matrix(data_shards, 10);
+            assert!(matrix == extended.slice(0..data_shards + 4));
+        }
+    }
+
+    #[test]
+    fn independent_rows() {
+        let matrix = Matrix {
+            rows: 5,
+            columns: 3,
+            elements: vec![1, 0, 0, 2, 0, 0, 0, 1, 1, 3, 1, 1, 0, 0, 1]
+                .into_iter()
+                .map(Gf8)
+                .collect::<Vec<Gf8>>()
+                .into_boxed_slice(),
+        };
+
+        assert_eq!(
+            matrix.independent_rows(&[0, 1, 2, 3, 4], 3),
+            Some(vec![0, 2, 4])
+        );
+        assert_eq!(
+            matrix.independent_rows(&[1, 2, 3, 4], 3),
+            Some(vec![1, 2, 4])
+        );
+        assert_eq!(matrix.independent_rows(&[0, 1, 2, 3], 3), None);
+        assert_eq!(matrix.independent_rows(&[4, 3, 2], 2), Some(vec![4, 3]));
+    }
 }
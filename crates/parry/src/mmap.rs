@@ -0,0 +1,317 @@
+use memmap2::{Mmap, MmapMut};
+use std::fs::File;
+use std::io;
+
+use crate::ReedSolomonEncoder;
+use crate::gf8::Gf8;
+use crate::header::{HEADER_SIZE, SetInfo};
+use crate::io::{open_chunk, seal_chunk};
+use crate::matrix::Matrix;
+use crate::stripe::StripeDecoder;
+
+fn map(file: &File) -> io::Result<Mmap> {
+    unsafe { Mmap::map(file) }
+}
+
+fn map_mut(file: &File) -> io::Result<MmapMut> {
+    unsafe { MmapMut::map_mut(file) }
+}
+
+fn unsupported_encryption() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Encrypted shard sets cannot be encoded or decoded through memory maps",
+    )
+}
+
+impl ReedSolomonEncoder {
+    /// Encodes the whole of `input` through memory maps. Each shard file is resized to its final
+    /// size and mapped, data chunks are copied straight from the mapped input and parity chunks
+    /// are computed from the mapped input in place. Shard files must be opened for reading and
+    /// writing.
+    pub fn encode_mmap(&self, input: &File, shard_files: &[File]) -> io::Result<()> {
+        assert_eq!(shard_files.len(), self.data_shards + self.parity_shards);
+
+        if self.checksum.is_encrypted() {
+            return Result::Err(unsupported_encryption());
+        }
+
+        let layout = self.layout();
+        let length = input.metadata()?.len() as usize;
+        let input = map(input)?;
+        let shard_file_size = layout.shard_file_size(length);
+
+        let mut shards = Vec::with_capacity(shard_files.len());
+        for file in shard_files {
+            file.set_len(shard_file_size)?;
+            shards.push(map_mut(file)?);
+        }
+
+        let set = SetInfo {
+            set_id: self.new_set_id()?,
+            length,
+            framing: None,
+            sparse: false,
+            streamed: false,
+        };
+        for (shard, map) in shards.iter_mut().enumerate() {
+            self.write_header(&mut &mut map[..HEADER_SIZE], shard, set)?;
+        }
+
+        let encoding_matrix = Matrix::<Gf8>::encoding_matrix(self.data_shards, self.parity_shards);
+        let block_size = layout.block_size();
+        let checksum = self.chunk_checksum(&set.set_id)?;
+        let checksum_size = checksum.size();
+        let chunk_stride = layout.chunk_stride();
+        let mut final_block = vec![];
+        let mut monitor = self.monitor.start(Some(length));
+
+        for stripe in 0..layout.stripes(length) {
+            let block = if (stripe + 1) * block_size <= length {
+                &input[stripe * block_size..(stripe + 1) * block_size]
+            } else {
+                final_block.clear();
+                final_block.extend_from_slice(&input[stripe * block_size..]);
+                final_block.resize(block_size, 0);
+                &final_block[..]
+            };
+
+            let offset = layout.chunk_offset(stripe) as usize;
+
+            for (shard, map) in shards.iter_mut().enumerate() {
+                let bytes = &mut map[offset..offset + chunk_stride];
+
+                if shard < self.data_shards {
+                    bytes[checksum_size..].copy_from_slice(
+                        &block[shard * self.chunk_size..(shard + 1) * self.chunk_size],
+                    );
+                } else {
+                    bytes[checksum_size..].fill(0);
+
+                    for (data_shard, chunk) in block.chunks(self.chunk_size).enumerate() {
+                        encoding_matrix[shard][data_shard]
+                            .mul_add_slice(chunk, &mut bytes[checksum_size..]);
+                    }
+                }
+
+                seal_chunk(bytes, checksum, shard, stripe);
+            }
+
+            monitor.stripe_done(block_size, 0)?;
+        }
+
+        for map in shards.iter() {
+            map.flush()?;
+        }
+
+        Result::Ok(())
+    }
+
+    /// Decodes a shard set into `output` through memory maps. Chunks of every stripe are
+    /// validated in place and handed to the same reconstruction as `decode`, which only
+    /// computes erased data chunks, and the decoded block is copied into the mapped output.
+    /// `output` must be opened for reading and writing.
+    pub fn decode_mmap(&self, shard_files: &[Option<File>], output: &File) -> io::Result<()> {
+        assert_eq!(shard_files.len(), self.data_shards + self.parity_shards);
+
+        if self.checksum.is_encrypted() {
+            return Result::Err(unsupported_encryption());
+        }
+
+        let mut maps = Vec::with_capacity(shard_files.len());
+        for file in shard_files {
+            maps.push(match file {
+                Some(file) => map(file).ok(),
+                None => None,
+            });
+        }
+
+        let mut shards: Vec<Option<&[u8]>> = maps.iter().map(|map| map.as_deref()).collect();
+
+        let layout = self.layout();
+        let mut decoder = StripeDecoder::new(self);
+
+        let length = {
+            let mut headers = shards.clone();
+            let length = decoder.read_headers(&mut headers)?;
+            if decoder.set().framing.is_some() {
+                return Result::Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Compressed shard sets cannot be decoded through memory maps",
+                ));
+            }
+            if decoder.set().streamed {
+                return Result::Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Streamed shard sets cannot be decoded through memory maps",
+                ));
+            }
+
+            for (shard, header) in shards.iter_mut().zip(headers) {
+                if header.is_none()
+                    || shard.is_some_and(|map| (map.len() as u64) < layout.shard_file_size(length))
+                {
+                    *shard = None;
+                }
+            }
+
+            length
+        };
+
+        output.set_len(length as u64)?;
+        let mut output_map = map_mut(output)?;
+
+        let checksum = decoder.checksum();
+        let chunk_stride = layout.chunk_stride();
+        let mut decoder = decoder.with_monitor(self.monitor.start(Some(length)));
+
+        for stripe in 0..layout.stripes(length) {
+            let offset = layout.chunk_offset(stripe) as usize;
+
+            decoder.begin_stripe();
+            for (shard, map) in shards.iter().enumerate() {
+                if let Some(chunk) = map.and_then(|map| {
+                    open_chunk(&map[offset..offset + chunk_stride], checksum, shard, stripe)
+                }) {
+                    decoder.set_chunk(shard, chunk);
+                }
+            }
+            decoder.reconstruct()?;
+
+            let stripe_start = layout.stripe_start(stripe);
+            let stripe_end = length.min(stripe_start + layout.block_size());
+            output_map[stripe_start..stripe_end]
+                .copy_from_slice(&decoder.block()[..stripe_end - stripe_start]);
+        }
+
+        output_map.flush()?;
+
+        Result::Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SetId;
+    use crate::test_util::scratch_directory;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+    use std::fs;
+    use std::io::{Cursor, Read};
+    use std::path::PathBuf;
+
+    fn open(path: &PathBuf) -> File {
+        File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .unwrap()
+    }
+
+    #[test]
+    fn matches_streaming_encoder() {
+        let directory = scratch_directory("mmap-encode");
+        let encoder = ReedSolomonEncoder::new(4, 2, 1000).with_set_id(SetId::random());
+        let mut rng = StdRng::from_seed([42u8; 32]);
+
+        for length in [0, 1, 3999, 4000, 4001, 50000] {
+            let mut buffer = vec![0u8; length];
+            rng.fill_bytes(&mut buffer);
+            fs::write(directory.join("input"), &buffer).unwrap();
+
+            let shard_files: Vec<File> = (0..6)
+                .map(|shard| open(&directory.join(format!("shard.{}", shard))))
+                .collect();
+            encoder
+                .encode_mmap(&File::open(directory.join("input")).unwrap(), &shard_files)
+                .unwrap();
+
+            let mut writers: Vec<Cursor<Vec<u8>>> = (0..6).map(|_| Cursor::new(vec![])).collect();
+            encoder
+                .encode(&mut Cursor::new(&buffer), length, &mut writers)
+                .unwrap();
+
+            for (shard, writer) in writers.into_iter().enumerate() {
+                let mut contents = vec![];
+                File::open(directory.join(format!("shard.{}", shard)))
+                    .unwrap()
+                    .read_to_end(&mut contents)
+                    .unwrap();
+                assert!(
+                    contents == writer.into_inner(),
+                    "length {}, shard {}",
+                    length,
+                    shard
+                );
+            }
+
+            for missing in [vec![], vec![0], vec![1, 5]] {
+                let shard_files: Vec<Option<File>> = (0..6)
+                    .map(|shard| {
+                        (!missing.contains(&shard)).then(|| {
+                            File::open(directory.join(format!("shard.{}", shard))).unwrap()
+                        })
+                    })
+                    .collect();
+
+                let output_path = directory.join("output");
+                let _ = fs::remove_file(&output_path);
+                encoder
+                    .decode_mmap(&shard_files, &open(&output_path))
+                    .unwrap();
+                assert!(fs::read(&output_path).unwrap() == buffer);
+            }
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn decode_corrupted_chunks() {
+        let directory = scratch_directory("mmap-corrupted");
+        let encoder = ReedSolomonEncoder::new(4, 2, 1000);
+        let mut rng = StdRng::from_seed([42u8; 32]);
+
+        let mut buffer = vec![0u8; 30000];
+        rng.fill_bytes(&mut buffer);
+
+        let mut writers: Vec<Cursor<Vec<u8>>> = (0..6).map(|_| Cursor::new(vec![])).collect();
+        encoder
+            .encode(&mut Cursor::new(&buffer), buffer.len(), &mut writers)
+            .unwrap();
+
+        for (shard, writer) in writers.into_iter().enumerate() {
+            let mut contents = writer.into_inner();
+            if shard < 2 {
+                contents[HEADER_SIZE + (shard + 1) * (encoder.checksum().size() + 1000) + 17] ^=
+                    0x01;
+            }
+            fs::write(directory.join(format!("shard.{}", shard)), contents).unwrap();
+        }
+
+        let shard_files: Vec<Option<File>> = (0..6)
+            .map(|shard| Some(File::open(directory.join(format!("shard.{}", shard))).unwrap()))
+            .collect();
+
+        let output_path = directory.join("output");
+        encoder
+            .decode_mmap(&shard_files, &open(&output_path))
+            .unwrap();
+        assert!(fs::read(&output_path).unwrap() == buffer);
+
+        let shard_files: Vec<Option<File>> = (0..6)
+            .map(|shard| {
+                (shard < 4).then(|| File::open(directory.join(format!("shard.{}", shard))).unwrap())
+            })
+            .collect();
+        let error = encoder
+            .decode_mmap(&shard_files, &open(&output_path))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
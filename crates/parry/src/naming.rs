@@ -0,0 +1,380 @@
+use std::fmt;
+use std::fmt::Write;
+use std::io;
+use std::str::FromStr;
+
+use crate::header::SetId;
+
+/// Template for the names of shard files. Placeholders are `{index}`, optionally zero-padded as
+/// in `{index:03}`, `{total}`, the number of shards, `{kind}`, `data` or `parity`, `{name}`, the
+/// base name of the encoded file, and `{set}`, the set ID. `{}` is short for `{index}`, and `{{`
+/// and `}}` stand for literal braces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameTemplate {
+    template: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Index { width: usize },
+    Total,
+    Kind,
+    Name,
+    Set,
+}
+
+/// What is known about a shard set when naming its shard files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameContext {
+    data_shards: usize,
+    total: usize,
+    name: Option<String>,
+    set_id: Option<SetId>,
+}
+
+impl NameContext {
+    pub fn new(data_shards: usize, total: usize) -> NameContext {
+        NameContext {
+            data_shards,
+            total,
+            name: None,
+            set_id: None,
+        }
+    }
+
+    pub fn with_name(self, name: String) -> NameContext {
+        NameContext {
+            name: Some(name),
+            ..self
+        }
+    }
+
+    pub fn with_set_id(self, set_id: SetId) -> NameContext {
+        NameContext {
+            set_id: Some(set_id),
+            ..self
+        }
+    }
+
+    fn kind(&self, shard: usize) -> &'static str {
+        if shard < self.data_shards {
+            "data"
+        } else {
+            "parity"
+        }
+    }
+}
+
+/// Values captured while matching a file name against a template.
+#[derive(Copy, Clone)]
+struct Captures {
+    index: Option<usize>,
+    kind: Option<&'static str>,
+}
+
+fn unknown(placeholder: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("The value of {{{}}} is not known", placeholder),
+    )
+}
+
+impl NameTemplate {
+    /// Returns the name of the file of `shard`, failing if the template uses a value `context`
+    /// does not know.
+    pub fn render(&self, shard: usize, context: &NameContext) -> io::Result<String> {
+        let mut name = String::new();
+
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(literal) => name.push_str(literal),
+                Part::Index { width } => write!(name, "{:0width$}", shard, width = width).unwrap(),
+                Part::Total => write!(name, "{}", context.total).unwrap(),
+                Part::Kind => name.push_str(context.kind(shard)),
+                Part::Name => {
+                    name.push_str(context.name.as_deref().ok_or_else(|| unknown("name"))?)
+                }
+                Part::Set => {
+                    write!(name, "{}", context.set_id.ok_or_else(|| unknown("set"))?).unwrap()
+                }
+            }
+        }
+
+        Result::Ok(name)
+    }
+
+    /// Returns the shard index of the file named `name` if the template could have produced it.
+    /// Values `context` does not know match anything that could stand in their place.
+    pub fn matches(&self, name: &str, context: &NameContext) -> Option<usize> {
+        let captures = Captures {
+            index: None,
+            kind: None,
+        };
+
+        self.match_parts(&self.parts, name, context, captures)
+    }
+
+    fn match_parts(
+        &self,
+        parts: &[Part],
+        text: &str,
+        context: &NameContext,
+        captures: Captures,
+    ) -> Option<usize> {
+        let Some((part, rest)) = parts.split_first() else {
+            let index = captures.index?;
+            let kind_matches = captures.kind.is_none_or(|kind| kind == context.kind(index));
+
+            return (text.is_empty() && index < context.total && kind_matches).then_some(index);
+        };
+
+        let literal = match part {
+            Part::Literal(literal) => Some(literal.clone()),
+            Part::Total => Some(context.total.to_string()),
+            Part::Name => context.name.clone(),
+            Part::Set => context.set_id.map(|set_id| set_id.to_string()),
+            Part::Index { .. } | Part::Kind => None,
+        };
+
+        if let Some(literal) = literal {
+            let text = text.strip_prefix(literal.as_str())?;
+            return self.match_parts(rest, text, context, captures);
+        }
+
+        match part {
+            Part::Index { width } => {
+                let digits = text.bytes().take_while(u8::is_ascii_digit).count();
+
+                (1..=digits).rev().find_map(|length| {
+                    let (number, text) = text.split_at(length);
+                    let index: usize = number.parse().ok()?;
+
+                    if format!("{:0width$}", index, width = width) != number
+                        || captures.index.is_some_and(|other| other != index)
+                    {
+                        return None;
+                    }
+
+                    let captures = Captures {
+                        index: Some(index),
+                        ..captures
+                    };
+                    self.match_parts(rest, text, context, captures)
+                })
+            }
+            Part::Kind => ["data", "parity"].into_iter().find_map(|kind| {
+                let text = text.strip_prefix(kind)?;
+
+                if captures.kind.is_some_and(|other| other != kind) {
+                    return None;
+                }
+
+                let captures = Captures {
+                    kind: Some(kind),
+                    ..captures
+                };
+                self.match_parts(rest, text, context, captures)
+            }),
+            Part::Name => text
+                .char_indices()
+                .skip(1)
+                .map(|(length, _)| length)
+                .chain([text.len()])
+                .filter(|&length| length > 0)
+                .find_map(|length| self.match_parts(rest, &text[length..], context, captures)),
+            Part::Set => {
+                let set_id = text.get(..36)?;
+                let is_set_id = set_id.char_indices().all(|(i, c)| match i {
+                    8 | 13 | 18 | 23 => c == '-',
+                    _ => c.is_ascii_hexdigit(),
+                });
+
+                if !is_set_id {
+                    return None;
+                }
+
+                self.match_parts(rest, &text[36..], context, captures)
+            }
+            Part::Literal(_) | Part::Total => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Display for NameTemplate {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.template)
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<NameTemplate, String> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => {
+                                return Result::Err(format!(
+                                    "Unclosed placeholder in {:?}",
+                                    template
+                                ));
+                            }
+                        }
+                    }
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+
+                    parts.push(match placeholder.as_str() {
+                        "" | "index" => Part::Index { width: 0 },
+                        "total" => Part::Total,
+                        "kind" => Part::Kind,
+                        "name" => Part::Name,
+                        "set" => Part::Set,
+                        _ => match placeholder
+                            .strip_prefix("index:")
+                            .and_then(|width| width.parse().ok())
+                        {
+                            Some(width) => Part::Index { width },
+                            None => {
+                                return Result::Err(format!(
+                                    "Unknown placeholder {{{}}}, expected one of {{index}}, \
+                                     {{index:03}}, {{total}}, {{kind}}, {{name}}, {{set}}",
+                                    placeholder
+                                ));
+                            }
+                        },
+                    });
+                }
+                '}' => return Result::Err(format!("Unmatched }} in {:?}", template)),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        if !parts.iter().any(|part| matches!(part, Part::Index { .. })) {
+            return Result::Err(format!(
+                "Template {:?} must contain {{index}} to give every shard its own file",
+                template
+            ));
+        }
+
+        Result::Ok(NameTemplate {
+            template: template.to_string(),
+            parts,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(template: &str) -> NameTemplate {
+        template.parse().unwrap()
+    }
+
+    #[test]
+    fn render() {
+        let set_id = SetId::from_bytes([0xab; 16]);
+        let context = NameContext::new(4, 6)
+            .with_name("disk.img".to_string())
+            .with_set_id(set_id);
+
+        for (template_text, shard, name) in [
+            ("shard.{}", 2, "shard.2"),
+            ("shard.{index}", 10, "shard.10"),
+            (
+                "{name}.{index:03}-of-{total}.{kind}",
+                5,
+                "disk.img.005-of-6.parity",
+            ),
+            (
+                "{name}.{index:03}-of-{total}.{kind}",
+                1234,
+                "disk.img.1234-of-6.parity",
+            ),
+            ("{kind}/{index:2}", 3, "data/03"),
+            ("{{{index}}}", 3, "{3}"),
+            ("{set}.{index}", 0, "abababab-abab-abab-abab-abababababab.0"),
+        ] {
+            assert_eq!(
+                template(template_text).render(shard, &context).unwrap(),
+                name
+            );
+        }
+
+        let error = template("{name}.{index}")
+            .render(0, &NameContext::new(4, 6))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        for invalid in ["shard", "{index", "index}", "{index:x}", "{size}.{index}"] {
+            assert!(invalid.parse::<NameTemplate>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn matches() {
+        let set_id = SetId::random();
+        let known = NameContext::new(4, 6)
+            .with_name("a.b".to_string())
+            .with_set_id(set_id);
+        let unknown = NameContext::new(4, 6);
+
+        let padded = template("{name}.{index:03}.{kind}");
+        for shard in 0..6 {
+            let name = padded.render(shard, &known).unwrap();
+            assert_eq!(padded.matches(&name, &known), Some(shard));
+            assert_eq!(padded.matches(&name, &unknown), Some(shard));
+        }
+
+        assert_eq!(padded.matches("x.y.003.data", &unknown), Some(3));
+        assert_eq!(padded.matches("x.y.003.data", &known), None);
+        assert_eq!(padded.matches("a.b.3.data", &unknown), None);
+        assert_eq!(padded.matches("a.b.004.data", &unknown), None);
+        assert_eq!(padded.matches("a.b.006.parity", &unknown), None);
+        assert_eq!(padded.matches(".001.data", &unknown), None);
+
+        let with_set = template("{set}/{index}");
+        let name = with_set.render(11, &NameContext::new(10, 12).with_set_id(set_id));
+        assert_eq!(
+            with_set.matches(&name.unwrap(), &NameContext::new(10, 12)),
+            Some(11)
+        );
+        assert_eq!(with_set.matches("not-a-set-id/1", &unknown), None);
+
+        assert_eq!(
+            template("{index}.{index:02}").matches("3.03", &unknown),
+            Some(3)
+        );
+        assert_eq!(
+            template("{index}.{index:02}").matches("3.04", &unknown),
+            None
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Assigns shards to directories, typically one per disk, so that losing a disk only loses the
+/// shards placed on it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Placement {
+    directories: Vec<PathBuf>,
+    mapping: Option<Vec<usize>>,
+}
+
+impl Placement {
+    /// Spreads shards over `directories` in turn.
+    pub fn round_robin(directories: Vec<PathBuf>) -> Placement {
+        assert!(
+            !directories.is_empty(),
+            "At least one directory is required"
+        );
+
+        Placement {
+            directories,
+            mapping: None,
+        }
+    }
+
+    /// Places shard `i` in the directory at index `mapping[i]` of `directories`.
+    pub fn mapped(directories: Vec<PathBuf>, mapping: Vec<usize>) -> Placement {
+        assert!(
+            mapping
+                .iter()
+                .all(|&directory| directory < directories.len()),
+            "Shards can only be mapped to one of the {} directories",
+            directories.len()
+        );
+
+        Placement {
+            directories,
+            mapping: Some(mapping),
+        }
+    }
+
+    pub fn directories(&self) -> &[PathBuf] {
+        &self.directories
+    }
+
+    pub fn directory(&self, shard: usize) -> &Path {
+        let index = match &self.mapping {
+            Some(mapping) => *mapping
+                .get(shard)
+                .unwrap_or_else(|| panic!("No directory is mapped to shard {}", shard)),
+            None => shard % self.directories.len(),
+        };
+
+        &self.directories[index]
+    }
+
+    /// Returns the path of the file named `file_name` for `shard`.
+    pub fn shard_path(&self, shard: usize, file_name: &str) -> PathBuf {
+        self.directory(shard).join(file_name)
+    }
+
+    /// Looks for the file named `file_name` for `shard` in its own directory first, then in every
+    /// other directory, so that shards are found even after being moved between disks.
+    pub fn find_shard(&self, shard: usize, file_name: &str) -> Option<PathBuf> {
+        let own_directory = self.directory(shard);
+
+        std::iter::once(own_directory)
+            .chain(
+                self.directories
+                    .iter()
+                    .map(PathBuf::as_path)
+                    .filter(|&directory| directory != own_directory),
+            )
+            .map(|directory| directory.join(file_name))
+            .find(|path| path.is_file())
+    }
+
+    /// Fails unless each of the first `shards` shards is placed on a different file system, since
+    /// every stripe has a chunk on every shard.
+    pub fn check_devices(&self, shards: usize) -> io::Result<()> {
+        let mut devices = Vec::with_capacity(shards);
+
+        for shard in 0..shards {
+            let device = device(self.directory(shard))?;
+
+            if let Some(other) = devices.iter().position(|other| *other == device) {
+                return Result::Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Shards {} and {} would be placed on the same file system",
+                        other, shard
+                    ),
+                ));
+            }
+
+            devices.push(device);
+        }
+
+        Result::Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn device(directory: &Path) -> io::Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Result::Ok(fs::metadata(directory)?.dev())
+}
+
+/// Without device IDs, only shards placed in the same directory are detected.
+#[cfg(not(unix))]
+fn device(directory: &Path) -> io::Result<PathBuf> {
+    fs::canonicalize(directory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::scratch_directory;
+
+    #[test]
+    fn directories() {
+        let directories: Vec<PathBuf> = ["a", "b", "c"].iter().map(PathBuf::from).collect();
+
+        let placement = Placement::round_robin(directories.clone());
+        for (shard, directory) in [(0, "a"), (1, "b"), (2, "c"), (3, "a"), (7, "b")] {
+            assert_eq!(placement.directory(shard), Path::new(directory));
+        }
+        assert_eq!(placement.shard_path(4, "shard.4"), Path::new("b/shard.4"));
+
+        let placement = Placement::mapped(directories, vec![2, 2, 0, 1]);
+        for (shard, directory) in [(0, "c"), (1, "c"), (2, "a"), (3, "b")] {
+            assert_eq!(placement.directory(shard), Path::new(directory));
+        }
+    }
+
+    #[test]
+    fn devices_and_moved_shards() {
+        let root = scratch_directory("placement");
+
+        let directories: Vec<PathBuf> =
+            ["a", "b", "c"].iter().map(|name| root.join(name)).collect();
+        for directory in directories.iter() {
+            fs::create_dir_all(directory).unwrap();
+        }
+
+        let placement = Placement::mapped(directories.clone(), vec![0, 1, 0]);
+        let error = placement.check_devices(3).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("Shards 0 and 1"));
+
+        assert!(
+            Placement::round_robin(vec![root.join("missing")])
+                .check_devices(1)
+                .is_err()
+        );
+
+        fs::write(directories[2].join("shard.0"), b"").unwrap();
+        fs::write(directories[1].join("shard.1"), b"").unwrap();
+        assert_eq!(
+            placement.find_shard(0, "shard.0"),
+            Some(directories[2].join("shard.0"))
+        );
+        assert_eq!(
+            placement.find_shard(1, "shard.1"),
+            Some(directories[1].join("shard.1"))
+        );
+        assert_eq!(placement.find_shard(2, "shard.2"), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,248 @@
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+use std::sync::Mutex;
+
+/// Lets encoding carry on when some shard writers fail, as long as at least a minimum number of
+/// shards are still written. Shard writers are wrapped through `writers`, and the wrapped writers
+/// are passed to any of the encoding methods in their place. A writer that fails is dropped from
+/// then on, with its writes silently discarded, and its shard is recorded in `failed_shards` for
+/// repair later. The first failure that leaves fewer than the minimum number of working shards
+/// is returned as an error and aborts encoding.
+///
+/// A minimum of `data_shards + 1` keeps every stripe recoverable with one erasure to spare.
+#[derive(Debug)]
+pub struct WritePolicy {
+    min_shards: usize,
+    state: Mutex<PolicyState>,
+}
+
+#[derive(Debug, Default)]
+struct PolicyState {
+    shards: usize,
+    failed: Vec<usize>,
+}
+
+impl WritePolicy {
+    pub fn new(min_shards: usize) -> WritePolicy {
+        WritePolicy {
+            min_shards,
+            state: Mutex::new(PolicyState::default()),
+        }
+    }
+
+    pub fn min_shards(&self) -> usize {
+        self.min_shards
+    }
+
+    /// Wraps the writers of a shard set, in shard order. Shards without a writer, such as those
+    /// whose file could not be created, count as failed from the start.
+    pub fn writers<W: Write>(
+        &self,
+        shard_writers: impl IntoIterator<Item = Option<W>>,
+    ) -> io::Result<Vec<PolicyWriter<'_, W>>> {
+        let writers: Vec<PolicyWriter<W>> = shard_writers
+            .into_iter()
+            .enumerate()
+            .map(|(shard, writer)| PolicyWriter {
+                writer,
+                shard,
+                policy: self,
+            })
+            .collect();
+
+        let mut state = self.state.lock().unwrap();
+        state.shards = writers.len();
+        state.failed = writers
+            .iter()
+            .filter(|writer| writer.writer.is_none())
+            .map(|writer| writer.shard)
+            .collect();
+
+        if state.shards - state.failed.len() < self.min_shards {
+            return Result::Err(io::Error::other(format!(
+                "Only {} of {} shards can be written, but at least {} are required",
+                state.shards - state.failed.len(),
+                state.shards,
+                self.min_shards
+            )));
+        }
+
+        drop(state);
+        Result::Ok(writers)
+    }
+
+    /// Returns the shards whose writers failed, in ascending order. Their contents are
+    /// incomplete and have to be rebuilt from the other shards.
+    pub fn failed_shards(&self) -> Vec<usize> {
+        let mut failed = self.state.lock().unwrap().failed.clone();
+        failed.sort_unstable();
+        failed
+    }
+
+    /// Records the failure of `shard`, and returns it as an error if too few shards are left.
+    fn fail(&self, shard: usize, error: io::Error) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.failed.push(shard);
+
+        let working = state.shards - state.failed.len();
+        if working < self.min_shards {
+            return Result::Err(io::Error::new(
+                error.kind(),
+                format!(
+                    "Shard {} failed, leaving {} working shards where at least {} are required: {}",
+                    shard, working, self.min_shards, error
+                ),
+            ));
+        }
+
+        Result::Ok(())
+    }
+}
+
+/// A shard writer governed by a `WritePolicy`, which absorbs its failures while enough other
+/// shards are still written.
+#[derive(Debug)]
+pub struct PolicyWriter<'a, W> {
+    writer: Option<W>,
+    shard: usize,
+    policy: &'a WritePolicy,
+}
+
+impl<W> PolicyWriter<'_, W> {
+    /// Returns the underlying writer, or None if it failed.
+    pub fn into_inner(self) -> Option<W> {
+        self.writer
+    }
+
+    /// Runs `operation` on the underlying writer unless it already failed, in which case, or if
+    /// it fails now, `absorbed` is returned instead.
+    fn attempt<T>(
+        &mut self,
+        absorbed: T,
+        operation: impl FnOnce(&mut W) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let Some(writer) = &mut self.writer else {
+            return Result::Ok(absorbed);
+        };
+
+        match operation(writer) {
+            Result::Ok(value) => Result::Ok(value),
+            Result::Err(error) if error.kind() == io::ErrorKind::Interrupted => Result::Err(error),
+            Result::Err(error) => {
+                self.writer = None;
+                self.policy.fail(self.shard, error)?;
+                Result::Ok(absorbed)
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for PolicyWriter<'_, W> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.attempt(buffer.len(), |writer| writer.write(buffer))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.attempt((), W::flush)
+    }
+}
+
+impl<W: Seek> Seek for PolicyWriter<'_, W> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.attempt(0, |writer| writer.seek(position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ReedSolomonEncoder;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+    use std::io::Cursor;
+
+    /// Accepts `capacity` bytes, like a disk filling up, and fails every write after that.
+    struct FullDisk {
+        contents: Vec<u8>,
+        capacity: usize,
+    }
+
+    impl Write for FullDisk {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            let count = buffer.len().min(self.capacity - self.contents.len());
+            if count == 0 && !buffer.is_empty() {
+                return Result::Err(io::Error::new(
+                    io::ErrorKind::StorageFull,
+                    "No space left on device",
+                ));
+            }
+
+            self.contents.extend_from_slice(&buffer[..count]);
+            Result::Ok(count)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Result::Ok(())
+        }
+    }
+
+    fn disks(capacities: &[usize]) -> Vec<Option<FullDisk>> {
+        capacities
+            .iter()
+            .map(|&capacity| {
+                Some(FullDisk {
+                    contents: vec![],
+                    capacity,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn failed_shards_are_dropped() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let mut buffer = vec![0u8; 16 * 1024 + 17];
+        rng.fill_bytes(&mut buffer);
+
+        let policy = WritePolicy::new(5);
+        let mut writers = policy
+            .writers(disks(&[
+                usize::MAX,
+                usize::MAX,
+                3000,
+                usize::MAX,
+                usize::MAX,
+                5,
+            ]))
+            .unwrap();
+        let error = encoder
+            .encode(&mut Cursor::new(&buffer), buffer.len(), &mut writers)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::StorageFull);
+        assert_eq!(policy.failed_shards(), vec![2, 5]);
+
+        let mut shards = disks(&[usize::MAX; 6]);
+        shards[0] = None;
+        shards[1] = None;
+        assert!(WritePolicy::new(5).writers(shards).is_err());
+
+        let policy = WritePolicy::new(4);
+        let mut shards = disks(&[usize::MAX; 6]);
+        shards[3] = None;
+        shards[1].as_mut().unwrap().capacity = 3000;
+        let mut writers = policy.writers(shards).unwrap();
+        encoder
+            .encode(&mut Cursor::new(&buffer), buffer.len(), &mut writers)
+            .unwrap();
+        assert_eq!(policy.failed_shards(), vec![1, 3]);
+
+        let mut readers: Vec<Option<Cursor<Vec<u8>>>> = writers
+            .into_iter()
+            .map(|writer| writer.into_inner().map(|disk| Cursor::new(disk.contents)))
+            .collect();
+        let mut output = vec![];
+        encoder.decode(&mut readers, &mut output).unwrap();
+        assert!(output == buffer);
+    }
+}
@@ -0,0 +1,250 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Progress of a long-running operation, reported after every stripe.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Bytes of data processed so far. For compressed shard sets, this counts compressed bytes.
+    pub bytes: u64,
+    /// Bytes the operation processes in total, if known.
+    pub total_bytes: Option<u64>,
+    pub stripes: u64,
+    /// Chunks that were missing or corrupted and had to be reconstructed, or, when verifying, that
+    /// were found to be damaged.
+    pub repaired_chunks: u64,
+}
+
+/// Receives progress reports from encoders. Reports come from the thread running the operation,
+/// so observers should return quickly.
+pub trait ProgressObserver: Send + Sync {
+    fn on_progress(&self, progress: &Progress);
+}
+
+/// Cancels operations from another thread. Cancellation is checked between stripes, after which
+/// the operation fails with an error for which `is_cancellation` returns true. Shard files left
+/// behind by a cancelled operation are incomplete.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "Operation was cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+/// Returns whether `error` reports that an operation was cancelled through a
+/// `CancellationToken`.
+pub fn is_cancellation(error: &io::Error) -> bool {
+    error.get_ref().is_some_and(|inner| inner.is::<Cancelled>())
+}
+
+/// Tracks the progress of one operation, reporting it to the observer and checking for
+/// cancellation. Encoders hold an idle monitor that `start` copies for every operation.
+#[derive(Clone, Default)]
+pub(crate) struct Monitor {
+    observer: Option<Arc<dyn ProgressObserver>>,
+    cancellation: Option<CancellationToken>,
+    progress: Progress,
+}
+
+impl Monitor {
+    pub fn with_observer(self, observer: Arc<dyn ProgressObserver>) -> Monitor {
+        Monitor {
+            observer: Some(observer),
+            ..self
+        }
+    }
+
+    pub fn with_cancellation(self, cancellation: CancellationToken) -> Monitor {
+        Monitor {
+            cancellation: Some(cancellation),
+            ..self
+        }
+    }
+
+    /// Returns a monitor for a new operation processing `total_bytes`, if known.
+    pub fn start(&self, total_bytes: Option<usize>) -> Monitor {
+        Monitor {
+            progress: Progress {
+                total_bytes: total_bytes.map(|total| total as u64),
+                ..Progress::default()
+            },
+            ..self.clone()
+        }
+    }
+
+    pub fn set_total_bytes(&mut self, total_bytes: usize) {
+        self.progress.total_bytes = Some(total_bytes as u64);
+    }
+
+    /// Records a finished stripe holding up to `bytes` bytes of data and fails if the operation
+    /// has been cancelled, so that cancellation takes effect between stripes.
+    pub fn stripe_done(&mut self, bytes: usize, repaired_chunks: usize) -> io::Result<()> {
+        let progress = &mut self.progress;
+        progress.bytes += bytes as u64;
+        if let Some(total_bytes) = progress.total_bytes {
+            progress.bytes = progress.bytes.min(total_bytes);
+        }
+        progress.stripes += 1;
+        progress.repaired_chunks += repaired_chunks as u64;
+
+        if let Some(observer) = &self.observer {
+            observer.on_progress(progress);
+        }
+
+        if self
+            .cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            return Result::Err(io::Error::other(Cancelled));
+        }
+
+        Result::Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ReedSolomonEncoder;
+    use std::io::Cursor;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder {
+        reports: Mutex<Vec<Progress>>,
+        cancel_after: Option<(u64, CancellationToken)>,
+    }
+
+    impl ProgressObserver for Recorder {
+        fn on_progress(&self, progress: &Progress) {
+            self.reports.lock().unwrap().push(*progress);
+
+            if let Some((stripes, token)) = &self.cancel_after
+                && progress.stripes == *stripes
+            {
+                token.cancel();
+            }
+        }
+    }
+
+    impl Recorder {
+        fn last(&self) -> Progress {
+            *self.reports.lock().unwrap().last().unwrap()
+        }
+    }
+
+    #[test]
+    fn reports_every_stripe() {
+        let length = 16 * 1024 + 17;
+        let data = vec![7u8; length];
+        let recorder = Arc::new(Recorder::default());
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024).with_progress(recorder.clone());
+
+        let mut writers: Vec<Cursor<Vec<u8>>> = (0..6).map(|_| Cursor::new(vec![])).collect();
+        encoder
+            .encode(&mut Cursor::new(&data), length, &mut writers)
+            .unwrap();
+
+        let reports = recorder.reports.lock().unwrap().clone();
+        assert_eq!(reports.len(), 5);
+        assert_eq!(reports[0].bytes, 4096);
+        assert_eq!(
+            reports[4],
+            Progress {
+                bytes: length as u64,
+                total_bytes: Some(length as u64),
+                stripes: 5,
+                repaired_chunks: 0,
+            }
+        );
+
+        let recorder = Arc::new(Recorder::default());
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024).with_progress(recorder.clone());
+        let mut readers: Vec<Option<Cursor<Vec<u8>>>> = writers
+            .into_iter()
+            .enumerate()
+            .map(|(shard, writer)| (shard != 1).then(|| Cursor::new(writer.into_inner())))
+            .collect();
+
+        let mut output = vec![];
+        encoder.decode(&mut readers, &mut output).unwrap();
+        assert!(output == data);
+        assert_eq!(recorder.last().stripes, 5);
+        assert_eq!(recorder.last().repaired_chunks, 5);
+        assert_eq!(recorder.last().total_bytes, Some(length as u64));
+
+        let recorder = Arc::new(Recorder::default());
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024).with_progress(recorder.clone());
+        let mut output = vec![];
+        encoder
+            .decode_at(&mut readers, &mut output, 5000, 4000)
+            .unwrap();
+        assert!(output == data[5000..9000]);
+        assert_eq!(
+            recorder.last(),
+            Progress {
+                bytes: 2 * 4096,
+                total_bytes: Some(2 * 4096),
+                stripes: 2,
+                repaired_chunks: 2,
+            }
+        );
+
+        let recorder = Arc::new(Recorder::default());
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024).with_progress(recorder.clone());
+        let mut output = vec![];
+        encoder.repair(1, &mut readers, &mut output).unwrap();
+        assert_eq!(recorder.last().stripes, 5);
+        assert_eq!(recorder.last().repaired_chunks, 5);
+        assert_eq!(recorder.last().bytes, length as u64);
+    }
+
+    #[test]
+    fn cancellation() {
+        let length = 16 * 1024;
+        let token = CancellationToken::new();
+        let recorder = Arc::new(Recorder {
+            cancel_after: Some((2, token.clone())),
+            ..Recorder::default()
+        });
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024)
+            .with_progress(recorder.clone())
+            .with_cancellation(token.clone());
+
+        let mut writers: Vec<Cursor<Vec<u8>>> = (0..6).map(|_| Cursor::new(vec![])).collect();
+        let error = encoder
+            .encode(&mut Cursor::new(vec![0u8; length]), length, &mut writers)
+            .unwrap_err();
+
+        assert!(is_cancellation(&error));
+        assert!(token.is_cancelled());
+        assert_eq!(recorder.last().stripes, 2);
+        assert!(!is_cancellation(&io::Error::other("other")));
+    }
+}
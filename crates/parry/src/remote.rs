@@ -0,0 +1,451 @@
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::store::{ShardStore, ShardWriter};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Amount of shard data sent in each chunk of an upload.
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+/// Longest error message read from a response.
+const MAX_ERROR_SIZE: u64 = 4096;
+
+/// Stores shards on nodes running `parry-server`, spreading the shards of every object over
+/// `servers` in turn. Shards are spooled to temporary files while they are encoded and only sent
+/// when they are committed, one at a time, so that an upload holds a connection and a server
+/// thread for no longer than the transfer takes, however many shards a node stores. Reads from
+/// servers that are down or fail make the shard unavailable, which `get_object` and
+/// `get_object_at` treat as an erasure, so objects are decoded from whichever servers respond.
+///
+/// Servers speak a small subset of HTTP/1.1, one request per connection:
+///
+/// - `PUT /shards/{object}/{shard}` stores a shard sent with chunked transfer encoding.
+/// - `GET /shards/{object}/{shard}` reads a shard, or the part of it given by a `Range` header.
+/// - `HEAD /shards/{object}/{shard}` returns the size of a shard as its Content-Length.
+/// - `DELETE /shards/{object}/{shard}` removes a shard.
+/// - `GET /shards/{object}` lists the indices of the stored shards, one per line.
+/// - `GET /checksum/{object}/{shard}` returns the hex-encoded BLAKE3 hash of a whole shard.
+#[derive(Clone, Debug)]
+pub struct RemoteStore {
+    servers: Vec<String>,
+    timeout: Duration,
+}
+
+impl RemoteStore {
+    /// Connects to servers at `host:port` addresses.
+    pub fn new(servers: Vec<String>) -> RemoteStore {
+        assert!(!servers.is_empty(), "At least one server is required");
+
+        RemoteStore {
+            servers,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Fails requests to servers that take longer than `timeout` to connect or to respond, so
+    /// that a server that hangs counts as failed. Defaults to 30 seconds.
+    pub fn with_timeout(self, timeout: Duration) -> RemoteStore {
+        RemoteStore { timeout, ..self }
+    }
+
+    /// Returns the BLAKE3 hash of shard `shard` of `object` as computed by its server, so that
+    /// a shard can be compared against a known copy without transferring it.
+    pub fn checksum(&self, object: &str, shard: usize) -> io::Result<[u8; 32]> {
+        let path = format!("/checksum/{}/{}", encode(object), shard);
+        let mut response = self.request(self.server(shard), "GET", &path, None)?;
+
+        let mut hex = String::new();
+        response.body.read_to_string(&mut hex)?;
+
+        let hex = hex.trim();
+        let mut hash = [0u8; 32];
+        if hex.len() != 64 {
+            return Result::Err(invalid_response("Malformed checksum"));
+        }
+        for (byte, digits) in hash.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            *byte = std::str::from_utf8(digits)
+                .ok()
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or_else(|| invalid_response("Malformed checksum"))?;
+        }
+
+        Result::Ok(hash)
+    }
+
+    fn server(&self, shard: usize) -> &str {
+        &self.servers[shard % self.servers.len()]
+    }
+
+    fn connect(&self, server: &str) -> io::Result<TcpStream> {
+        let mut last_error = None;
+
+        for address in server.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, self.timeout) {
+                Result::Ok(stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    stream.set_nodelay(true)?;
+                    return Result::Ok(stream);
+                }
+                Result::Err(error) => last_error = Some(error),
+            }
+        }
+
+        Result::Err(last_error.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} does not resolve to any address", server),
+            )
+        }))
+    }
+
+    /// Sends a request with the head of `method` and `path` and any extra header lines, without
+    /// a body, leaving the connection open for one.
+    fn send(
+        &self,
+        server: &str,
+        method: &str,
+        path: &str,
+        extra_headers: &str,
+    ) -> io::Result<TcpStream> {
+        let mut stream = self.connect(server)?;
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n{}\r\n",
+            method, path, server, extra_headers
+        )?;
+        Result::Ok(stream)
+    }
+
+    /// Sends a request without a body and reads the head of the response, whatever its status.
+    fn exchange(
+        &self,
+        server: &str,
+        method: &str,
+        path: &str,
+        range: Option<(u64, u64)>,
+    ) -> io::Result<Response> {
+        let extra_headers = match range {
+            Some((first, last)) => format!("Range: bytes={}-{}\r\n", first, last),
+            None => String::new(),
+        };
+
+        let stream = self.send(server, method, path, &extra_headers)?;
+        Response::read(stream, method == "HEAD")
+    }
+
+    fn request(
+        &self,
+        server: &str,
+        method: &str,
+        path: &str,
+        range: Option<(u64, u64)>,
+    ) -> io::Result<Response> {
+        self.exchange(server, method, path, range)?
+            .success(method, server, path)
+    }
+}
+
+/// A response whose body has yet to be read.
+struct Response {
+    status: u16,
+    content_length: u64,
+    body: io::Take<BufReader<TcpStream>>,
+}
+
+impl Response {
+    /// Reads the head of a response. Bodies are expected to have a Content-Length, which the
+    /// server always sends.
+    fn read(stream: TcpStream, head_only: bool) -> io::Result<Response> {
+        let mut reader = BufReader::new(stream);
+
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line)?;
+        let status: u16 = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| invalid_response("Malformed status line"))?;
+
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Result::Err(invalid_response("Truncated response head"));
+            }
+
+            let Some((name, value)) = line.trim_end().split_once(':') else {
+                break;
+            };
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid_response("Malformed Content-Length"))?;
+            }
+        }
+
+        let body_length = if head_only { 0 } else { content_length };
+        Result::Ok(Response {
+            status,
+            content_length,
+            body: reader.take(body_length),
+        })
+    }
+
+    /// Turns error statuses into errors carrying the message the server sent.
+    fn success(mut self, method: &str, server: &str, path: &str) -> io::Result<Response> {
+        if (200..300).contains(&self.status) {
+            return Result::Ok(self);
+        }
+
+        let mut message = String::new();
+        let _ = (&mut self.body)
+            .take(MAX_ERROR_SIZE)
+            .read_to_string(&mut message);
+        let message = format!(
+            "{} {} on {} failed with HTTP status {}: {}",
+            method,
+            path,
+            server,
+            self.status,
+            message.trim()
+        );
+
+        Result::Err(match self.status {
+            404 => io::Error::new(io::ErrorKind::NotFound, message),
+            400 => io::Error::new(io::ErrorKind::InvalidInput, message),
+            _ => io::Error::other(message),
+        })
+    }
+}
+
+fn invalid_response(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Percent-encodes an object name for use as one segment of a request path.
+fn encode(object: &str) -> String {
+    let mut encoded = String::with_capacity(object.len());
+
+    for &byte in object.as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => {
+                let _ = write!(encoded, "%{:02X}", byte);
+            }
+        }
+    }
+
+    encoded
+}
+
+fn shard_path(object: &str, shard: usize) -> String {
+    format!("/shards/{}/{}", encode(object), shard)
+}
+
+impl ShardStore for RemoteStore {
+    fn put(&self, object: &str, shard: usize) -> io::Result<Box<dyn ShardWriter>> {
+        Result::Ok(Box::new(RemoteShardWriter {
+            spool: Spool::create()?,
+            store: self.clone(),
+            server: self.server(shard).to_string(),
+            path: shard_path(object, shard),
+        }))
+    }
+
+    fn get(&self, object: &str, shard: usize) -> io::Result<Box<dyn Read + Send>> {
+        let response = self.request(self.server(shard), "GET", &shard_path(object, shard), None)?;
+        Result::Ok(Box::new(response.body))
+    }
+
+    fn get_range(
+        &self,
+        object: &str,
+        shard: usize,
+        offset: u64,
+        length: u64,
+    ) -> io::Result<Box<dyn Read + Send>> {
+        if length == 0 {
+            return Result::Ok(Box::new(io::empty()));
+        }
+
+        let server = self.server(shard);
+        let path = shard_path(object, shard);
+        let range = (offset, offset.saturating_add(length - 1));
+        let response = self.exchange(server, "GET", &path, Some(range))?;
+
+        // The range starts past the end of the shard.
+        if response.status == 416 {
+            return Result::Ok(Box::new(io::empty()));
+        }
+
+        Result::Ok(Box::new(response.success("GET", server, &path)?.body))
+    }
+
+    fn size(&self, object: &str, shard: usize) -> io::Result<u64> {
+        let response =
+            self.request(self.server(shard), "HEAD", &shard_path(object, shard), None)?;
+        Result::Ok(response.content_length)
+    }
+
+    fn delete(&self, object: &str, shard: usize) -> io::Result<()> {
+        self.request(
+            self.server(shard),
+            "DELETE",
+            &shard_path(object, shard),
+            None,
+        )?;
+        Result::Ok(())
+    }
+
+    /// Lists the shards stored on servers that respond. Shards on servers that fail to respond
+    /// are left out, like erasures, and an error is only returned if no server responds.
+    fn list(&self, object: &str) -> io::Result<Vec<usize>> {
+        let path = format!("/shards/{}", encode(object));
+        let mut shards = vec![];
+        let mut last_error = None;
+        let mut responded = false;
+
+        for (index, server) in self.servers.iter().enumerate() {
+            // Servers listed more than once are only asked once.
+            if self.servers[..index].contains(server) {
+                continue;
+            }
+
+            let mut response = match self.request(server, "GET", &path, None) {
+                Result::Ok(response) => response,
+                Result::Err(error) => {
+                    last_error = Some(error);
+                    continue;
+                }
+            };
+            responded = true;
+
+            let mut listing = String::new();
+            response.body.read_to_string(&mut listing)?;
+
+            // Shards stored on another server than their own would not be found by `get`.
+            shards.extend(
+                listing
+                    .lines()
+                    .filter_map(|line| line.trim().parse::<usize>().ok())
+                    .filter(|&shard| self.server(shard) == server),
+            );
+        }
+
+        match last_error {
+            Some(error) if !responded => Result::Err(error),
+            _ => {
+                shards.sort_unstable();
+                shards.dedup();
+                Result::Ok(shards)
+            }
+        }
+    }
+}
+
+/// Sends everything written as one chunk of a chunked request body.
+struct ChunkedWriter {
+    stream: TcpStream,
+}
+
+impl Write for ChunkedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Result::Ok(0);
+        }
+
+        write!(self.stream, "{:x}\r\n", buf.len())?;
+        self.stream.write_all(buf)?;
+        self.stream.write_all(b"\r\n")?;
+        Result::Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// A temporary file holding a shard until it is uploaded, which is removed when dropped.
+struct Spool {
+    file: BufWriter<File>,
+    path: PathBuf,
+}
+
+impl Spool {
+    fn create() -> io::Result<Spool> {
+        let path = std::env::temp_dir().join(format!(
+            ".parry-upload-{}-{:016x}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+
+        Result::Ok(Spool {
+            file: BufWriter::with_capacity(UPLOAD_CHUNK_SIZE, file),
+            path,
+        })
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Spools a shard and uploads it to its server on commit. The server only stores the shard
+/// once the final chunk arrives, so an upload that fails part way is discarded, as is a writer
+/// dropped without committing.
+struct RemoteShardWriter {
+    spool: Spool,
+    store: RemoteStore,
+    server: String,
+    path: String,
+}
+
+impl Write for RemoteShardWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.spool.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.spool.file.flush()
+    }
+}
+
+impl ShardWriter for RemoteShardWriter {
+    fn commit(mut self: Box<Self>) -> io::Result<()> {
+        self.spool.file.flush()?;
+        let file = self.spool.file.get_mut();
+        file.seek(SeekFrom::Start(0))?;
+
+        let stream = self.store.send(
+            &self.server,
+            "PUT",
+            &self.path,
+            "Transfer-Encoding: chunked\r\n",
+        )?;
+        let mut writer = BufWriter::with_capacity(UPLOAD_CHUNK_SIZE, ChunkedWriter { stream });
+        io::copy(file, &mut writer)?;
+
+        let mut stream = writer
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?
+            .stream;
+        stream.write_all(b"0\r\n\r\n")?;
+
+        Response::read(stream, false)?.success("PUT", &self.server, &self.path)?;
+        Result::Ok(())
+    }
+}
@@ -0,0 +1,505 @@
+use std::collections::BTreeSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::checkpoint::Checkpoint;
+
+/// Creates the files of a shard set so that none of them appears truncated under its name. Each
+/// shard is written to a hidden temporary file next to its final path. `commit` flushes and syncs
+/// every temporary file before renaming any of them into place, then syncs the directories
+/// holding them, so that every file at a final path is a complete shard. Files already at the
+/// final paths are moved aside to hidden `.old` files until the commit succeeds. When a commit
+/// fails with an error, the renames done so far are undone, which leaves the shard set it would
+/// have replaced intact. A crash in the middle of the renames is not undone: it can leave some
+/// final paths holding shards of the new set, some holding those of the old one and some empty,
+/// with the old shards that were moved aside still in their `.old` files. Decoding treats shards
+/// of the other set as missing, so either set can still be decoded as long as enough of its
+/// shards are in place, and the `.old` files can be moved back by hand. Dropping a sink without
+/// committing it removes the temporary files, unless a checkpoint was recorded through
+/// `checkpoints`, in which case they are kept for `resume`.
+#[derive(Debug)]
+pub struct ShardSink {
+    files: Vec<ShardFile>,
+    paths: Vec<PathBuf>,
+    temporary_paths: Vec<PathBuf>,
+    backup_paths: Vec<PathBuf>,
+    checkpoint_path: PathBuf,
+    discarded: Vec<bool>,
+}
+
+/// The buffered temporary file of a shard in a `ShardSink`. It can be read back as well, which
+/// resuming an encode relies on.
+#[derive(Debug)]
+pub struct ShardFile {
+    file: BufWriter<File>,
+}
+
+impl ShardFile {
+    pub fn get_ref(&self) -> &File {
+        self.file.get_ref()
+    }
+}
+
+impl Write for ShardFile {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.file.write(buffer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Read for ShardFile {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.file.flush()?;
+        self.file.get_mut().read(buffer)
+    }
+}
+
+impl Seek for ShardFile {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.file.seek(position)
+    }
+}
+
+/// Records checkpoints of the shards being written to a `ShardSink`.
+#[derive(Debug)]
+pub struct CheckpointRecorder {
+    files: Vec<File>,
+    checkpoint_path: PathBuf,
+}
+
+impl ShardSink {
+    /// Creates the temporary files of shards to be committed to `paths`, in shard order. They
+    /// are opened for reading as well, so that they can be memory-mapped.
+    pub fn create(paths: impl IntoIterator<Item = PathBuf>) -> io::Result<ShardSink> {
+        let sink = ShardSink::open(paths, true)?;
+
+        // A checkpoint left by an earlier sink no longer matches the truncated files.
+        match fs::remove_file(&sink.checkpoint_path) {
+            Result::Err(error) if error.kind() != io::ErrorKind::NotFound => Result::Err(error),
+            _ => Result::Ok(sink),
+        }
+    }
+
+    /// Reopens the temporary files of a sink for `paths` that was dropped or interrupted after
+    /// recording a checkpoint, returning them along with the last checkpoint, so that the encode
+    /// can be continued with `ReedSolomonEncoder::resume_encode`.
+    pub fn resume(paths: impl IntoIterator<Item = PathBuf>) -> io::Result<(ShardSink, Checkpoint)> {
+        let sink = ShardSink::open(paths, false)?;
+        let checkpoint = File::open(&sink.checkpoint_path)
+            .and_then(|mut file| Checkpoint::read_from(&mut file))
+            .map_err(|error| {
+                io::Error::new(
+                    error.kind(),
+                    format!("{}: {}", sink.checkpoint_path.display(), error),
+                )
+            })?;
+
+        Result::Ok((sink, checkpoint))
+    }
+
+    fn open(paths: impl IntoIterator<Item = PathBuf>, create: bool) -> io::Result<ShardSink> {
+        let paths: Vec<PathBuf> = paths.into_iter().collect();
+        let first_path = paths.first().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "A shard set needs shards")
+        })?;
+
+        let mut sink = ShardSink {
+            files: vec![],
+            paths: vec![],
+            temporary_paths: vec![],
+            backup_paths: vec![],
+            checkpoint_path: checkpoint_path(first_path)?,
+            discarded: vec![],
+        };
+
+        for path in paths {
+            let temporary_path = temporary_path(&path)?;
+            let backup_path = temporary_path.with_extension("old");
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(create)
+                .truncate(create)
+                .open(&temporary_path)
+                .map_err(|error| {
+                    io::Error::new(error.kind(), format!("{}: {}", path.display(), error))
+                })?;
+
+            sink.files.push(ShardFile {
+                file: BufWriter::new(file),
+            });
+            sink.paths.push(path);
+            sink.temporary_paths.push(temporary_path);
+            sink.backup_paths.push(backup_path);
+            sink.discarded.push(false);
+        }
+
+        Result::Ok(sink)
+    }
+
+    /// Returns a recorder of checkpoints of the shards being written, to be called from
+    /// `ReedSolomonEncoder::encode_with_checkpoints`.
+    pub fn checkpoints(&self) -> io::Result<CheckpointRecorder> {
+        let files = self
+            .files
+            .iter()
+            .map(|file| file.get_ref().try_clone())
+            .collect::<io::Result<Vec<File>>>()?;
+
+        Result::Ok(CheckpointRecorder {
+            files,
+            checkpoint_path: self.checkpoint_path.clone(),
+        })
+    }
+
+    /// Returns the writers of the shards, to be passed to the encoding methods.
+    pub fn writers(&mut self) -> &mut [ShardFile] {
+        &mut self.files
+    }
+
+    /// Returns the final paths of the shards.
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// Leaves `shard` out of the commit, such as after its writer failed, so that no incomplete
+    /// file appears in its place.
+    pub fn discard(&mut self, shard: usize) {
+        self.discarded[shard] = true;
+    }
+
+    /// Makes the shards durable and renames them into place. Should a rename fail, the shards
+    /// already renamed are removed again and the files they replaced are restored. Once every
+    /// shard is in place the commit has succeeded, so failing to remove the checkpoint or to sync
+    /// the directories afterwards is not reported, as the shards are complete either way.
+    pub fn commit(mut self) -> io::Result<()> {
+        let shards: Vec<usize> = (0..self.files.len())
+            .filter(|&shard| !self.discarded[shard])
+            .collect();
+
+        for &shard in &shards {
+            let file = &mut self.files[shard];
+            file.flush()
+                .and_then(|()| file.get_ref().sync_all())
+                .map_err(|error| self.describe(shard, error))?;
+        }
+
+        let mut placed: Vec<(usize, bool)> = vec![];
+        for &shard in &shards {
+            match self.place(shard) {
+                Result::Ok(replaced) => placed.push((shard, replaced)),
+                Result::Err(error) => {
+                    for &(shard, replaced) in placed.iter().rev() {
+                        let _ = if replaced {
+                            fs::rename(&self.backup_paths[shard], &self.paths[shard])
+                        } else {
+                            fs::remove_file(&self.paths[shard])
+                        };
+                    }
+
+                    return Result::Err(self.describe(shard, error));
+                }
+            }
+        }
+
+        let _ = fs::remove_file(&self.checkpoint_path);
+
+        let directories: BTreeSet<&Path> = shards
+            .iter()
+            .map(|&shard| parent_directory(&self.paths[shard]))
+            .chain([parent_directory(&self.checkpoint_path)])
+            .collect();
+        for directory in directories {
+            let _ = sync_directory(directory);
+        }
+
+        for (shard, replaced) in placed {
+            if replaced {
+                let _ = fs::remove_file(&self.backup_paths[shard]);
+            }
+        }
+
+        Result::Ok(())
+    }
+
+    /// Renames `shard` into place, moving any file already there aside first. Returns whether a
+    /// file was moved aside.
+    fn place(&self, shard: usize) -> io::Result<bool> {
+        let path = &self.paths[shard];
+        let backup_path = &self.backup_paths[shard];
+
+        // Directories are left where they are, which makes the rename fail.
+        let replaced = fs::symlink_metadata(path).is_ok_and(|metadata| !metadata.is_dir());
+        if replaced {
+            fs::rename(path, backup_path)?;
+        }
+
+        match fs::rename(&self.temporary_paths[shard], path) {
+            Result::Ok(()) => Result::Ok(replaced),
+            Result::Err(error) => {
+                if replaced {
+                    let _ = fs::rename(backup_path, path);
+                }
+                Result::Err(error)
+            }
+        }
+    }
+
+    fn describe(&self, shard: usize, error: io::Error) -> io::Error {
+        io::Error::new(
+            error.kind(),
+            format!("{}: {}", self.paths[shard].display(), error),
+        )
+    }
+}
+
+impl CheckpointRecorder {
+    /// Makes everything written to the shards so far durable, then replaces the checkpoint of
+    /// the sink with `checkpoint`. The shard writers must have been flushed.
+    pub fn record(&self, checkpoint: Checkpoint) -> io::Result<()> {
+        for file in &self.files {
+            file.sync_data()?;
+        }
+
+        let temporary_path = temporary_path(&self.checkpoint_path)?;
+        let mut file = File::create(&temporary_path)?;
+        checkpoint.write_to(&mut file)?;
+        commit_file(&file, &temporary_path, &self.checkpoint_path)
+    }
+}
+
+impl Drop for ShardSink {
+    fn drop(&mut self) {
+        if self.checkpoint_path.exists() {
+            return;
+        }
+
+        // After a commit, only the temporary files of discarded shards are left.
+        for temporary_path in &self.temporary_paths {
+            let _ = fs::remove_file(temporary_path);
+        }
+    }
+}
+
+/// Returns the hidden file next to the first shard at `path` that the checkpoints of its shard
+/// set are recorded in, such as `.shard.0.checkpoint` for `shard.0`.
+fn checkpoint_path(path: &Path) -> io::Result<PathBuf> {
+    let temporary_path = temporary_path(path)?;
+    Result::Ok(temporary_path.with_extension("checkpoint"))
+}
+
+/// Returns the hidden file next to `path` that its contents are written to before being renamed
+/// into place, such as `.shard.3.tmp` for `shard.3`.
+pub(crate) fn temporary_path(path: &Path) -> io::Result<PathBuf> {
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} does not name a file", path.display()),
+        )
+    })?;
+
+    let mut temporary_name = ".".to_string();
+    temporary_name.push_str(&file_name.to_string_lossy());
+    temporary_name.push_str(".tmp");
+    Result::Ok(path.with_file_name(temporary_name))
+}
+
+fn parent_directory(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Makes the entries of `directory`, such as files renamed into it, durable. Only Unix supports
+/// syncing directories, and elsewhere renames are left to the file system.
+pub(crate) fn sync_directory(directory: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(directory)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = directory;
+
+    Result::Ok(())
+}
+
+/// Syncs `file` and renames it from `temporary_path` to `path`, syncing the directory holding
+/// both.
+pub(crate) fn commit_file(file: &File, temporary_path: &Path, path: &Path) -> io::Result<()> {
+    file.sync_all()?;
+    fs::rename(temporary_path, path)?;
+    sync_directory(parent_directory(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ReedSolomonEncoder;
+    use crate::test_util::scratch_directory;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+    use std::io::Cursor;
+
+    fn file_names(directory: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn commit() {
+        let directory = scratch_directory("sink-commit");
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let mut buffer = vec![0u8; 10000];
+        rng.fill_bytes(&mut buffer);
+
+        let paths: Vec<PathBuf> = (0..6)
+            .map(|shard| directory.join(format!("shard.{}", shard)))
+            .collect();
+
+        let mut sink = ShardSink::create(paths.clone()).unwrap();
+        encoder
+            .encode(&mut Cursor::new(&buffer), buffer.len(), sink.writers())
+            .unwrap();
+        assert_eq!(file_names(&directory).len(), 6);
+        assert!(file_names(&directory)[0].starts_with(".shard.0"));
+        drop(sink);
+        assert!(file_names(&directory).is_empty());
+
+        let mut sink = ShardSink::create(paths.clone()).unwrap();
+        encoder
+            .encode(&mut Cursor::new(&buffer), buffer.len(), sink.writers())
+            .unwrap();
+        sink.discard(4);
+        sink.commit().unwrap();
+        assert_eq!(
+            file_names(&directory),
+            vec!["shard.0", "shard.1", "shard.2", "shard.3", "shard.5"]
+        );
+
+        let mut readers: Vec<Option<File>> =
+            paths.iter().map(|path| File::open(path).ok()).collect();
+        let mut output = vec![];
+        encoder.decode(&mut readers, &mut output).unwrap();
+        assert!(output == buffer);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn failed_commit_leaves_no_new_shards() {
+        let directory = scratch_directory("sink-failed");
+        let paths = vec![
+            directory.join("shard.0"),
+            directory.join("shard.1"),
+            directory.join("shard.2"),
+        ];
+
+        let mut sink = ShardSink::create(paths.clone()).unwrap();
+        for writer in sink.writers() {
+            writer.write_all(b"contents").unwrap();
+        }
+
+        // A directory in the place of a shard makes its rename fail.
+        fs::create_dir(directory.join("shard.2")).unwrap();
+        fs::write(directory.join("shard.2").join("entry"), b"").unwrap();
+        assert!(sink.commit().is_err());
+        assert_eq!(file_names(&directory), vec!["shard.2"]);
+
+        // The shards of a set being replaced are restored.
+        fs::write(directory.join("shard.0"), b"old").unwrap();
+        fs::write(directory.join("shard.1"), b"old").unwrap();
+        let mut sink = ShardSink::create(paths.clone()).unwrap();
+        for writer in sink.writers() {
+            writer.write_all(b"new").unwrap();
+        }
+        assert!(sink.commit().is_err());
+        assert_eq!(
+            file_names(&directory),
+            vec!["shard.0", "shard.1", "shard.2"]
+        );
+        assert_eq!(fs::read(directory.join("shard.0")).unwrap(), b"old");
+        assert_eq!(fs::read(directory.join("shard.1")).unwrap(), b"old");
+
+        fs::remove_dir_all(directory.join("shard.2")).unwrap();
+        let mut sink = ShardSink::create(paths).unwrap();
+        for writer in sink.writers() {
+            writer.write_all(b"new").unwrap();
+        }
+        sink.commit().unwrap();
+        assert_eq!(
+            file_names(&directory),
+            vec!["shard.0", "shard.1", "shard.2"]
+        );
+        assert_eq!(fs::read(directory.join("shard.0")).unwrap(), b"new");
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn resume() {
+        let directory = scratch_directory("sink-resume");
+        let encoder = ReedSolomonEncoder::new(4, 2, 1024);
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let mut buffer = vec![0u8; 50000];
+        rng.fill_bytes(&mut buffer);
+
+        let paths: Vec<PathBuf> = (0..6)
+            .map(|shard| directory.join(format!("shard.{}", shard)))
+            .collect();
+
+        // The encode is interrupted by a failure right after its second checkpoint.
+        let mut sink = ShardSink::create(paths.clone()).unwrap();
+        let recorder = sink.checkpoints().unwrap();
+        let mut recorded = 0;
+        let result = encoder.encode_with_checkpoints(
+            &mut Cursor::new(&buffer),
+            buffer.len(),
+            sink.writers(),
+            3,
+            |checkpoint| {
+                recorder.record(checkpoint)?;
+                recorded += 1;
+                if recorded == 2 {
+                    return Result::Err(io::Error::other("Interrupted"));
+                }
+                Result::Ok(())
+            },
+        );
+        assert!(result.is_err());
+        drop(sink);
+        assert!(file_names(&directory).contains(&".shard.0.checkpoint".to_string()));
+        assert_eq!(file_names(&directory).len(), 7);
+
+        let (mut sink, checkpoint) = ShardSink::resume(paths.clone()).unwrap();
+        assert_eq!(checkpoint.stripes, 6);
+        let recorder = sink.checkpoints().unwrap();
+        let offset = encoder
+            .resume_encode(
+                &mut Cursor::new(&buffer),
+                &checkpoint,
+                sink.writers(),
+                3,
+                |checkpoint| recorder.record(checkpoint),
+            )
+            .unwrap();
+        assert_eq!(offset, 6 * 4096);
+        sink.commit().unwrap();
+        assert_eq!(file_names(&directory).len(), 6);
+
+        let mut readers: Vec<Option<File>> =
+            paths.iter().map(|path| File::open(path).ok()).collect();
+        let mut output = vec![];
+        encoder.decode(&mut readers, &mut output).unwrap();
+        assert!(output == buffer);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+
+/// Reads a file, producing zeros for its holes without reading them. On Linux, holes are found
+/// with `SEEK_DATA` and `SEEK_HOLE`; elsewhere, and on file systems without support for them, the
+/// whole file is read.
+pub struct SparseReader<'a> {
+    file: &'a File,
+    length: u64,
+    position: u64,
+    /// The data region containing or following `position`.
+    data: Range<u64>,
+}
+
+impl<'a> SparseReader<'a> {
+    pub fn new(file: &'a File) -> io::Result<SparseReader<'a>> {
+        Result::Ok(SparseReader {
+            file,
+            length: file.metadata()?.len(),
+            position: 0,
+            data: 0..0,
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+}
+
+/// Returns the first region of data at or after `offset`, or an empty region at `length` if
+/// there is none.
+#[cfg(target_os = "linux")]
+fn next_data(file: &File, offset: u64, length: u64) -> io::Result<Range<u64>> {
+    use std::os::fd::AsRawFd;
+
+    let fd = file.as_raw_fd();
+
+    let start = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_DATA) };
+    if start < 0 {
+        let error = io::Error::last_os_error();
+
+        return match error.raw_os_error() {
+            Some(libc::ENXIO) => Result::Ok(length..length),
+            Some(libc::EINVAL) | Some(libc::EOPNOTSUPP) => Result::Ok(offset..length),
+            _ => Result::Err(error),
+        };
+    }
+
+    let end = unsafe { libc::lseek(fd, start, libc::SEEK_HOLE) };
+    if end < 0 {
+        return Result::Err(io::Error::last_os_error());
+    }
+
+    Result::Ok((start as u64).min(length)..(end as u64).min(length))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn next_data(_file: &File, offset: u64, length: u64) -> io::Result<Range<u64>> {
+    Result::Ok(offset..length)
+}
+
+impl Read for SparseReader<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.length || buffer.is_empty() {
+            return Result::Ok(0);
+        }
+
+        if self.position >= self.data.end {
+            self.data = next_data(self.file, self.position, self.length)?;
+        }
+
+        let count = if self.position < self.data.start {
+            let count = (self.data.start - self.position).min(buffer.len() as u64) as usize;
+            buffer[..count].fill(0);
+            count
+        } else {
+            let available = (self.data.end - self.position).min(buffer.len() as u64) as usize;
+
+            let mut file = self.file;
+            file.seek(SeekFrom::Start(self.position))?;
+            let count = file.read(&mut buffer[..available])?;
+
+            if count == 0 {
+                return Result::Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "File was truncated while being read",
+                ));
+            }
+
+            count
+        };
+
+        self.position += count as u64;
+
+        Result::Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+
+    #[test]
+    fn reads_holes_as_zeros() {
+        let path = std::env::temp_dir().join(format!("parry-sparse-{}", std::process::id()));
+
+        let mut file = File::create(&path).unwrap();
+        file.set_len(1 << 20).unwrap();
+        file.seek(SeekFrom::Start(300000)).unwrap();
+        file.write_all(&[0xab; 5000]).unwrap();
+        file.seek(SeekFrom::Start((1 << 20) - 10)).unwrap();
+        file.write_all(&[0xcd; 10]).unwrap();
+        drop(file);
+
+        let expected = fs::read(&path).unwrap();
+        let file = File::open(&path).unwrap();
+
+        for buffer_size in [1000, 4096, 1 << 20] {
+            let mut reader = SparseReader::new(&file).unwrap();
+            assert_eq!(reader.len(), 1 << 20);
+
+            let mut contents = vec![];
+            let mut buffer = vec![0u8; buffer_size];
+            loop {
+                let count = reader.read(&mut buffer).unwrap();
+                if count == 0 {
+                    break;
+                }
+                contents.extend_from_slice(&buffer[..count]);
+            }
+
+            assert!(contents == expected, "buffer size {}", buffer_size);
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,707 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::ReedSolomonEncoder;
+use crate::hedged::ShardLatencies;
+use crate::placement::Placement;
+use crate::sink::{commit_file, temporary_path};
+
+/// Storage for the shards of named objects, such as local disks, memory or a remote service.
+/// Each shard of an object is stored separately under the object name and the shard index, so
+/// that backends can spread the shards of an object over independent devices or endpoints.
+pub trait ShardStore: Send + Sync {
+    /// Starts writing shard `shard` of `object`. The shard replaces any stored one once the
+    /// returned writer is committed, and is discarded if the writer is dropped instead.
+    fn put(&self, object: &str, shard: usize) -> io::Result<Box<dyn ShardWriter>>;
+
+    /// Reads the whole of shard `shard` of `object`, failing with `ErrorKind::NotFound` if it is
+    /// not stored.
+    fn get(&self, object: &str, shard: usize) -> io::Result<Box<dyn Read + Send>>;
+
+    /// Reads up to `length` bytes of shard `shard` of `object` starting at `offset`.
+    fn get_range(
+        &self,
+        object: &str,
+        shard: usize,
+        offset: u64,
+        length: u64,
+    ) -> io::Result<Box<dyn Read + Send>>;
+
+    /// Returns the size of shard `shard` of `object`.
+    fn size(&self, object: &str, shard: usize) -> io::Result<u64>;
+
+    /// Removes shard `shard` of `object`. Removing a shard that is not stored is not an error.
+    fn delete(&self, object: &str, shard: usize) -> io::Result<()>;
+
+    /// Returns the indices of the stored shards of `object` in ascending order.
+    fn list(&self, object: &str) -> io::Result<Vec<usize>>;
+}
+
+/// Writes a shard to a `ShardStore`.
+pub trait ShardWriter: Write + Send {
+    /// Finishes writing the shard and makes it visible to readers.
+    fn commit(self: Box<Self>) -> io::Result<()>;
+}
+
+fn invalid_object_name(object: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Invalid object name {:?}", object),
+    )
+}
+
+/// Stores every shard as a file named after the object and the shard index, as in
+/// `photo.jpg.3`, in the directory `placement` assigns to the shard. Shards are written to
+/// hidden temporary files that are synced and renamed into place on commit.
+#[derive(Clone, Debug)]
+pub struct FileStore {
+    placement: Placement,
+}
+
+impl FileStore {
+    /// Stores all shards in `directory`.
+    pub fn new(directory: PathBuf) -> FileStore {
+        FileStore::with_placement(Placement::round_robin(vec![directory]))
+    }
+
+    /// Spreads shards over the directories of `placement`, typically one per disk.
+    pub fn with_placement(placement: Placement) -> FileStore {
+        FileStore { placement }
+    }
+
+    /// Returns the name of the file of shard `shard` of `object`. Object names cannot contain
+    /// path separators, so that every object stays within the directories of the store.
+    fn file_name(object: &str, shard: usize) -> io::Result<String> {
+        if object.is_empty()
+            || object == "."
+            || object == ".."
+            || object.contains(['/', '\\', '\0'])
+        {
+            return Result::Err(invalid_object_name(object));
+        }
+
+        Result::Ok(format!("{}.{}", object, shard))
+    }
+
+    fn find(&self, object: &str, shard: usize) -> io::Result<PathBuf> {
+        let file_name = FileStore::file_name(object, shard)?;
+
+        self.placement.find_shard(shard, &file_name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Shard {} of {} is not stored", shard, object),
+            )
+        })
+    }
+}
+
+impl ShardStore for FileStore {
+    fn put(&self, object: &str, shard: usize) -> io::Result<Box<dyn ShardWriter>> {
+        let file_name = FileStore::file_name(object, shard)?;
+        let directory = self.placement.directory(shard);
+        fs::create_dir_all(directory)?;
+
+        let path = directory.join(&file_name);
+        let temporary_path = temporary_path(&path)?;
+        let file = File::create(&temporary_path)?;
+
+        Result::Ok(Box::new(FileShardWriter {
+            file: Some(BufWriter::new(file)),
+            temporary_path,
+            path,
+        }))
+    }
+
+    fn get(&self, object: &str, shard: usize) -> io::Result<Box<dyn Read + Send>> {
+        let file = File::open(self.find(object, shard)?)?;
+        Result::Ok(Box::new(file))
+    }
+
+    fn get_range(
+        &self,
+        object: &str,
+        shard: usize,
+        offset: u64,
+        length: u64,
+    ) -> io::Result<Box<dyn Read + Send>> {
+        let mut file = File::open(self.find(object, shard)?)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Result::Ok(Box::new(file.take(length)))
+    }
+
+    fn size(&self, object: &str, shard: usize) -> io::Result<u64> {
+        Result::Ok(fs::metadata(self.find(object, shard)?)?.len())
+    }
+
+    fn delete(&self, object: &str, shard: usize) -> io::Result<()> {
+        // Shards moved between directories are found and deleted wherever they are.
+        while let Result::Ok(path) = self.find(object, shard) {
+            fs::remove_file(path)?;
+        }
+
+        Result::Ok(())
+    }
+
+    fn list(&self, object: &str) -> io::Result<Vec<usize>> {
+        FileStore::file_name(object, 0)?;
+        let mut shards = vec![];
+
+        for directory in self.placement.directories() {
+            let entries = match fs::read_dir(directory) {
+                Result::Ok(entries) => entries,
+                Result::Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                Result::Err(error) => return Result::Err(error),
+            };
+
+            for entry in entries {
+                let file_name = entry?.file_name();
+                let Some(shard) = file_name
+                    .to_str()
+                    .and_then(|name| name.strip_prefix(object)?.strip_prefix('.'))
+                    .and_then(|index| index.parse::<usize>().ok())
+                else {
+                    continue;
+                };
+
+                // Rules out other spellings of the index, such as with leading zeros.
+                if file_name.to_str() == Some(&format!("{}.{}", object, shard)) {
+                    shards.push(shard);
+                }
+            }
+        }
+
+        shards.sort_unstable();
+        shards.dedup();
+        Result::Ok(shards)
+    }
+}
+
+struct FileShardWriter {
+    file: Option<BufWriter<File>>,
+    temporary_path: PathBuf,
+    path: PathBuf,
+}
+
+impl Write for FileShardWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.as_mut().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().unwrap().flush()
+    }
+}
+
+impl ShardWriter for FileShardWriter {
+    fn commit(mut self: Box<Self>) -> io::Result<()> {
+        let file = self.file.take().unwrap();
+        let result = file
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)
+            .and_then(|file| commit_file(&file, &self.temporary_path, &self.path));
+
+        if result.is_err() {
+            let _ = fs::remove_file(&self.temporary_path);
+        }
+
+        result
+    }
+}
+
+impl Drop for FileShardWriter {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.temporary_path);
+        }
+    }
+}
+
+/// Contents of stored shards by object name and shard index.
+type Shards = BTreeMap<(String, usize), Arc<[u8]>>;
+
+/// Keeps shards in memory, mostly for tests. Clones share the same shards.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    shards: Arc<Mutex<Shards>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    fn contents(&self, object: &str, shard: usize) -> io::Result<Arc<[u8]>> {
+        self.shards
+            .lock()
+            .unwrap()
+            .get(&(object.to_string(), shard))
+            .cloned()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Shard {} of {} is not stored", shard, object),
+                )
+            })
+    }
+}
+
+impl ShardStore for MemoryStore {
+    fn put(&self, object: &str, shard: usize) -> io::Result<Box<dyn ShardWriter>> {
+        Result::Ok(Box::new(MemoryShardWriter {
+            store: self.clone(),
+            key: (object.to_string(), shard),
+            contents: vec![],
+        }))
+    }
+
+    fn get(&self, object: &str, shard: usize) -> io::Result<Box<dyn Read + Send>> {
+        Result::Ok(Box::new(Cursor::new(self.contents(object, shard)?)))
+    }
+
+    fn get_range(
+        &self,
+        object: &str,
+        shard: usize,
+        offset: u64,
+        length: u64,
+    ) -> io::Result<Box<dyn Read + Send>> {
+        let mut cursor = Cursor::new(self.contents(object, shard)?);
+        cursor.set_position(offset);
+        Result::Ok(Box::new(cursor.take(length)))
+    }
+
+    fn size(&self, object: &str, shard: usize) -> io::Result<u64> {
+        Result::Ok(self.contents(object, shard)?.len() as u64)
+    }
+
+    fn delete(&self, object: &str, shard: usize) -> io::Result<()> {
+        self.shards
+            .lock()
+            .unwrap()
+            .remove(&(object.to_string(), shard));
+        Result::Ok(())
+    }
+
+    fn list(&self, object: &str) -> io::Result<Vec<usize>> {
+        Result::Ok(
+            self.shards
+                .lock()
+                .unwrap()
+                .keys()
+                .filter(|(name, _)| name == object)
+                .map(|&(_, shard)| shard)
+                .collect(),
+        )
+    }
+}
+
+struct MemoryShardWriter {
+    store: MemoryStore,
+    key: (String, usize),
+    contents: Vec<u8>,
+}
+
+impl Write for MemoryShardWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.contents.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Result::Ok(())
+    }
+}
+
+impl ShardWriter for MemoryShardWriter {
+    fn commit(self: Box<Self>) -> io::Result<()> {
+        let MemoryShardWriter {
+            store,
+            key,
+            contents,
+        } = *self;
+
+        store.shards.lock().unwrap().insert(key, contents.into());
+        Result::Ok(())
+    }
+}
+
+/// Presents a stored shard as a seekable reader. Reading after a seek fetches the rest of the
+/// shard from the new position through `get_range`, so that decoding part of an object only
+/// transfers the stripes it needs.
+pub(crate) struct RangeReader<'a> {
+    store: &'a dyn ShardStore,
+    object: &'a str,
+    shard: usize,
+    size: u64,
+    position: u64,
+    reader: Option<Box<dyn Read + Send>>,
+}
+
+impl<'a> RangeReader<'a> {
+    pub fn open(
+        store: &'a dyn ShardStore,
+        object: &'a str,
+        shard: usize,
+    ) -> io::Result<RangeReader<'a>> {
+        Result::Ok(RangeReader {
+            store,
+            object,
+            shard,
+            size: store.size(object, shard)?,
+            position: 0,
+            reader: None,
+        })
+    }
+}
+
+impl Read for RangeReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.size {
+            return Result::Ok(0);
+        }
+
+        if self.reader.is_none() {
+            self.reader = Some(self.store.get_range(
+                self.object,
+                self.shard,
+                self.position,
+                self.size - self.position,
+            )?);
+        }
+
+        let count = self.reader.as_mut().unwrap().read(buf)?;
+        self.position += count as u64;
+        Result::Ok(count)
+    }
+}
+
+impl Seek for RangeReader<'_> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot seek before the start of a shard",
+            )
+        })?;
+
+        if position != self.position {
+            self.position = position;
+            self.reader = None;
+        }
+
+        Result::Ok(position)
+    }
+}
+
+impl ReedSolomonEncoder {
+    /// Encodes `length` bytes read from `data` as the shards of `object` in `store`. The shards
+    /// are only committed once all of them were written and flushed, so a failure before then
+    /// leaves any earlier version of the object in place. Stores commit shards one at a time, so
+    /// if committing one fails, the shards committed before it are deleted again. The earlier
+    /// version then stays readable as long as no more than `parity_shards` of its shards were
+    /// replaced, as shards of another version count as erasures.
+    pub fn put_object<R: Read>(
+        &self,
+        store: &dyn ShardStore,
+        object: &str,
+        data: &mut R,
+        length: usize,
+    ) -> io::Result<()> {
+        let mut shard_writers = (0..self.data_shards + self.parity_shards)
+            .map(|shard| store.put(object, shard))
+            .collect::<io::Result<Vec<Box<dyn ShardWriter>>>>()?;
+
+        self.encode(data, length, &mut shard_writers)?;
+
+        for shard_writer in shard_writers.iter_mut() {
+            shard_writer.flush()?;
+        }
+
+        for (shard, shard_writer) in shard_writers.into_iter().enumerate() {
+            if let Result::Err(error) = shard_writer.commit() {
+                for committed in 0..shard {
+                    let _ = store.delete(object, committed);
+                }
+
+                return Result::Err(error);
+            }
+        }
+
+        Result::Ok(())
+    }
+
+    /// Decodes `object` from the shards in `store` into `output`. Shards that cannot be read are
+    /// treated as erasures.
+    pub fn get_object<W: Write>(
+        &self,
+        store: &dyn ShardStore,
+        object: &str,
+        output: &mut W,
+    ) -> io::Result<()> {
+        let mut shard_readers: Vec<Option<Box<dyn Read + Send>>> = (0..self.data_shards
+            + self.parity_shards)
+            .map(|shard| store.get(object, shard).ok())
+            .collect();
+
+        self.decode(&mut shard_readers, output)
+    }
+
+    /// Decodes `length` bytes of `object` starting at `offset`, fetching only the stripes that
+    /// hold them through ranged reads.
+    pub fn get_object_at<W: Write>(
+        &self,
+        store: &dyn ShardStore,
+        object: &str,
+        output: &mut W,
+        offset: usize,
+        length: usize,
+    ) -> io::Result<()> {
+        let mut shard_readers: Vec<Option<RangeReader>> = (0..self.data_shards
+            + self.parity_shards)
+            .map(|shard| RangeReader::open(store, object, shard).ok())
+            .collect();
+
+        self.decode_at(&mut shard_readers, output, offset, length)
+    }
+
+    /// Decodes `object` from the shards in `store` into `output` through hedged reads, fetching
+    /// the chunks of each stripe from `data_shards + extra_reads` shards at once and using the
+    /// first to arrive. See `decode_hedged`.
+    pub fn get_object_hedged<W: Write>(
+        &self,
+        store: &dyn ShardStore,
+        object: &str,
+        output: &mut W,
+        extra_reads: usize,
+        latencies: &ShardLatencies,
+    ) -> io::Result<()> {
+        let mut shard_readers: Vec<Option<RangeReader>> = (0..self.data_shards
+            + self.parity_shards)
+            .map(|shard| RangeReader::open(store, object, shard).ok())
+            .collect();
+
+        self.decode_hedged(&mut shard_readers, output, extra_reads, latencies)
+    }
+
+    /// Removes every stored shard of `object` from `store`.
+    pub fn delete_object(&self, store: &dyn ShardStore, object: &str) -> io::Result<()> {
+        for shard in store.list(object)? {
+            store.delete(object, shard)?;
+        }
+
+        Result::Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::scratch_directory;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+
+    fn round_trip(store: &dyn ShardStore) {
+        let encoder = ReedSolomonEncoder::new(4, 2, 1000);
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let mut buffer = vec![0u8; 30017];
+        rng.fill_bytes(&mut buffer);
+
+        encoder
+            .put_object(store, "object", &mut Cursor::new(&buffer), buffer.len())
+            .unwrap();
+        encoder
+            .put_object(store, "other", &mut Cursor::new(&buffer[..10]), 10)
+            .unwrap();
+        assert_eq!(store.list("object").unwrap(), vec![0, 1, 2, 3, 4, 5]);
+
+        let mut output = vec![];
+        encoder.get_object(store, "object", &mut output).unwrap();
+        assert!(output == buffer);
+
+        let mut output = vec![];
+        encoder
+            .get_object_hedged(store, "object", &mut output, 1, &ShardLatencies::new())
+            .unwrap();
+        assert!(output == buffer);
+
+        store.delete("object", 0).unwrap();
+        store.delete("object", 4).unwrap();
+        store.delete("object", 4).unwrap();
+        assert_eq!(store.list("object").unwrap(), vec![1, 2, 3, 5]);
+        assert_eq!(
+            store.get("object", 0).err().map(|error| error.kind()),
+            Some(io::ErrorKind::NotFound)
+        );
+
+        let mut output = vec![];
+        encoder.get_object(store, "object", &mut output).unwrap();
+        assert!(output == buffer);
+
+        for (offset, length) in [(0, 10), (3999, 2), (12345, 10000), (30000, 17)] {
+            let mut output = vec![];
+            encoder
+                .get_object_at(store, "object", &mut output, offset, length)
+                .unwrap();
+            assert!(output == buffer[offset..offset + length]);
+        }
+
+        store.delete("object", 1).unwrap();
+        assert!(encoder.get_object(store, "object", &mut vec![]).is_err());
+
+        encoder.delete_object(store, "object").unwrap();
+        assert!(store.list("object").unwrap().is_empty());
+        assert_eq!(store.list("other").unwrap().len(), 6);
+    }
+
+    #[test]
+    fn memory_store() {
+        round_trip(&MemoryStore::new());
+    }
+
+    #[test]
+    fn file_store() {
+        let directory = scratch_directory("store-files");
+        let store = FileStore::with_placement(Placement::round_robin(vec![
+            directory.join("a"),
+            directory.join("b"),
+            directory.join("c"),
+        ]));
+        round_trip(&store);
+
+        assert!(directory.join("b/other.4").is_file());
+        assert_eq!(
+            store.put("../escape", 0).err().map(|error| error.kind()),
+            Some(io::ErrorKind::InvalidInput)
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    /// Fails to commit shard `failing` of every object.
+    struct FailingStore {
+        store: MemoryStore,
+        failing: usize,
+    }
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Result::Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Result::Ok(())
+        }
+    }
+
+    impl ShardWriter for FailingWriter {
+        fn commit(self: Box<Self>) -> io::Result<()> {
+            Result::Err(io::Error::other("Commit failed"))
+        }
+    }
+
+    impl ShardStore for FailingStore {
+        fn put(&self, object: &str, shard: usize) -> io::Result<Box<dyn ShardWriter>> {
+            if shard == self.failing {
+                return Result::Ok(Box::new(FailingWriter));
+            }
+
+            self.store.put(object, shard)
+        }
+
+        fn get(&self, object: &str, shard: usize) -> io::Result<Box<dyn Read + Send>> {
+            self.store.get(object, shard)
+        }
+
+        fn get_range(
+            &self,
+            object: &str,
+            shard: usize,
+            offset: u64,
+            length: u64,
+        ) -> io::Result<Box<dyn Read + Send>> {
+            self.store.get_range(object, shard, offset, length)
+        }
+
+        fn size(&self, object: &str, shard: usize) -> io::Result<u64> {
+            self.store.size(object, shard)
+        }
+
+        fn delete(&self, object: &str, shard: usize) -> io::Result<()> {
+            self.store.delete(object, shard)
+        }
+
+        fn list(&self, object: &str) -> io::Result<Vec<usize>> {
+            self.store.list(object)
+        }
+    }
+
+    #[test]
+    fn failed_commit_keeps_earlier_version() {
+        let encoder = ReedSolomonEncoder::new(4, 2, 1000);
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let mut first = vec![0u8; 10000];
+        rng.fill_bytes(&mut first);
+        let mut second = vec![0u8; 12000];
+        rng.fill_bytes(&mut second);
+
+        let store = MemoryStore::new();
+        encoder
+            .put_object(&store, "object", &mut Cursor::new(&first), first.len())
+            .unwrap();
+
+        let failing = FailingStore {
+            store: store.clone(),
+            failing: 2,
+        };
+        assert!(
+            encoder
+                .put_object(&failing, "object", &mut Cursor::new(&second), second.len())
+                .is_err()
+        );
+
+        // Shards 0 and 1 of the second version were deleted again.
+        assert_eq!(store.list("object").unwrap(), vec![2, 3, 4, 5]);
+
+        let mut output = vec![];
+        encoder.get_object(&store, "object", &mut output).unwrap();
+        assert!(output == first);
+    }
+
+    #[test]
+    fn uncommitted_shards_are_discarded() {
+        let directory = scratch_directory("store-uncommitted");
+        let stores: [&dyn ShardStore; 2] =
+            [&FileStore::new(directory.clone()), &MemoryStore::new()];
+
+        for store in stores {
+            let mut writer = store.put("object", 0).unwrap();
+            writer.write_all(b"first").unwrap();
+            writer.commit().unwrap();
+
+            let mut writer = store.put("object", 0).unwrap();
+            writer.write_all(b"second").unwrap();
+            drop(writer);
+
+            let mut contents = vec![];
+            store
+                .get("object", 0)
+                .unwrap()
+                .read_to_end(&mut contents)
+                .unwrap();
+            assert_eq!(contents, b"first");
+            assert_eq!(store.list("object").unwrap(), vec![0]);
+        }
+
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
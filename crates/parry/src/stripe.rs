@@ -0,0 +1,897 @@
+use std::io;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use crate::ReedSolomonEncoder;
+use crate::checksum::{Checksum, ChecksumKind, ChunkKey};
+use crate::compression;
+use crate::gf8::Gf8;
+use crate::header::{HEADER_SIZE, SetId, SetInfo, ShardHeader, read_header};
+use crate::io::{ChunkReadError, is_zero, read_chunk, seek_to_chunk, write_chunk, write_hole};
+use crate::layout::Layout;
+use crate::matrix::Matrix;
+use crate::progress::Monitor;
+
+pub(crate) struct StripeEncoder {
+    data_shards: usize,
+    chunk_size: usize,
+    checksum: Checksum,
+    parity_matrix: Matrix<Gf8>,
+    data_matrix: Matrix<Gf8>,
+    first_parity_shard: usize,
+    stripe: usize,
+    monitor: Monitor,
+}
+
+impl StripeEncoder {
+    pub fn new(encoder: &ReedSolomonEncoder, checksum: Checksum) -> StripeEncoder {
+        StripeEncoder::with_parity_shards(encoder, 0..encoder.parity_shards(), checksum)
+    }
+
+    /// Creates an encoder that only computes the given range of parity shards.
+    pub fn with_parity_shards(
+        encoder: &ReedSolomonEncoder,
+        parity_shards: Range<usize>,
+        checksum: Checksum,
+    ) -> StripeEncoder {
+        let data_shards = encoder.data_shards();
+        let encoding_matrix = Matrix::<Gf8>::encoding_matrix(data_shards, encoder.parity_shards());
+
+        let mut stripe_encoder = StripeEncoder::with_parity_matrix(
+            encoding_matrix
+                .slice(data_shards + parity_shards.start..data_shards + parity_shards.end),
+            encoder.chunk_size(),
+            checksum,
+        );
+
+        stripe_encoder.first_parity_shard = data_shards + parity_shards.start;
+        stripe_encoder
+    }
+
+    /// Creates an encoder whose parity chunks are the rows of `parity_matrix` applied to the data
+    /// chunks of each stripe.
+    pub fn with_parity_matrix(
+        parity_matrix: Matrix<Gf8>,
+        chunk_size: usize,
+        checksum: Checksum,
+    ) -> StripeEncoder {
+        let data_shards = parity_matrix.columns;
+
+        StripeEncoder {
+            data_shards,
+            chunk_size,
+            checksum,
+            parity_matrix,
+            data_matrix: Matrix::<Gf8>::with_dimensions(data_shards, chunk_size),
+            first_parity_shard: data_shards,
+            stripe: 0,
+            monitor: Monitor::default(),
+        }
+    }
+
+    /// Reports every encoded stripe to `monitor`.
+    pub fn with_monitor(self, monitor: Monitor) -> StripeEncoder {
+        StripeEncoder { monitor, ..self }
+    }
+
+    pub fn layout(&self) -> Layout {
+        Layout::new(self.data_shards, self.chunk_size, self.checksum.kind())
+    }
+
+    /// Sets the number of the next stripe to be encoded, which keyed checksums depend on.
+    pub fn seek_to_stripe(&mut self, stripe: usize) {
+        self.stripe = stripe;
+    }
+
+    /// Returns the number of the stripe that is encoded next.
+    pub fn stripe(&self) -> usize {
+        self.stripe
+    }
+
+    pub fn block_mut(&mut self) -> &mut [u8] {
+        self.data_matrix.as_bytes_mut()
+    }
+
+    pub fn encode_stripe<W: Write>(&mut self, shard_writers: &mut [W]) -> io::Result<()> {
+        let (data_writers, parity_writers) = shard_writers.split_at_mut(self.data_shards);
+
+        let data = self.data_matrix.as_bytes().chunks(self.chunk_size);
+        for (shard, (writer, chunk)) in data_writers.iter_mut().zip(data).enumerate() {
+            write_chunk(writer, self.checksum, shard, self.stripe, chunk)?;
+        }
+
+        self.encode_parity(parity_writers)
+    }
+
+    /// Computes and writes the parity chunks of the current stripe, then moves on to the next.
+    pub fn encode_parity<W: Write>(&mut self, parity_writers: &mut [W]) -> io::Result<()> {
+        let parity_matrix = &self.parity_matrix * &self.data_matrix;
+
+        let parity = parity_matrix.as_bytes().chunks(self.chunk_size);
+        for (i, (writer, chunk)) in parity_writers.iter_mut().zip(parity).enumerate() {
+            let shard = self.first_parity_shard + i;
+            write_chunk(writer, self.checksum, shard, self.stripe, chunk)?;
+        }
+
+        self.stripe += 1;
+        self.monitor.stripe_done(self.layout().block_size(), 0)
+    }
+
+    /// Encodes the current stripe like `encode_stripe`, but only writes the checksums of all-zero
+    /// chunks, leaving holes in place of their contents. Parity is not computed for all-zero
+    /// stripes, whose parity chunks are all zeros as well.
+    pub fn encode_sparse_stripe<W: Write + Seek>(
+        &mut self,
+        shard_writers: &mut [W],
+    ) -> io::Result<()> {
+        let parity_matrix = if is_zero(self.data_matrix.as_bytes()) {
+            Matrix::<Gf8>::with_dimensions(self.parity_matrix.rows, self.chunk_size)
+        } else {
+            &self.parity_matrix * &self.data_matrix
+        };
+
+        let chunks = self
+            .data_matrix
+            .as_bytes()
+            .chunks(self.chunk_size)
+            .chain(parity_matrix.as_bytes().chunks(self.chunk_size));
+        let shards = (0..self.data_shards).chain(self.first_parity_shard..);
+
+        for ((writer, chunk), shard) in shard_writers.iter_mut().zip(chunks).zip(shards) {
+            if is_zero(chunk) {
+                write_hole(writer, self.checksum, shard, self.stripe, chunk)?;
+            } else {
+                write_chunk(writer, self.checksum, shard, self.stripe, chunk)?;
+            }
+        }
+
+        self.stripe += 1;
+        self.monitor.stripe_done(self.layout().block_size(), 0)
+    }
+
+    /// Fills stripes with `length` bytes read from `data` and encodes them. The first `filled`
+    /// bytes of the current block are kept, which lets a partially filled stripe be extended.
+    pub fn encode_stripes<R: Read, W: Write>(
+        &mut self,
+        data: &mut R,
+        mut filled: usize,
+        length: usize,
+        shard_writers: &mut [W],
+    ) -> io::Result<()> {
+        let block_size = self.layout().block_size();
+        let mut remaining = length;
+
+        while remaining > 0 {
+            let count = remaining.min(block_size - filled);
+
+            let block = self.block_mut();
+            data.read_exact(&mut block[filled..filled + count])?;
+            block[filled + count..].fill(0);
+
+            self.encode_stripe(shard_writers)?;
+
+            remaining -= count;
+            filled = 0;
+        }
+
+        Result::Ok(())
+    }
+
+    /// Encodes everything read from `data` until it is exhausted, returning the number of bytes
+    /// read.
+    pub fn encode_stream<R: Read, W: Write>(
+        &mut self,
+        data: &mut R,
+        shard_writers: &mut [W],
+    ) -> io::Result<usize> {
+        let mut total = 0;
+
+        loop {
+            let block = self.block_mut();
+            let mut filled = 0;
+
+            while filled < block.len() {
+                match data.read(&mut block[filled..]) {
+                    Result::Ok(0) => break,
+                    Result::Ok(count) => filled += count,
+                    Result::Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                    Result::Err(error) => return Result::Err(error),
+                }
+            }
+
+            if filled == 0 {
+                return Result::Ok(total);
+            }
+
+            block[filled..].fill(0);
+            self.encode_stripe(shard_writers)?;
+            total += filled;
+
+            if filled < self.layout().block_size() {
+                return Result::Ok(total);
+            }
+        }
+    }
+
+    /// Encodes the trailer stripe of a streamed shard set holding `length` bytes of data, which
+    /// follows the last data stripe. It is not reported as progress.
+    pub fn encode_trailer<W: Write>(
+        &mut self,
+        length: usize,
+        shard_writers: &mut [W],
+    ) -> io::Result<()> {
+        let block = self.block_mut();
+        block.fill(0);
+        block[..TRAILER_SIZE].copy_from_slice(&(length as u64).to_be_bytes());
+
+        let monitor = std::mem::take(&mut self.monitor);
+        let result = self.encode_stripe(shard_writers);
+        self.monitor = monitor;
+        result
+    }
+}
+
+/// Size of the length recorded at the start of the trailer stripe of streamed shard sets.
+const TRAILER_SIZE: usize = 8;
+
+/// Returns the length recorded in the trailer stripe `block` of a streamed shard set, checking
+/// that it accounts for the `data_stripes` stripes preceding the trailer.
+fn trailer_length(block: &[u8], layout: &Layout, data_stripes: usize) -> io::Result<usize> {
+    let length = u64::from_be_bytes(block[..TRAILER_SIZE].try_into().unwrap()) as usize;
+
+    if layout.stripes(length) != data_stripes {
+        return Result::Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Trailer does not match the number of stripes",
+        ));
+    }
+
+    Result::Ok(length)
+}
+
+fn missing_trailer() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Streamed shard set has no trailer",
+    )
+}
+
+/// Returns whether every remaining shard has been read to its end, which is how streams of
+/// stripes are found to end without seeking. Shards that fail to read are erased.
+fn at_end<R: Read>(shard_readers: &mut [Option<BufReader<R>>]) -> bool {
+    let mut ended = true;
+
+    for shard_reader in shard_readers.iter_mut() {
+        let Some(reader) = shard_reader else {
+            continue;
+        };
+
+        match reader.fill_buf() {
+            Result::Ok(buffer) => ended &= buffer.is_empty(),
+            Result::Err(_) => *shard_reader = None,
+        }
+    }
+
+    ended
+}
+
+pub(crate) struct StripeDecoder {
+    data_shards: usize,
+    parity_shards: usize,
+    local_parity_shards: usize,
+    chunk_size: usize,
+    checksum_kind: ChecksumKind,
+    key: ChunkKey,
+    checksum: Option<Checksum>,
+    set: SetInfo,
+    generator_matrix: Matrix<Gf8>,
+    stripe: usize,
+    chunks: Matrix<Gf8>,
+    data_matrix: Matrix<Gf8>,
+    available_shards: Vec<usize>,
+    decoding_shards: Vec<usize>,
+    decoding_matrix: Option<Matrix<Gf8>>,
+    monitor: Monitor,
+}
+
+impl StripeDecoder {
+    pub fn new(encoder: &ReedSolomonEncoder) -> StripeDecoder {
+        let encoding_matrix =
+            Matrix::<Gf8>::encoding_matrix(encoder.data_shards(), encoder.parity_shards());
+
+        StripeDecoder::with_generator_matrix(
+            encoding_matrix,
+            0,
+            encoder.chunk_size(),
+            encoder.checksum(),
+            encoder.chunk_key().clone(),
+        )
+    }
+
+    /// Creates a decoder for shard sets whose chunks are the rows of `generator_matrix` applied
+    /// to the data chunks of each stripe. The first rows must form the identity matrix.
+    pub fn with_generator_matrix(
+        generator_matrix: Matrix<Gf8>,
+        local_parity_shards: usize,
+        chunk_size: usize,
+        checksum_kind: ChecksumKind,
+        key: ChunkKey,
+    ) -> StripeDecoder {
+        let data_shards = generator_matrix.columns;
+        let shards = generator_matrix.rows;
+
+        StripeDecoder {
+            data_shards,
+            parity_shards: shards - data_shards,
+            local_parity_shards,
+            chunk_size,
+            checksum_kind,
+            key,
+            checksum: None,
+            set: SetInfo {
+                set_id: SetId::default(),
+                length: 0,
+                framing: None,
+                sparse: false,
+                streamed: false,
+            },
+            generator_matrix,
+            stripe: 0,
+            chunks: Matrix::<Gf8>::with_dimensions(shards, chunk_size),
+            data_matrix: Matrix::<Gf8>::with_dimensions(data_shards, chunk_size),
+            available_shards: Vec::with_capacity(shards),
+            decoding_shards: vec![],
+            decoding_matrix: None,
+            monitor: Monitor::default(),
+        }
+    }
+
+    /// Reports every decoded stripe to `monitor`, along with the number of data chunks that had
+    /// to be reconstructed.
+    pub fn with_monitor(self, monitor: Monitor) -> StripeDecoder {
+        StripeDecoder { monitor, ..self }
+    }
+
+    /// Reads the header of every shard, erasing shards whose header is unreadable, describes a
+    /// different encoding or belongs to another shard set, and returns the payload length agreed
+    /// upon by the most shards. The key of the shard set is resolved here.
+    pub fn read_headers<R: Read>(&mut self, shard_readers: &mut [Option<R>]) -> io::Result<usize> {
+        let mut set_ids: Vec<Option<SetId>> = vec![None; shard_readers.len()];
+        let mut tallies: Vec<(SetInfo, usize)> = vec![];
+
+        for (shard, shard_reader) in shard_readers.iter_mut().enumerate() {
+            let Some(reader) = shard_reader else {
+                continue;
+            };
+
+            match read_header(reader, &self.key) {
+                Result::Ok(header) if self.matches(shard, &header) => {
+                    set_ids[shard] = Some(header.set.set_id);
+
+                    match tallies.iter_mut().find(|(set, _)| *set == header.set) {
+                        Some((_, count)) => *count += 1,
+                        None => tallies.push((header.set, 1)),
+                    }
+                }
+                _ => *shard_reader = None,
+            }
+        }
+
+        let set = tallies
+            .iter()
+            .max_by_key(|&&(set, count)| (count, set.length))
+            .map(|&(set, _)| set)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "No shard has a valid header")
+            })?;
+
+        for (shard_reader, set_id) in shard_readers.iter_mut().zip(set_ids) {
+            if set_id.is_some_and(|set_id| set_id != set.set_id) {
+                *shard_reader = None;
+            }
+        }
+
+        self.set = set;
+        if !set.streamed {
+            self.monitor.set_total_bytes(set.length);
+        }
+        self.checksum = Some(self.key.checksum(self.checksum_kind, &set.set_id)?);
+
+        Result::Ok(set.length)
+    }
+
+    /// Returns the payload length, which for streamed shard sets is read from the trailer stripe
+    /// located from the size of the shard files rather than taken from the headers. The readers
+    /// are then left at the first stripe.
+    pub fn read_trailer<R: Read + Seek>(
+        &mut self,
+        shard_readers: &mut [Option<R>],
+    ) -> io::Result<usize> {
+        if !self.set.streamed {
+            return Result::Ok(self.set.length);
+        }
+
+        let layout = self.layout();
+        let mut tallies: Vec<(u64, usize)> = vec![];
+
+        for shard_reader in shard_readers.iter_mut() {
+            let Some(reader) = shard_reader else {
+                continue;
+            };
+
+            match reader.seek(SeekFrom::End(0)) {
+                Result::Ok(size) => match tallies.iter_mut().find(|(other, _)| *other == size) {
+                    Some((_, count)) => *count += 1,
+                    None => tallies.push((size, 1)),
+                },
+                Result::Err(_) => *shard_reader = None,
+            }
+        }
+
+        let size = tallies
+            .iter()
+            .max_by_key(|&&(size, count)| (count, size))
+            .map_or(0, |&(size, _)| size);
+        let stripes = size.saturating_sub(HEADER_SIZE as u64) / layout.chunk_stride() as u64;
+        let Some(data_stripes) = (stripes as usize).checked_sub(1) else {
+            return Result::Err(missing_trailer());
+        };
+
+        let monitor = std::mem::take(&mut self.monitor);
+        self.seek_to_stripe(shard_readers, data_stripes);
+        let result = self.decode_stripe(shard_readers);
+        self.monitor = monitor;
+        result?;
+
+        let length = trailer_length(self.block(), &layout, data_stripes)?;
+        self.monitor.set_total_bytes(length);
+        self.seek_to_stripe(shard_readers, 0);
+
+        Result::Ok(length)
+    }
+
+    pub fn matches(&self, shard: usize, header: &ShardHeader) -> bool {
+        header.shard == shard
+            && header.data_shards == self.data_shards
+            && header.parity_shards == self.parity_shards
+            && header.local_parity_shards == self.local_parity_shards
+            && header.chunk_size == self.chunk_size
+            && header.checksum == self.checksum_kind
+    }
+
+    pub fn layout(&self) -> Layout {
+        Layout::new(self.data_shards, self.chunk_size, self.checksum_kind)
+    }
+
+    pub fn seek_to_stripe<R: Seek>(&mut self, shard_readers: &mut [Option<R>], stripe: usize) {
+        self.stripe = stripe;
+        let layout = self.layout();
+
+        for shard_reader in shard_readers.iter_mut() {
+            if let Some(reader) = shard_reader
+                && seek_to_chunk(reader, &layout, stripe).is_err()
+            {
+                *shard_reader = None;
+            }
+        }
+    }
+
+    pub fn decode_stripe<R: Read>(&mut self, shard_readers: &mut [Option<R>]) -> io::Result<()> {
+        let checksum = self.checksum();
+        self.available_shards.clear();
+
+        for (shard, shard_reader) in shard_readers.iter_mut().enumerate() {
+            let Some(reader) = shard_reader else {
+                continue;
+            };
+
+            let stripe = self.stripe;
+            match read_chunk(reader, checksum, shard, stripe, self.chunk_mut(shard)) {
+                Result::Ok(()) => self.available_shards.push(shard),
+                Result::Err(ChunkReadError::ChecksumValidationFailure) => {}
+                Result::Err(ChunkReadError::Truncated | ChunkReadError::IoError(_)) => {
+                    *shard_reader = None;
+                }
+            }
+        }
+
+        self.reconstruct()
+    }
+
+    /// Starts a stripe whose chunks are supplied through `set_chunk` instead of being read by
+    /// `decode_stripe`.
+    pub fn begin_stripe(&mut self) {
+        self.available_shards.clear();
+    }
+
+    /// Supplies the validated contents of the chunk of `shard` in the current stripe.
+    pub fn set_chunk(&mut self, shard: usize, chunk: &[u8]) {
+        self.chunk_mut(shard).copy_from_slice(chunk);
+        self.available_shards.push(shard);
+    }
+
+    /// Returns where the chunk of `shard` is read into. Data chunks go straight to their place in
+    /// the decoded block, as the code is systematic, so that stripes without erasures need no
+    /// further work, and only the other chunks go to `chunks`.
+    fn chunk_mut(&mut self, shard: usize) -> &mut [u8] {
+        let chunk_size = self.chunk_size;
+        let chunks = if shard < self.data_shards {
+            self.data_matrix.as_bytes_mut()
+        } else {
+            self.chunks.as_bytes_mut()
+        };
+        &mut chunks[shard * chunk_size..(shard + 1) * chunk_size]
+    }
+
+    /// Reconstructs the data chunks of the current stripe from its available chunks and moves on
+    /// to the next stripe.
+    pub fn reconstruct(&mut self) -> io::Result<()> {
+        let repaired_chunks = (0..self.data_shards)
+            .filter(|shard| !self.available_shards.contains(shard))
+            .count();
+
+        if repaired_chunks > 0 {
+            self.solve()?;
+        }
+
+        self.stripe += 1;
+        self.monitor
+            .stripe_done(self.layout().block_size(), repaired_chunks)
+    }
+
+    /// Computes the data chunks of the current stripe from a set of available chunks that
+    /// determines them.
+    fn solve(&mut self) -> io::Result<()> {
+        let reusable = self.decoding_matrix.is_some()
+            && self.decoding_shards.len() == self.data_shards
+            && self
+                .decoding_shards
+                .iter()
+                .all(|shard| self.available_shards.contains(shard));
+
+        if !reusable {
+            let Some(decoding_shards) = self
+                .generator_matrix
+                .independent_rows(&self.available_shards, self.data_shards)
+            else {
+                return Result::Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Too many erasures to reconstruct stripe",
+                ));
+            };
+
+            let matrix = self
+                .generator_matrix
+                .select_rows(&decoding_shards)
+                .invert()
+                .expect("Independent rows form an invertible matrix");
+
+            self.decoding_matrix = Some(matrix);
+            self.decoding_shards = decoding_shards;
+        }
+
+        let chunk_size = self.chunk_size;
+        for &shard in self
+            .decoding_shards
+            .iter()
+            .filter(|&&shard| shard < self.data_shards)
+        {
+            let chunk = shard * chunk_size..(shard + 1) * chunk_size;
+            self.chunks.as_bytes_mut()[chunk.clone()]
+                .copy_from_slice(&self.data_matrix.as_bytes()[chunk]);
+        }
+
+        let decoding_matrix = self.decoding_matrix.as_ref().unwrap();
+        self.data_matrix = decoding_matrix * &self.chunks.select_rows(&self.decoding_shards);
+
+        Result::Ok(())
+    }
+
+    /// Returns the number of the stripe that is decoded next.
+    pub fn stripe(&self) -> usize {
+        self.stripe
+    }
+
+    /// Returns the properties of the shard set agreed upon by the most shard headers.
+    pub fn set(&self) -> SetInfo {
+        self.set
+    }
+
+    pub fn checksum(&self) -> Checksum {
+        self.checksum
+            .expect("Shard headers must be read before the checksum is known")
+    }
+
+    pub fn data_matrix(&self) -> &Matrix<Gf8> {
+        &self.data_matrix
+    }
+
+    pub fn block(&self) -> &[u8] {
+        self.data_matrix.as_bytes()
+    }
+}
+
+/// Presents the data decoded from a shard set as a byte stream, reconstructing one stripe at a
+/// time. The shard headers must already have been read.
+pub(crate) struct StripeReader<'a, R: Read> {
+    decoder: StripeDecoder,
+    shard_readers: &'a mut [Option<R>],
+    block_size: usize,
+    remaining: usize,
+    position: usize,
+    available: usize,
+}
+
+impl<'a, R: Read> StripeReader<'a, R> {
+    pub fn new(
+        decoder: StripeDecoder,
+        shard_readers: &'a mut [Option<R>],
+        length: usize,
+    ) -> StripeReader<'a, R> {
+        let block_size = decoder.layout().block_size();
+
+        StripeReader {
+            decoder,
+            shard_readers,
+            block_size,
+            remaining: length,
+            position: 0,
+            available: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for StripeReader<'_, R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.available {
+            if self.remaining == 0 {
+                return Result::Ok(0);
+            }
+
+            self.decoder.decode_stripe(self.shard_readers)?;
+
+            self.available = self.remaining.min(self.block_size);
+            self.remaining -= self.available;
+            self.position = 0;
+        }
+
+        let count = buffer.len().min(self.available - self.position);
+        buffer[..count]
+            .copy_from_slice(&self.decoder.block()[self.position..self.position + count]);
+        self.position += count;
+
+        Result::Ok(count)
+    }
+}
+
+/// Decodes a whole shard set into `output`, decompressing it if it is compressed.
+pub(crate) fn decode<R: Read, W: Write>(
+    mut decoder: StripeDecoder,
+    shard_readers: &mut [Option<R>],
+    output: &mut W,
+) -> io::Result<()> {
+    let length = decoder.read_headers(shard_readers)?;
+
+    if decoder.set().streamed {
+        return decode_streamed(decoder, shard_readers, output);
+    }
+
+    if let Some(framing) = decoder.set().framing {
+        let mut payload = StripeReader::new(decoder, shard_readers, length);
+        return compression::decompress(framing, &mut payload, output);
+    }
+
+    let block_size = decoder.layout().block_size();
+    let mut remaining = length;
+
+    while remaining > 0 {
+        decoder.decode_stripe(shard_readers)?;
+
+        let count = remaining.min(block_size);
+        output.write_all(&decoder.block()[..count])?;
+
+        remaining -= count;
+    }
+
+    Result::Ok(())
+}
+
+/// Decodes a streamed shard set, whose length is only known from its trailer stripe. Since the
+/// readers cannot seek, the stripes end where every remaining shard ends, and the last two
+/// decoded stripes are held back until then, as they might be the final data stripe, which is
+/// padded, and the trailer.
+fn decode_streamed<R: Read, W: Write>(
+    mut decoder: StripeDecoder,
+    shard_readers: &mut [Option<R>],
+    output: &mut W,
+) -> io::Result<()> {
+    let mut shard_readers: Vec<Option<BufReader<&mut R>>> = shard_readers
+        .iter_mut()
+        .map(|shard_reader| shard_reader.as_mut().map(BufReader::new))
+        .collect();
+
+    let layout = decoder.layout();
+    let mut held: Vec<Vec<u8>> = Vec::with_capacity(2);
+    let mut written = 0;
+
+    while !at_end(&mut shard_readers) {
+        decoder.decode_stripe(&mut shard_readers)?;
+
+        let mut block = if held.len() == 2 {
+            let block = held.remove(0);
+            output.write_all(&block)?;
+            written += block.len();
+            block
+        } else {
+            vec![0u8; layout.block_size()]
+        };
+
+        block.copy_from_slice(decoder.block());
+        held.push(block);
+    }
+
+    let trailer = held.pop().ok_or_else(missing_trailer)?;
+    let length = trailer_length(
+        &trailer,
+        &layout,
+        written / layout.block_size() + held.len(),
+    )?;
+
+    if let Some(block) = held.pop() {
+        output.write_all(&block[..length - written])?;
+    }
+
+    Result::Ok(())
+}
+
+/// Decodes `length` bytes of data starting at `offset`, decoding only the stripes that hold them
+/// or, for compressed sets, the frames that hold them and their entries in the seek table.
+pub(crate) fn decode_at<R: Read + Seek, W: Write>(
+    mut decoder: StripeDecoder,
+    shard_readers: &mut [Option<R>],
+    output: &mut W,
+    offset: usize,
+    length: usize,
+) -> io::Result<()> {
+    for shard_reader in shard_readers.iter_mut() {
+        if let Some(reader) = shard_reader
+            && reader.seek(SeekFrom::Start(0)).is_err()
+        {
+            *shard_reader = None;
+        }
+    }
+
+    decoder.read_headers(shard_readers)?;
+    let payload_length = decoder.read_trailer(shard_readers)?;
+
+    if let Some(framing) = decoder.set().framing {
+        let read_payload = |offset: usize, length: usize| {
+            if offset + length > payload_length {
+                return Result::Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Seek table points past the end of the payload",
+                ));
+            }
+
+            let mut buffer = Vec::with_capacity(length);
+            decode_range(&mut decoder, shard_readers, &mut buffer, offset, length)?;
+            Result::Ok(buffer)
+        };
+
+        return compression::decompress_at(
+            framing,
+            payload_length,
+            read_payload,
+            output,
+            offset,
+            length,
+        );
+    }
+
+    if offset
+        .checked_add(length)
+        .is_none_or(|end| end > payload_length)
+    {
+        return Result::Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Requested range extends past the end of the encoded data",
+        ));
+    }
+
+    // Only the stripes holding the range are decoded.
+    if length > 0 {
+        let layout = decoder.layout();
+        let stripes = layout.locate(offset + length - 1).stripe - layout.locate(offset).stripe + 1;
+        decoder
+            .monitor
+            .set_total_bytes(stripes * layout.block_size());
+    }
+
+    decode_range(&mut decoder, shard_readers, output, offset, length)
+}
+
+fn decode_range<R: Read + Seek, W: Write>(
+    decoder: &mut StripeDecoder,
+    shard_readers: &mut [Option<R>],
+    output: &mut W,
+    offset: usize,
+    length: usize,
+) -> io::Result<()> {
+    if length == 0 {
+        return Result::Ok(());
+    }
+
+    let layout = decoder.layout();
+    let end = offset + length;
+
+    for stripe in layout.locate(offset).stripe..=layout.locate(end - 1).stripe {
+        decoder.seek_to_stripe(shard_readers, stripe);
+        decoder.decode_stripe(shard_readers)?;
+
+        let stripe_start = layout.stripe_start(stripe);
+        let from = offset.max(stripe_start) - stripe_start;
+        let to = end.min(stripe_start + layout.block_size()) - stripe_start;
+        output.write_all(&decoder.block()[from..to])?;
+    }
+
+    Result::Ok(())
+}
+
+/// Reads every chunk of every shard and validates its checksum without decoding anything, and
+/// returns the number of damaged chunks of each shard. All chunks of shards that are missing or
+/// have an unusable header count as damaged, as do those past the end of a truncated shard.
+pub(crate) fn verify<R: Read>(
+    mut decoder: StripeDecoder,
+    shard_readers: &mut [Option<R>],
+) -> io::Result<Vec<usize>> {
+    let length = decoder.read_headers(shard_readers)?;
+    let layout = decoder.layout();
+    let checksum = decoder.checksum();
+    let mut chunk = vec![0u8; layout.chunk_size()];
+    let mut damaged = vec![0; shard_readers.len()];
+
+    // Streamed sets end with a trailer stripe where the shards end.
+    let stripes = (!decoder.set().streamed).then(|| layout.stripes(length));
+    let mut shard_readers: Vec<Option<BufReader<&mut R>>> = shard_readers
+        .iter_mut()
+        .map(|shard_reader| shard_reader.as_mut().map(BufReader::new))
+        .collect();
+
+    for stripe in 0.. {
+        let ended = match stripes {
+            Some(stripes) => stripe == stripes,
+            None => at_end(&mut shard_readers),
+        };
+
+        if ended {
+            break;
+        }
+
+        let mut damaged_in_stripe = 0;
+
+        for (shard, shard_reader) in shard_readers.iter_mut().enumerate() {
+            let intact = match shard_reader {
+                Some(reader) => match read_chunk(reader, checksum, shard, stripe, &mut chunk) {
+                    Result::Ok(()) => true,
+                    Result::Err(ChunkReadError::ChecksumValidationFailure) => false,
+                    Result::Err(ChunkReadError::Truncated | ChunkReadError::IoError(_)) => {
+                        *shard_reader = None;
+                        false
+                    }
+                },
+                None => false,
+            };
+
+            if !intact {
+                damaged[shard] += 1;
+                damaged_in_stripe += 1;
+            }
+        }
+
+        decoder
+            .monitor
+            .stripe_done(layout.block_size(), damaged_in_stripe)?;
+    }
+
+    Result::Ok(damaged)
+}
@@ -0,0 +1,73 @@
+use std::fs;
+use std::io;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use rand::RngCore;
+use rand::rngs::StdRng;
+
+use crate::ReedSolomonEncoder;
+use crate::lrc::LrcEncoder;
+
+/// The encoders whose shard sets the tests build in memory with `encode_shards`.
+pub(crate) trait ShardEncoder {
+    fn shard_count(&self) -> usize;
+
+    fn encode_buffer(&self, buffer: &[u8], writers: &mut [Cursor<Vec<u8>>]) -> io::Result<()>;
+}
+
+impl ShardEncoder for ReedSolomonEncoder {
+    fn shard_count(&self) -> usize {
+        self.data_shards + self.parity_shards
+    }
+
+    fn encode_buffer(&self, buffer: &[u8], writers: &mut [Cursor<Vec<u8>>]) -> io::Result<()> {
+        self.encode(&mut Cursor::new(buffer), buffer.len(), writers)
+    }
+}
+
+impl ShardEncoder for LrcEncoder {
+    fn shard_count(&self) -> usize {
+        self.shards()
+    }
+
+    fn encode_buffer(&self, buffer: &[u8], writers: &mut [Cursor<Vec<u8>>]) -> io::Result<()> {
+        self.encode(&mut Cursor::new(buffer), buffer.len(), writers)
+    }
+}
+
+/// Encodes `length` random bytes, returning them along with the contents of every shard.
+pub(crate) fn encode_shards(
+    encoder: &impl ShardEncoder,
+    rng: &mut StdRng,
+    length: usize,
+) -> (Vec<u8>, Vec<Vec<u8>>) {
+    let mut buffer = vec![0u8; length];
+    rng.fill_bytes(&mut buffer);
+
+    let mut writers: Vec<Cursor<Vec<u8>>> = (0..encoder.shard_count())
+        .map(|_| Cursor::new(Vec::<u8>::new()))
+        .collect();
+
+    encoder.encode_buffer(&buffer, &mut writers).unwrap();
+
+    let shards = writers.into_iter().map(Cursor::into_inner).collect();
+    (buffer, shards)
+}
+
+/// Readers over copies of `shards`, leaving out those in `missing`.
+pub(crate) fn shard_readers(shards: &[Vec<u8>], missing: &[usize]) -> Vec<Option<Cursor<Vec<u8>>>> {
+    shards
+        .iter()
+        .enumerate()
+        .map(|(shard, contents)| (!missing.contains(&shard)).then(|| Cursor::new(contents.clone())))
+        .collect()
+}
+
+/// An empty directory for the test `name`, distinct for every test process.
+pub(crate) fn scratch_directory(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("parry-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    path
+}